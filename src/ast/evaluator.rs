@@ -1,28 +1,637 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::hint;
+use std::io::{self, Write};
+use std::iter;
+use std::sync::Arc;
 
 use ast::node::*;
 use ast::visitor::NodeVisitor;
+use interpreter::Dialect;
+use lexer::token::Token;
 use lexer::token::Token::*;
-use std::str::FromStr;
+use lexer::integer_value;
+use utils::exception::{self, Exception, RuntimeError};
+use utils::heap::{Heap, LeakReport};
+use utils::names::{self, NameMap};
 use utils::number::{Number, Number::Nil, NumberResult};
+use utils::set::{OrdinalSet, MAX_RANGE_SIZE};
+
+/// The variables, types and routines declared by the main program, or
+/// by a single procedure or function call.
+#[derive(Default)]
+struct Frame {
+  // The name of the program or routine, for the call chain
+  name: String,
+  // The frame of the call to the routine this one was declared in,
+  // through which its variables are found; the global frame has none
+  static_link: Option<usize>,
+  values: NameMap<Number>,
+  var_types: NameMap<TypeNode>,
+  types: NameMap<TypeNode>,
+  routines: NameMap<Routine>,
+  // The value assigned to a function's name, which becomes its result
+  result: Option<Number>,
+}
+
+impl Frame {
+  fn declares(&self, name: &str) -> bool {
+    self.var_types.contains_key(name) || self.values.contains_key(name)
+  }
+}
+
+/// A procedure or function body, kept so the routine can be called.
+#[derive(Clone)]
+struct Routine {
+  name: String,
+  params: Vec<(String, TypeNode)>,
+  return_type: Option<TypeNode>,
+  block: Arc<Box<Node>>,
+}
+
+/// A transfer of control out of the statements being executed. While
+/// one is in progress the visitor methods return an error, so that it
+/// passes through them, until it reaches the loop, routine or program
+/// being left, the statement labelled by a GOTO, or a handler for the
+/// exception raised.
+#[derive(Clone, Debug, PartialEq)]
+enum Flow {
+  Break,
+  Continue,
+  Exit,
+  Halt(i32),
+  Goto(String),
+  Raise(Exception),
+  // A runtime error, with the exception it raises
+  Fail(Exception),
+}
+
+/// Returns the approximate address of the top of the native stack,
+/// which grows downwards as calls are nested.
+fn stack_pointer() -> usize {
+  let marker = 0u8;
+  hint::black_box(&marker) as *const u8 as usize
+}
 
 pub struct Evaluator {
-  global_scope: HashMap<String, String>,
+  // The global frame comes first, followed by one frame for each
+  // procedure or function call in progress
+  frames: Vec<Frame>,
+  heap: Heap,
+  output: Box<Write + Send>,
+  dialect: Dialect,
+  max_call_depth: usize,
+  // The native stack the program may use, if known, and the lowest
+  // address it may reach once the program is running
+  stack_size: Option<usize>,
+  stack_limit: usize,
+  flow: Option<Flow>,
+  // The exceptions being handled, innermost last
+  handling: Vec<Exception>,
 }
 
 impl Evaluator {
   pub fn new() -> Self {
     Evaluator {
-      global_scope: HashMap::new(),
+      frames: vec![Frame::default()],
+      heap: Heap::new(),
+      output: Box::new(io::stdout()),
+      dialect: Dialect::Iso,
+      max_call_depth: usize::MAX,
+      stack_size: None,
+      stack_limit: 0,
+      flow: None,
+      handling: vec![],
+    }
+  }
+  /// Limits how deeply procedure and function calls may be nested.
+  pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+    self.max_call_depth = max_call_depth;
+  }
+  /// Tells the evaluator how much native stack it may use, so that a
+  /// call which would overflow it fails with a runtime error instead.
+  pub fn set_stack_size(&mut self, stack_size: usize) {
+    self.stack_size = Some(stack_size);
+  }
+  pub fn set_dialect(&mut self, dialect: Dialect) {
+    self.dialect = dialect;
+  }
+  /// Sends the output of Write and WriteLn to the given writer.
+  pub fn set_output(&mut self, output: Box<Write + Send>) {
+    self.output = output;
+  }
+  /// Lists the blocks allocated by New that were never disposed.
+  pub fn leaks(&self) -> LeakReport {
+    self.heap.leaks()
+  }
+  /// Gives a global variable a value from outside the program.
+  pub fn bind(&mut self, name: &str, value: Number) {
+    self.frames[0].values.insert(name.to_string(), value);
+  }
+  /// Returns the current value of a global variable.
+  pub fn lookup(&self, name: &str) -> Option<Number> {
+    self.frames[0].values.get(name).cloned()
+  }
+  /// Returns the innermost frame in scope that satisfies the predicate.
+  /// The static links are followed from the current frame, so a nested
+  /// routine sees the frames of the routines it was declared in, rather
+  /// than those of its callers.
+  fn find_frame<P: Fn(&Frame) -> bool>(&self, predicate: P) -> Option<usize> {
+    let mut index = self.frames.len() - 1;
+    loop {
+      if predicate(&self.frames[index]) {
+        return Some(index);
+      }
+      index = self.frames[index].static_link?;
+    }
+  }
+  /// Returns the index of the frame holding a variable, or of the
+  /// global frame if no frame in scope declares it.
+  fn frame_of(&self, name: &str) -> usize {
+    self.find_frame(|frame| frame.declares(name)).unwrap_or(0)
+  }
+  /// Returns the routine with the given name, along with the index of
+  /// the frame it was declared in, unless a variable hides it.
+  fn routine(&self, name: &str) -> Option<(usize, Routine)> {
+    let index =
+      self.find_frame(|frame| frame.declares(name) || frame.routines.contains_key(name))?;
+    let routine = self.frames[index].routines.get(name)?;
+    Some((index, routine.clone()))
+  }
+  /// Returns the user-defined function with the given name, along with
+  /// the index of the frame it was declared in.
+  fn function(&self, name: &str) -> Option<(usize, Routine)> {
+    self
+      .routine(name)
+      .filter(|(_, routine)| routine.return_type.is_some())
+  }
+  /// Calls a user-defined procedure or function, declared in the given
+  /// frame. The arguments are evaluated in the caller's frame before the
+  /// routine's own frame is entered.
+  fn call(&mut self, routine: &Routine, static_link: usize, args: &[Box<Node>]) -> NumberResult {
+    if self.frames.len() > self.max_call_depth || stack_pointer() < self.stack_limit {
+      let msg = self.stack_overflow(&routine.name);
+      return self.fail(RuntimeError::new("EStackOverflow", msg));
+    }
+    let mut frame = Frame {
+      name: routine.name.clone(),
+      static_link: Some(static_link),
+      ..Default::default()
+    };
+    for ((name, type_node), arg) in routine.params.iter().zip(args) {
+      let value = self.visit(arg)?;
+      frame
+        .values
+        .insert(name.to_string(), self.coerce(type_node, value));
+      frame.var_types.insert(name.to_string(), type_node.clone());
+    }
+    self.frames.push(frame);
+    let result = match self.visit(&**routine.block) {
+      Err(_) if self.flow == Some(Flow::Exit) => {
+        self.flow = None;
+        Ok(Nil)
+      }
+      result => result,
+    };
+    let frame = self.frames.pop().unwrap();
+    result?;
+    match (&routine.return_type, frame.result) {
+      (None, _) => Ok(Nil),
+      (Some(return_type), Some(value)) => Ok(self.coerce(return_type, value)),
+      (Some(_), None) => self.fail(RuntimeError::new(
+        "Exception",
+        format!("function {} returned without a result", routine.name),
+      )),
+    }
+  }
+  /// Starts a transfer of control.
+  fn transfer(&mut self, flow: Flow) -> NumberResult {
+    let msg = format!("Unhandled transfer of control: {:?}", flow);
+    self.flow = Some(flow);
+    Err(msg)
+  }
+  /// Returns true if a GOTO to the given label is in progress.
+  fn jumping_to(&self, label: &Token) -> bool {
+    match self.flow {
+      Some(Flow::Goto(ref target)) => *target == label.to_string(),
+      _ => false,
+    }
+  }
+  /// Returns the exception behind an error, if it can be handled: one
+  /// raised by RAISE, or by a runtime error. Other transfers of control
+  /// and errors in the program itself are never handled.
+  fn exception(&self) -> Option<Exception> {
+    match self.flow {
+      Some(Flow::Raise(ref exception)) | Some(Flow::Fail(ref exception)) => {
+        Some(exception.clone())
+      }
+      _ => None,
+    }
+  }
+  /// Fails with a runtime error, passing on the exception it raises
+  /// to any handler.
+  fn fail<T>(&mut self, error: RuntimeError) -> Result<T, String> {
+    let msg = error.to_string();
+    self.flow = Some(Flow::Fail(error.exception()));
+    Err(msg)
+  }
+  /// Passes on the value of an operation, or the runtime error it
+  /// failed with.
+  fn check<T>(&mut self, result: Result<T, RuntimeError>) -> Result<T, String> {
+    result.or_else(|error| self.fail(error))
+  }
+  /// Runs an exception handler, with the exception it handles named by
+  /// the given variable, if any.
+  fn handle(
+    &mut self,
+    exception: Exception,
+    variable: Option<&Token>,
+    statement: &Node,
+  ) -> NumberResult {
+    let name = variable.map(|variable| variable.to_string());
+    let hidden = name.as_ref().and_then(|name| {
+      let frame = self.frames.last_mut().unwrap();
+      frame.values.insert(name.to_string(), exception.to_value())
+    });
+    self.handling.push(exception);
+    let result = self.visit(statement);
+    self.handling.pop();
+    if let Some(name) = name {
+      let frame = self.frames.last_mut().unwrap();
+      match hidden {
+        Some(value) => frame.values.insert(name, value),
+        None => frame.values.remove(&name),
+      };
+    }
+    result
+  }
+  /// Runs the body of a loop, returning false if the loop should end
+  /// because the body called Break.
+  fn loop_body(&mut self, body: &Node) -> Result<bool, String> {
+    match self.visit(body) {
+      Err(_) if self.flow == Some(Flow::Continue) => {
+        self.flow = None;
+        Ok(true)
+      }
+      Err(_) if self.flow == Some(Flow::Break) => {
+        self.flow = None;
+        Ok(false)
+      }
+      result => result.map(|_| true),
+    }
+  }
+  /// Describes a stack overflow, listing the calls in progress with
+  /// repeated calls collapsed, as in "Main -> Loop -> Fact x 1000".
+  fn stack_overflow(&self, name: &str) -> String {
+    let mut calls: Vec<(&str, usize)> = vec![];
+    for name in self.frames.iter().map(|frame| frame.name.as_str()) {
+      match calls.last_mut() {
+        Some((last, count)) if *last == name => *count += 1,
+        _ => calls.push((name, 1)),
+      }
+    }
+    let mut chain: Vec<String> = calls
+      .iter()
+      .map(|&(name, count)| match count {
+        1 => name.to_string(),
+        _ => format!("{} x {}", name, count),
+      })
+      .collect();
+    // Mutual recursion can't be collapsed, so only the ends are shown
+    if chain.len() > 10 {
+      let hidden = chain.len() - 8;
+      chain.splice(4..chain.len() - 4, iter::once(format!("({} more)", hidden)));
+    }
+    format!(
+      "Stack overflow calling {}, call chain: {}",
+      name,
+      chain.join(" -> ")
+    )
+  }
+  /// Converts an integer to a real where a real value is expected.
+  fn coerce(&self, type_node: &TypeNode, value: Number) -> Number {
+    match (self.resolve(type_node).kind, value) {
+      (TypeKind::Named(Real), Number::Int(value)) => Number::Real(value as f32),
+      (_, value) => value,
+    }
+  }
+  fn call_builtin_function(&mut self, name: &str, arg: Number) -> NumberResult {
+    let result = match name {
+      "ABS" => arg.abs(),
+      "SQR" => arg.sqr(),
+      "SIN" => arg.sin(),
+      "COS" => arg.cos(),
+      "EXP" => arg.exp(),
+      "LN" => arg.ln(),
+      "SQRT" => arg.sqrt(),
+      "ARCTAN" => arg.arctan(),
+      "TRUNC" => arg.trunc(),
+      "ROUND" => arg.round(),
+      "ODD" => arg.odd(),
+      "ORD" => arg.ord(),
+      "CHR" => Number::chr(arg.ordinal()? as i64),
+      "SUCC" => arg.succ(),
+      "PRED" => arg.pred(),
+      _ => return Err(format!("Unknown function {} called.", name)),
+    };
+    self.check(result)
+  }
+  /// Returns the value of a constant appearing in a type specification.
+  fn constant(&self, token: &Token) -> NumberResult {
+    match token {
+      IntegerConst(value) => Ok(Number::Int(integer_value(value).unwrap())),
+      StringConst(value) => Ok(Number::Char(value.chars().next().unwrap())),
+      True => Ok(Number::Bool(true)),
+      False => Ok(Number::Bool(false)),
+      Id(name) => self.frames[self.frame_of(name)]
+        .values
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("Undeclared constant {} found.", name)),
+      token => Err(format!("Invalid constant {} found.", token)),
+    }
+  }
+  /// Follows type names through to the type they were declared as.
+  fn resolve(&self, type_node: &TypeNode) -> TypeNode {
+    if let TypeKind::Named(Id(name)) = &type_node.kind {
+      if let Some(index) = self.find_frame(|frame| frame.types.contains_key(name)) {
+        return self.resolve(&self.frames[index].types[name]);
+      }
+    }
+    type_node.clone()
+  }
+  /// Returns the lowest and highest values of an ordinal type.
+  fn bounds(&self, type_node: &TypeNode) -> Result<(Number, Number), String> {
+    match self.resolve(type_node).kind {
+      TypeKind::Named(Integer) => {
+        Ok((Number::Int(i32::MIN), Number::Int(i32::MAX)))
+      }
+      TypeKind::Named(Char) => Ok((Number::Char(0 as char), Number::Char(255 as char))),
+      TypeKind::Named(Boolean) => Ok((Number::Bool(false), Number::Bool(true))),
+      TypeKind::Subrange(low, high) => Ok((self.constant(&low)?, self.constant(&high)?)),
+      TypeKind::Enumeration(ref identifiers) => {
+        let names = Arc::new(identifiers.iter().map(|id| id.to_string()).collect());
+        let high = identifiers.len() as i32 - 1;
+        Ok((
+          Number::Enum(0, Arc::clone(&names)),
+          Number::Enum(high, names),
+        ))
+      }
+      _ => Err("Expected an ordinal type.".to_string()),
+    }
+  }
+  /// Returns the initial value of a variable of the given type.
+  fn default_value(&self, type_node: &TypeNode) -> NumberResult {
+    match self.resolve(type_node).kind {
+      TypeKind::Array(index, element) => {
+        let (low, high) = self.bounds(&index)?;
+        let length = (high.ordinal()? as i64 - low.ordinal()? as i64 + 1) as usize;
+        Ok(Number::Array(
+          Box::new(low),
+          vec![self.default_value(&element)?; length],
+        ))
+      }
+      TypeKind::Record(fields) => {
+        let mut record: Vec<(String, Number)> = vec![];
+        for (name, type_node, _) in &fields {
+          record.push((name.to_string(), self.default_value(type_node)?));
+        }
+        Ok(Number::Record(record))
+      }
+      _ => Ok(Nil),
+    }
+  }
+  /// Returns the position of an index within an array,
+  /// or a range error if it falls outside the array bounds.
+  fn offset(&self, low: &Number, length: usize, index: &Number) -> Result<usize, RuntimeError> {
+    let low = low.ordinal()? as i64;
+    let offset = index.ordinal()? as i64 - low;
+    if offset < 0 || offset >= length as i64 {
+      return Err(RuntimeError::new(
+        "ERangeError",
+        format!(
+          "range check error, index {} is outside {}..{}",
+          index.ordinal()?,
+          low,
+          low + length as i64 - 1
+        ),
+      ));
+    }
+    Ok(offset as usize)
+  }
+  /// Stores a value in a variable or array element.
+  fn store(&mut self, target: &Node, value: Number) -> NumberResult {
+    if target.is::<VarNode>() {
+      let var_node: &VarNode = target.downcast_ref().unwrap();
+      if let Id(name) = &var_node.identifier {
+        if let Some((declared_in, _)) = self.function(name) {
+          // Assigning to a function's name sets the result of the call
+          // to it whose body encloses the assignment
+          let call = self.find_frame(|frame| {
+            names::same_name(&frame.name, name) && frame.static_link == Some(declared_in)
+          });
+          if let Some(index) = call {
+            self.frames[index].result = Some(value);
+            return Ok(Nil);
+          }
+        }
+        let index = self.frame_of(name);
+        self.frames[index].values.insert(name.to_string(), value);
+      }
+      Ok(Nil)
+    } else if target.is::<DerefNode>() {
+      let DerefNode { pointer, .. } = target.downcast_ref().unwrap();
+      let address = self.address(&**pointer)?;
+      let stored = self.heap.store(address, value);
+      self.check(stored)?;
+      Ok(Nil)
+    } else if target.is::<FieldNode>() {
+      let FieldNode { record, field, .. } = target.downcast_ref().unwrap();
+      match self.visit(record)? {
+        Number::Record(mut fields) => {
+          let name = field.to_string();
+          match fields
+            .iter_mut()
+            .find(|(field, _)| names::same_name(field, &name))
+          {
+            Some(field) => field.1 = value,
+            None => return Err(format!("Unknown field {} found.", name)),
+          }
+          self.store(&**record, Number::Record(fields))
+        }
+        other => Err(format!("Cannot select a field from {}", other.to_string())),
+      }
+    } else if target.is::<IndexNode>() {
+      let IndexNode { array, index, .. } = target.downcast_ref().unwrap();
+      let index = self.visit(index)?;
+      match self.visit(array)? {
+        Number::Array(low, mut elements) => {
+          let offset = self.check(self.offset(&low, elements.len(), &index))?;
+          elements[offset] = value;
+          self.store(&**array, Number::Array(low, elements))
+        }
+        other => Err(format!("Cannot index into {}", other.to_string())),
+      }
+    } else {
+      Err(format!("Cannot assign to {}", to_string(target)))
+    }
+  }
+  /// Returns the heap address held by a pointer.
+  fn address(&mut self, pointer: &Node) -> Result<usize, String> {
+    match self.visit(pointer)? {
+      Number::Pointer(Some(address)) => Ok(address),
+      Number::Pointer(None) => self.fail(RuntimeError::new(
+        "EAccessViolation",
+        format!("NIL pointer {} dereferenced", to_string(pointer)),
+      )),
+      other => Err(format!("Cannot dereference {}", other.to_string())),
+    }
+  }
+  /// Returns the declared type of a variable, array element, record
+  /// field or dynamic variable.
+  fn type_node_of(&self, node: &Node) -> Result<TypeNode, String> {
+    let type_node = if let Some(VarNode { identifier, .. }) = node.downcast_ref::<VarNode>() {
+      let name = identifier.to_string();
+      self.frames[self.frame_of(&name)]
+        .var_types
+        .get(&name)
+        .cloned()
+    } else if let Some(IndexNode { array, .. }) = node.downcast_ref::<IndexNode>() {
+      match self.resolve(&self.type_node_of(&**array)?).kind {
+        TypeKind::Array(_, element) => Some(*element),
+        _ => None,
+      }
+    } else if let Some(FieldNode { record, field, .. }) = node.downcast_ref::<FieldNode>() {
+      match self.resolve(&self.type_node_of(&**record)?).kind {
+        TypeKind::Record(fields) => fields
+          .into_iter()
+          .find(|(name, _, _)| names::same_name(&name.to_string(), &field.to_string()))
+          .map(|(_, type_node, _)| type_node),
+        _ => None,
+      }
+    } else if let Some(DerefNode { pointer, .. }) = node.downcast_ref::<DerefNode>() {
+      match self.resolve(&self.type_node_of(&**pointer)?).kind {
+        TypeKind::Pointer(name) => Some(TypeNode::named(name)),
+        _ => None,
+      }
+    } else {
+      None
+    };
+    type_node.ok_or_else(|| format!("Unable to determine the type of {}", to_string(node)))
+  }
+  /// Evaluates Low and High, whose argument may be a type or a variable.
+  fn low_or_high(&mut self, name: &str, arg: &Node) -> NumberResult {
+    let type_name = match arg.downcast_ref::<VarNode>() {
+      Some(VarNode {
+        identifier: Id(name),
+        ..
+      }) if self
+        .find_frame(|frame| frame.types.contains_key(name))
+        .is_some() =>
+      {
+        Some(TypeNode::named(Id(name.to_string())))
+      }
+      _ => None,
+    };
+    let (low, high) = if arg.is::<TypeNode>() {
+      self.bounds(arg.downcast_ref().unwrap())?
+    } else if let Some(type_node) = type_name {
+      self.bounds(&type_node)?
+    } else {
+      match self.visit(arg)? {
+        Number::Array(low, elements) => {
+          let high = low.with_ordinal(low.ordinal()? as i64 + elements.len() as i64 - 1);
+          let high = self.check(high)?;
+          (*low, high)
+        }
+        value => value.bounds()?,
+      }
+    };
+    Ok(if name == "LOW" { low } else { high })
+  }
+  /// Evaluates the comparison of two sets.
+  fn compare_sets(&self, lhs: &Number, rhs: &OrdinalSet, operator: &Token) -> NumberResult {
+    let lhs = match lhs {
+      Number::Set(set) => set,
+      other => return Err(format!("Cannot compare {} with a set", other.to_string())),
+    };
+    match operator {
+      Equal => Ok(Number::from(lhs == rhs)),
+      NotEqual => Ok(Number::from(lhs != rhs)),
+      LessEqual => Ok(Number::from(lhs.is_subset(rhs))),
+      GreaterEqual => Ok(Number::from(rhs.is_subset(lhs))),
+      _ => Err(format!("Invalid set operator found: {}", operator)),
     }
   }
+  /// Runs the main program's block, or a declaration, statement or
+  /// expression entered on its own in the global frame, where whatever
+  /// it declares is kept for the next.
+  pub fn execute(&mut self, node: &Node) -> NumberResult {
+    // Calls stop before the stack runs out, whatever the call depth
+    self.stack_limit = match self.stack_size {
+      Some(stack_size) => stack_pointer().saturating_sub(stack_size),
+      None => 0,
+    };
+    self.flow = None;
+    let result = self.visit(node);
+    // Exit leaves the main program normally, Halt with an exit code
+    match (result, self.flow.take()) {
+      (Err(_), Some(Flow::Exit)) => Ok(Nil),
+      (Err(_), Some(Flow::Halt(code))) => Ok(Number::Int(code)),
+      (Err(_), Some(Flow::Raise(exception))) => {
+        Err(format!("Runtime error: unhandled exception {}", exception))
+      }
+      (result, _) => result,
+    }
+  }
+  fn write(&mut self, args: &[Box<Node>], newline: bool) -> NumberResult {
+    let mut text = String::new();
+    for arg in args {
+      text += &self.visit(arg)?.to_text()?;
+    }
+    if newline {
+      text += "\n";
+    }
+    if let Err(err) = self.output.write_all(text.as_bytes()) {
+      let msg = format!("unable to write output, {}", err);
+      return self.fail(RuntimeError::new("EInOutError", msg));
+    }
+    Ok(Nil)
+  }
 }
 
 impl NodeVisitor for Evaluator {
   fn visit_program(&mut self, node: &ProgramNode) -> NumberResult {
-    self.visit(&node.block)
+    self.frames = vec![Frame {
+      name: node.identifier.to_string(),
+      ..Default::default()
+    }];
+    self.heap.clear();
+    self.execute(&*node.block)
   }
-  fn visit_procedure(&mut self, _node: &ProcedureNode) -> NumberResult {
+  fn visit_procedure(&mut self, node: &ProcedureNode) -> NumberResult {
+    // A FORWARD declaration is replaced by the body declared later
+    if let Some(ref block) = node.block {
+      let params = node
+        .params
+        .iter()
+        .map(|param| {
+          let ParameterNode {
+            var_node: VarNode { identifier, .. },
+            type_node,
+            ..
+          } = param.downcast_ref().unwrap();
+          (identifier.to_string(), type_node.clone())
+        })
+        .collect();
+      let routine = Routine {
+        name: node.proc_name.to_string(),
+        params,
+        return_type: node.return_type.clone(),
+        block: Arc::clone(block),
+      };
+      let frame = self.frames.last_mut().unwrap();
+      frame.routines.insert(node.proc_name.to_string(), routine);
+    }
     Ok(Nil)
   }
   fn visit_parameter(&mut self, _node: &ParameterNode) -> NumberResult {
@@ -30,11 +639,39 @@ impl NodeVisitor for Evaluator {
   }
   fn visit_block(&mut self, node: &BlockNode) -> NumberResult {
     for declaration in &node.declarations {
-      self.visit(&declaration)?;
+      self.visit(declaration)?;
     }
     self.visit(&node.compound_statement)
   }
-  fn visit_declaration(&mut self, _node: &DeclarationNode) -> NumberResult {
+  fn visit_declaration(&mut self, node: &DeclarationNode) -> NumberResult {
+    if let Id(name) = &node.var_node.identifier {
+      let value = match self.resolve(&node.type_node).kind {
+        TypeKind::Array(_, _) | TypeKind::Record(_) => Some(self.default_value(&node.type_node)?),
+        _ => None,
+      };
+      let frame = self.frames.last_mut().unwrap();
+      frame
+        .var_types
+        .insert(name.to_string(), node.type_node.clone());
+      if let Some(value) = value {
+        frame.values.insert(name.to_string(), value);
+      }
+    }
+    Ok(Nil)
+  }
+  fn visit_type_declaration(&mut self, node: &TypeDeclarationNode) -> NumberResult {
+    if let Id(name) = &node.type_name {
+      let frame = self.frames.last_mut().unwrap();
+      frame.types.insert(name.to_string(), node.type_node.clone());
+    }
+    if let TypeKind::Enumeration(identifiers) = &node.type_node.kind {
+      let (first, _) = self.bounds(&node.type_node)?;
+      for (identifier, ordinal) in identifiers.iter().zip(0..) {
+        let value = self.check(first.with_ordinal(ordinal))?;
+        let frame = self.frames.last_mut().unwrap();
+        frame.values.insert(identifier.to_string(), value);
+      }
+    }
     Ok(Nil)
   }
   fn visit_type(&mut self, _node: &TypeNode) -> NumberResult {
@@ -46,46 +683,308 @@ impl NodeVisitor for Evaluator {
   fn visit_real(&mut self, node: &RealNumNode) -> NumberResult {
     Ok(Number::from(node.value))
   }
+  fn visit_char(&mut self, node: &CharNode) -> NumberResult {
+    Ok(Number::Char(node.value))
+  }
+  fn visit_boolean(&mut self, node: &BooleanNode) -> NumberResult {
+    Ok(Number::from(node.value))
+  }
+  fn visit_string(&mut self, node: &StringNode) -> NumberResult {
+    Ok(Number::Str(node.value.clone()))
+  }
   fn visit_binop(&mut self, node: &BinOpNode) -> NumberResult {
     let BinOpNode {
       left,
       right,
       operator,
+      ..
     } = node;
 
-    let lhs = self.visit(left);
-    let rhs = self.visit(right);
-    match operator {
-      Plus => Ok(lhs? + rhs?),
-      Multiply => Ok(lhs? * rhs?),
-      Minus => Ok(lhs? - rhs?),
-      IntegerDivision => Ok(lhs? / rhs?),
-      RealDivision => Ok(lhs? / rhs?),
-      _ => Err(format!("Unknown operator found: {}", operator)),
+    // The right operand isn't evaluated once the left has failed
+    let lhs = self.visit(left)?;
+    let rhs = self.visit(right)?;
+    if operator.is_relational() {
+      if let Number::Set(ref set) = rhs {
+        return match operator {
+          In => Ok(Number::from(set.contains(lhs.ordinal()?))),
+          _ => self.compare_sets(&lhs, set, operator),
+        };
+      }
+      let ordering = lhs.compare(&rhs)?;
+      return Ok(Number::from(match operator {
+        Equal => ordering == Ordering::Equal,
+        NotEqual => ordering != Ordering::Equal,
+        LessThan => ordering == Ordering::Less,
+        GreaterThan => ordering == Ordering::Greater,
+        LessEqual => ordering != Ordering::Greater,
+        _ => ordering != Ordering::Less,
+      }));
     }
+    // An integer is taken as a real alongside a real, and by /, which
+    // gives a real even for two integers
+    let real = match (&lhs, &rhs) {
+      (Number::Int(_), Number::Real(_)) | (Number::Real(_), Number::Int(_)) => true,
+      (Number::Int(_), Number::Int(_)) => *operator == RealDivision,
+      _ => false,
+    };
+    let (lhs, rhs) = if real {
+      (Number::Real(lhs.as_real()?), Number::Real(rhs.as_real()?))
+    } else {
+      (lhs, rhs)
+    };
+    let result = match operator {
+      Plus => lhs.plus(rhs),
+      Multiply => lhs.times(rhs),
+      Minus => lhs.minus(rhs),
+      IntegerDivision | RealDivision => lhs.divide(rhs),
+      _ => return Err(format!("Unknown operator found: {}", operator)),
+    };
+    self.check(result)
   }
   fn visit_unaryop(&mut self, node: &UnaryOpNode) -> NumberResult {
-    let UnaryOpNode { operator, expr } = node;
+    let UnaryOpNode { operator, expr, .. } = node;
     match operator {
       Plus => self.visit(expr),
-      Minus => Ok(-self.visit(expr)?),
+      Minus => {
+        let value = self.visit(expr)?.negate();
+        self.check(value)
+      }
       _ => Err(format!("Unexpected Unary Operator found: {}", operator)),
     }
   }
   fn visit_compound(&mut self, node: &CompoundNode) -> NumberResult {
-    for child in &node.children {
-      self.visit(child)?;
+    let mut index = 0;
+    while index < node.children.len() {
+      if let Err(msg) = self.visit(&node.children[index]) {
+        // A GOTO resumes at the statement with its label, if that is
+        // one of this statement sequence
+        let target = node.children.iter().position(|child| {
+          match child.downcast_ref::<LabelledStatementNode>() {
+            Some(LabelledStatementNode { label, .. }) => self.jumping_to(label),
+            None => false,
+          }
+        });
+        match target {
+          Some(target) => {
+            self.flow = None;
+            index = target;
+            continue;
+          }
+          None => return Err(msg),
+        }
+      }
+      index += 1;
     }
     Ok(Nil)
   }
+  fn visit_label_declaration(&mut self, _node: &LabelDeclarationNode) -> NumberResult {
+    Ok(Nil)
+  }
+  fn visit_labelled_statement(&mut self, node: &LabelledStatementNode) -> NumberResult {
+    // A GOTO from within the statement starts it again
+    loop {
+      match self.visit(&node.statement) {
+        Err(_) if self.jumping_to(&node.label) => self.flow = None,
+        result => return result,
+      }
+    }
+  }
+  fn visit_goto(&mut self, node: &GotoNode) -> NumberResult {
+    self.transfer(Flow::Goto(node.label.to_string()))
+  }
+  fn visit_try_except(&mut self, node: &TryExceptNode) -> NumberResult {
+    // Anything pending from before the block is put back once the block
+    // is done with, rather than lost with an exception handled in it
+    let outer = self.flow.take();
+    let msg = match self.visit(&node.body) {
+      Err(msg) => msg,
+      ok => {
+        self.flow = outer;
+        return ok;
+      }
+    };
+    let exception = match self.exception() {
+      Some(exception) => exception,
+      None => return Err(msg),
+    };
+    // The first ON clause for the exception's class handles it
+    let handler = node
+      .handlers
+      .iter()
+      .find(|handler| exception.is_a(&handler.class.to_string()));
+    let (variable, statement) = match (handler, &node.else_branch) {
+      (Some(handler), _) => (handler.variable.as_ref(), &handler.statement),
+      (None, Some(statement)) => (None, statement),
+      (None, None) => return Err(msg),
+    };
+    self.flow = outer;
+    self.handle(exception, variable, &**statement)
+  }
+  fn visit_try_finally(&mut self, node: &TryFinallyNode) -> NumberResult {
+    let result = self.visit(&node.body);
+    // Halt ends the program at once, without running FINALLY blocks
+    if let Some(Flow::Halt(_)) = self.flow {
+      return result;
+    }
+    // Whatever left the body carries on once the FINALLY block has run,
+    // unless the block itself fails
+    let pending = self.flow.take();
+    self.visit(&node.finally)?;
+    self.flow = pending;
+    result
+  }
+  fn visit_raise(&mut self, node: &RaiseNode) -> NumberResult {
+    let exception = match node.exception {
+      Some(ref exception) => {
+        let value = self.visit(exception)?;
+        Exception::from_value(&value)
+          .ok_or_else(|| format!("Cannot raise {}", value.to_string()))?
+      }
+      None => self
+        .handling
+        .last()
+        .cloned()
+        .ok_or("RAISE found outside an exception handler.")?,
+    };
+    self.transfer(Flow::Raise(exception))
+  }
+  fn visit_exception(&mut self, node: &ExceptionNode) -> NumberResult {
+    let message = self.visit(&node.message)?.to_text()?;
+    let class = node.class.to_string();
+    let class = exception::class_name(&class).unwrap_or(&class);
+    Ok(Exception::new(class, &message).to_value())
+  }
   fn visit_assign(&mut self, node: &AssignNode) -> NumberResult {
-    if node.identifier.is::<VarNode>() {
-      let var_node: &VarNode = node.identifier.downcast_ref().unwrap();
-      if let Id(name) = &var_node.identifier {
-        let value = self.visit(&node.expr);
-        self
-          .global_scope
-          .insert(name.to_string(), value?.to_string());
+    let value = self.visit(&node.expr)?;
+    let value = match self.type_node_of(&*node.identifier) {
+      Ok(type_node) => self.coerce(&type_node, value),
+      Err(_) => value,
+    };
+    self.store(&*node.identifier, value)
+  }
+  fn visit_nil(&mut self, _node: &NilNode) -> NumberResult {
+    Ok(Number::Pointer(None))
+  }
+  fn visit_deref(&mut self, node: &DerefNode) -> NumberResult {
+    let address = self.address(&*node.pointer)?;
+    match self.check(self.heap.get(address))? {
+      Nil => Err(format!(
+        "Possible use of uninitialised value {}^.",
+        to_string(&*node.pointer)
+      )),
+      value => Ok(value),
+    }
+  }
+  fn visit_field(&mut self, node: &FieldNode) -> NumberResult {
+    match self.visit(&node.record)? {
+      Number::Record(fields) => {
+        let name = node.field.to_string();
+        match fields
+          .into_iter()
+          .find(|(field, _)| names::same_name(field, &name))
+        {
+          Some((_, Nil)) => Err(format!("Possible use of uninitialised field {}.", name)),
+          Some((_, value)) => Ok(value),
+          None => Err(format!("Unknown field {} found.", name)),
+        }
+      }
+      other => Err(format!("Cannot select a field from {}", other.to_string())),
+    }
+  }
+  fn visit_while(&mut self, node: &WhileNode) -> NumberResult {
+    loop {
+      match self.visit(&node.condition)? {
+        Number::Bool(true) => {
+          if !self.loop_body(&*node.body)? {
+            return Ok(Nil);
+          }
+        }
+        Number::Bool(false) => return Ok(Nil),
+        other => {
+          return Err(format!(
+            "Expected a BOOLEAN condition, found {}",
+            other.to_string()
+          ))
+        }
+      };
+    }
+  }
+  fn visit_set(&mut self, node: &SetNode) -> NumberResult {
+    let mut set = OrdinalSet::new();
+    for (low, high) in &node.members {
+      let low = self.visit(low)?.ordinal()?;
+      let high = match high {
+        Some(high) => self.visit(high)?.ordinal()?,
+        None => low,
+      };
+      if high as i64 - low as i64 >= MAX_RANGE_SIZE {
+        return self.fail(RuntimeError::new(
+          "Exception",
+          format!("set range {}..{} is too large", low, high),
+        ));
+      }
+      set.insert_range(low, high);
+    }
+    Ok(Number::Set(set))
+  }
+  fn visit_if(&mut self, node: &IfNode) -> NumberResult {
+    match self.visit(&node.condition)? {
+      Number::Bool(true) => self.visit(&node.then_branch),
+      Number::Bool(false) => match node.else_branch {
+        Some(ref statement) => self.visit(statement),
+        None => Ok(Nil),
+      },
+      other => Err(format!(
+        "Expected a BOOLEAN condition, found {}",
+        other.to_string()
+      )),
+    }
+  }
+  fn visit_case(&mut self, node: &CaseNode) -> NumberResult {
+    let selector = self.visit(&node.selector)?;
+    let value = selector.ordinal()?;
+    for branch in &node.branches {
+      for (low, high) in &branch.labels {
+        if self.constant(low)?.ordinal()? <= value && value <= self.constant(high)?.ordinal()? {
+          return self.visit(&branch.statement);
+        }
+      }
+    }
+    match node.else_branch {
+      Some(ref statement) => self.visit(statement),
+      // ISO 7185 makes a missing label an error, Turbo Pascal ignores it
+      None if self.dialect == Dialect::Iso => {
+        let msg = format!("no CASE label matches {}", selector.to_text()?);
+        self.fail(RuntimeError::new("Exception", msg))
+      }
+      None => Ok(Nil),
+    }
+  }
+  fn visit_for(&mut self, node: &ForNode) -> NumberResult {
+    let ForNode {
+      identifier,
+      start,
+      end,
+      direction,
+      body,
+      ..
+    } = node;
+
+    // The bounds are evaluated once, before the loop starts
+    let start = self.visit(start)?;
+    let end = self.visit(end)?;
+    let (first, last) = (start.ordinal()? as i64, end.ordinal()? as i64);
+    let (step, count) = match direction {
+      Downto => (-1, first - last + 1),
+      _ => (1, last - first + 1),
+    };
+    let control = identifier.identifier.to_string();
+    for i in 0..count.max(0) {
+      let value = self.check(start.with_ordinal(first + i * step))?;
+      let index = self.frame_of(&control);
+      self.frames[index].values.insert(control.clone(), value);
+      if !self.loop_body(&**body)? {
+        break;
       }
     }
     Ok(Nil)
@@ -93,14 +992,124 @@ impl NodeVisitor for Evaluator {
   fn visit_var(&mut self, node: &VarNode) -> NumberResult {
     if let VarNode {
       identifier: Id(name),
+      ..
     } = node
     {
-      match self.global_scope.get(name.as_str()) {
-        Some(value) => Ok(Number::from_str(value).unwrap()),
+      if let Some((declared_in, routine)) = self.function(name) {
+        return self.call(&routine, declared_in, &[]);
+      }
+      match self.frames[self.frame_of(name)].values.get(name.as_str()) {
+        Some(value) => Ok(value.clone()),
         None => Err(format!("Possible use of uninitialised variable: {}.", name)),
       }
     } else {
       Err(format!("Invalid identifier found {}", node.identifier))
     }
   }
+  fn visit_index(&mut self, node: &IndexNode) -> NumberResult {
+    let index = self.visit(&node.index)?;
+    match self.visit(&node.array)? {
+      Number::Array(low, elements) => {
+        let offset = self.check(self.offset(&low, elements.len(), &index))?;
+        match elements[offset] {
+          Nil => Err(format!(
+            "Possible use of uninitialised element {}[{}].",
+            to_string(&*node.array),
+            index.ordinal()?
+          )),
+          ref element => Ok(element.clone()),
+        }
+      }
+      other => Err(format!("Cannot index into {}", other.to_string())),
+    }
+  }
+  fn visit_function_call(&mut self, node: &FunctionCallNode) -> NumberResult {
+    let FunctionCallNode {
+      func_name, args, ..
+    } = node;
+    if let Some((declared_in, routine)) = self.function(&func_name.to_string()) {
+      return self.call(&routine, declared_in, args);
+    }
+    if args.len() != 1 {
+      return Err(format!("Function {} expects 1 argument.", func_name));
+    }
+    let name = func_name.to_string().to_uppercase();
+    if name == "LOW" || name == "HIGH" {
+      return self.low_or_high(&name, &*args[0]);
+    }
+    let arg = self.visit(&args[0])?;
+    self.call_builtin_function(&name, arg)
+  }
+  fn visit_procedure_call(&mut self, node: &ProcedureCallNode) -> NumberResult {
+    let ProcedureCallNode {
+      proc_name,
+      args,
+      position,
+      ..
+    } = node;
+    // A user-defined procedure hides a standard one of the same name
+    if let Some((declared_in, routine)) = self.routine(&proc_name.to_string()) {
+      return self.call(&routine, declared_in, args);
+    }
+    let name = proc_name.to_string().to_uppercase();
+    match name.as_str() {
+      "INC" | "DEC" => {
+        let value = self.visit(&args[0])?;
+        let mut step = match args.get(1) {
+          Some(step) => self.visit(step)?.ordinal()? as i64,
+          None => 1,
+        };
+        if name == "DEC" {
+          step = -step;
+        }
+        let value = self.check(value.with_ordinal(value.ordinal()? as i64 + step))?;
+        self.store(&*args[0], value)
+      }
+      "NEW" => {
+        let target = match self.resolve(&self.type_node_of(&*args[0])?).kind {
+          TypeKind::Pointer(name) => name,
+          _ => return Err(format!("Procedure {} expects a pointer.", proc_name)),
+        };
+        let value = self.default_value(&TypeNode::named(target.clone()))?;
+        let address =
+          self
+            .heap
+            .allocate(value, &target.to_string(), position.line, position.column);
+        self.store(&*args[0], Number::Pointer(Some(address)))
+      }
+      "DISPOSE" => {
+        let address = self.address(&*args[0])?;
+        let disposed = self.heap.dispose(address);
+        self.check(disposed)?;
+        Ok(Nil)
+      }
+      "BREAK" => self.transfer(Flow::Break),
+      "CONTINUE" => self.transfer(Flow::Continue),
+      "EXIT" => {
+        if let Some(value) = args.first() {
+          let value = self.visit(value)?;
+          self.frames.last_mut().unwrap().result = Some(value);
+        }
+        self.transfer(Flow::Exit)
+      }
+      "HALT" => {
+        let code = match args.first() {
+          Some(code) => self.visit(code)?.ordinal()?,
+          None => 0,
+        };
+        // The code becomes the exit status of a process, which only
+        // keeps its lowest byte
+        if !(0..=255).contains(&code) {
+          return self.fail(RuntimeError::new(
+            "ERangeError",
+            format!("Halt code {} is outside 0..255", code),
+          ));
+        }
+        self.transfer(Flow::Halt(code))
+      }
+      "WRITE" => self.write(args, false),
+      "WRITELN" => self.write(args, true),
+      _ => Err(format!("Unknown procedure {} called.", proc_name)),
+    }
+  }
 }
@@ -0,0 +1,405 @@
+use ast::node::*;
+use ast::printer::Printer;
+use ast::visitor::NodeVisitor;
+use lexer::token::Token;
+use std::fmt::Write;
+use symbols::symbol::SymbolTable;
+use utils::number::{Number::Nil, NumberResult};
+
+/// Escapes text for a DOT label, keeping line breaks.
+fn escape(text: &str) -> String {
+  text
+    .replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace('\n', "\\n")
+}
+
+/// Escapes text for a field of a DOT record, where braces, bars and
+/// angle brackets lay out the fields.
+fn escape_field(text: &str) -> String {
+  let mut escaped = String::new();
+  for c in escape(text).chars() {
+    if "{}|<>".contains(c) {
+      escaped.push('\\');
+    }
+    escaped.push(c);
+  }
+  escaped
+}
+
+/// Returns the Pascal source of a type specification.
+fn type_text(type_node: &TypeNode) -> String {
+  let mut printer = Printer::new();
+  printer.visit_type(type_node).unwrap();
+  printer.source()
+}
+
+/// Writes a syntax tree as a Graphviz DOT graph, which `dot -Tpng`
+/// draws as a diagram. Each node is labelled with its kind, or with
+/// the operator, identifier or value it holds, and points to its
+/// children from left to right.
+pub struct AstGraph {
+  output: String,
+  // The number of nodes written so far
+  count: usize,
+}
+
+impl AstGraph {
+  pub fn new() -> Self {
+    AstGraph {
+      output: String::new(),
+      count: 0,
+    }
+  }
+  /// Returns the graph of the tree visited.
+  pub fn graph(self) -> String {
+    format!(
+      "digraph astgraph {{\n  \
+       node [shape=box, style=rounded, fontsize=12, fontname=\"Courier\", height=.1];\n  \
+       ranksep=.3;\n  \
+       edge [arrowsize=.5]\n\n\
+       {}}}\n",
+      self.output
+    )
+  }
+  /// Writes a node with the given label, returning its number.
+  fn add(&mut self, label: &str) -> usize {
+    let id = self.count;
+    self.count += 1;
+    writeln!(self.output, "  node{} [label=\"{}\"]", id, escape(label)).unwrap();
+    id
+  }
+  fn edge(&mut self, parent: usize, child: usize, label: &str) {
+    if label.is_empty() {
+      writeln!(self.output, "  node{} -> node{}", parent, child).unwrap();
+    } else {
+      writeln!(
+        self.output,
+        "  node{} -> node{} [label=\"{}\"]",
+        parent,
+        child,
+        escape(label)
+      )
+      .unwrap();
+    }
+  }
+  /// Writes a child of a node, along with the edge leading to it.
+  fn child(&mut self, parent: usize, node: &Node) -> NumberResult {
+    self.labelled_child(parent, node, "")
+  }
+  fn labelled_child(&mut self, parent: usize, node: &Node, label: &str) -> NumberResult {
+    // Nodes are numbered as they are written, parents before children
+    let child = self.count;
+    self.visit(node)?;
+    self.edge(parent, child, label);
+    Ok(Nil)
+  }
+  fn children(&mut self, parent: usize, nodes: &[Box<Node>]) -> NumberResult {
+    for node in nodes {
+      self.child(parent, &**node)?;
+    }
+    Ok(Nil)
+  }
+  fn leaf(&mut self, parent: usize, label: &str) {
+    let child = self.add(label);
+    self.edge(parent, child, "");
+  }
+  fn var(&mut self, parent: usize, node: &VarNode) {
+    self.leaf(parent, &node.identifier.to_string());
+  }
+  fn type_leaf(&mut self, parent: usize, type_node: &TypeNode) {
+    self.leaf(parent, &type_text(type_node));
+  }
+}
+
+impl NodeVisitor for AstGraph {
+  fn visit_program(&mut self, node: &ProgramNode) -> NumberResult {
+    let id = self.add(&format!("Program {}", node.identifier));
+    self.child(id, &*node.block)
+  }
+  fn visit_procedure(&mut self, node: &ProcedureNode) -> NumberResult {
+    let kind = match node.return_type {
+      Some(_) => "Function",
+      None => "Procedure",
+    };
+    let id = self.add(&format!("{} {}", kind, node.proc_name));
+    self.children(id, &node.params)?;
+    if let Some(return_type) = &node.return_type {
+      self.type_leaf(id, return_type);
+    }
+    match &node.block {
+      Some(block) => self.child(id, &***block),
+      None => {
+        self.leaf(id, &Token::Forward.to_string());
+        Ok(Nil)
+      }
+    }
+  }
+  fn visit_parameter(&mut self, node: &ParameterNode) -> NumberResult {
+    let id = self.add("Parameter");
+    self.var(id, &node.var_node);
+    self.type_leaf(id, &node.type_node);
+    Ok(Nil)
+  }
+  fn visit_block(&mut self, node: &BlockNode) -> NumberResult {
+    let id = self.add("Block");
+    self.children(id, &node.declarations)?;
+    self.child(id, &*node.compound_statement)
+  }
+  fn visit_declaration(&mut self, node: &DeclarationNode) -> NumberResult {
+    let id = self.add("VarDecl");
+    self.var(id, &node.var_node);
+    self.type_leaf(id, &node.type_node);
+    Ok(Nil)
+  }
+  fn visit_type_declaration(&mut self, node: &TypeDeclarationNode) -> NumberResult {
+    let id = self.add("TypeDecl");
+    self.leaf(id, &node.type_name.to_string());
+    self.type_leaf(id, &node.type_node);
+    Ok(Nil)
+  }
+  fn visit_type(&mut self, node: &TypeNode) -> NumberResult {
+    self.add(&type_text(node));
+    Ok(Nil)
+  }
+  fn visit_integer(&mut self, node: &IntegerNumNode) -> NumberResult {
+    self.add(&node.value.to_string());
+    Ok(Nil)
+  }
+  fn visit_real(&mut self, node: &RealNumNode) -> NumberResult {
+    self.add(&format!("{:?}", node.value));
+    Ok(Nil)
+  }
+  fn visit_char(&mut self, node: &CharNode) -> NumberResult {
+    self.add(&Token::StringConst(node.value.to_string()).to_string());
+    Ok(Nil)
+  }
+  fn visit_string(&mut self, node: &StringNode) -> NumberResult {
+    self.add(&Token::StringConst(node.value.clone()).to_string());
+    Ok(Nil)
+  }
+  fn visit_boolean(&mut self, node: &BooleanNode) -> NumberResult {
+    let value = if node.value {
+      Token::True
+    } else {
+      Token::False
+    };
+    self.add(&value.to_string());
+    Ok(Nil)
+  }
+  fn visit_binop(&mut self, node: &BinOpNode) -> NumberResult {
+    let id = self.add(&node.operator.to_string());
+    self.child(id, &*node.left)?;
+    self.child(id, &*node.right)
+  }
+  fn visit_unaryop(&mut self, node: &UnaryOpNode) -> NumberResult {
+    let id = self.add(&format!("unary {}", node.operator));
+    self.child(id, &*node.expr)
+  }
+  fn visit_compound(&mut self, node: &CompoundNode) -> NumberResult {
+    let id = self.add("Compound");
+    self.children(id, &node.children)
+  }
+  fn visit_assign(&mut self, node: &AssignNode) -> NumberResult {
+    let id = self.add(&node.operator.to_string());
+    self.child(id, &*node.identifier)?;
+    self.child(id, &*node.expr)
+  }
+  fn visit_nil(&mut self, _node: &NilNode) -> NumberResult {
+    self.add(&Token::Nil.to_string());
+    Ok(Nil)
+  }
+  fn visit_deref(&mut self, node: &DerefNode) -> NumberResult {
+    let id = self.add(&Token::Caret.to_string());
+    self.child(id, &*node.pointer)
+  }
+  fn visit_field(&mut self, node: &FieldNode) -> NumberResult {
+    let id = self.add(&format!(".{}", node.field));
+    self.child(id, &*node.record)
+  }
+  fn visit_while(&mut self, node: &WhileNode) -> NumberResult {
+    let id = self.add("While");
+    self.child(id, &*node.condition)?;
+    self.labelled_child(id, &*node.body, "do")
+  }
+  fn visit_label_declaration(&mut self, node: &LabelDeclarationNode) -> NumberResult {
+    self.add(&format!("Label {}", node.label));
+    Ok(Nil)
+  }
+  fn visit_labelled_statement(&mut self, node: &LabelledStatementNode) -> NumberResult {
+    let id = self.add(&format!("{}:", node.label));
+    self.child(id, &*node.statement)
+  }
+  fn visit_goto(&mut self, node: &GotoNode) -> NumberResult {
+    self.add(&format!("Goto {}", node.label));
+    Ok(Nil)
+  }
+  fn visit_try_except(&mut self, node: &TryExceptNode) -> NumberResult {
+    let id = self.add("Try");
+    self.child(id, &*node.body)?;
+    for handler in &node.handlers {
+      let label = match &handler.variable {
+        Some(variable) => format!("On {}: {}", variable, handler.class),
+        None => format!("On {}", handler.class),
+      };
+      let handler_id = self.add(&label);
+      self.edge(id, handler_id, "except");
+      self.child(handler_id, &*handler.statement)?;
+    }
+    if let Some(else_branch) = &node.else_branch {
+      let label = if node.handlers.is_empty() {
+        "except"
+      } else {
+        "else"
+      };
+      self.labelled_child(id, &**else_branch, label)?;
+    }
+    Ok(Nil)
+  }
+  fn visit_try_finally(&mut self, node: &TryFinallyNode) -> NumberResult {
+    let id = self.add("Try");
+    self.child(id, &*node.body)?;
+    self.labelled_child(id, &*node.finally, "finally")
+  }
+  fn visit_raise(&mut self, node: &RaiseNode) -> NumberResult {
+    let id = self.add("Raise");
+    if let Some(exception) = &node.exception {
+      self.child(id, &**exception)?;
+    }
+    Ok(Nil)
+  }
+  fn visit_exception(&mut self, node: &ExceptionNode) -> NumberResult {
+    let id = self.add(&format!("{}.Create", node.class));
+    self.child(id, &*node.message)
+  }
+  fn visit_set(&mut self, node: &SetNode) -> NumberResult {
+    let id = self.add("Set");
+    for (low, high) in &node.members {
+      match high {
+        Some(high) => {
+          let range = self.add(&Token::Range.to_string());
+          self.edge(id, range, "");
+          self.child(range, &**low)?;
+          self.child(range, &**high)?;
+        }
+        None => {
+          self.child(id, &**low)?;
+        }
+      }
+    }
+    Ok(Nil)
+  }
+  fn visit_if(&mut self, node: &IfNode) -> NumberResult {
+    let id = self.add("If");
+    self.child(id, &*node.condition)?;
+    self.labelled_child(id, &*node.then_branch, "then")?;
+    if let Some(else_branch) = &node.else_branch {
+      self.labelled_child(id, &**else_branch, "else")?;
+    }
+    Ok(Nil)
+  }
+  fn visit_case(&mut self, node: &CaseNode) -> NumberResult {
+    let id = self.add("Case");
+    self.child(id, &*node.selector)?;
+    for branch in &node.branches {
+      let labels: Vec<String> = branch
+        .labels
+        .iter()
+        .map(|(low, high)| {
+          if low == high {
+            low.to_string()
+          } else {
+            format!("{}{}{}", low, Token::Range, high)
+          }
+        })
+        .collect();
+      let branch_id = self.add(&format!("{}:", labels.join(", ")));
+      self.edge(id, branch_id, "");
+      self.child(branch_id, &*branch.statement)?;
+    }
+    if let Some(else_branch) = &node.else_branch {
+      self.labelled_child(id, &**else_branch, "else")?;
+    }
+    Ok(Nil)
+  }
+  fn visit_for(&mut self, node: &ForNode) -> NumberResult {
+    let id = self.add(&format!("For {}", node.direction));
+    self.var(id, &node.identifier);
+    self.child(id, &*node.start)?;
+    self.child(id, &*node.end)?;
+    self.labelled_child(id, &*node.body, "do")
+  }
+  fn visit_var(&mut self, node: &VarNode) -> NumberResult {
+    self.add(&node.identifier.to_string());
+    Ok(Nil)
+  }
+  fn visit_index(&mut self, node: &IndexNode) -> NumberResult {
+    let id = self.add("[]");
+    self.child(id, &*node.array)?;
+    self.child(id, &*node.index)
+  }
+  fn visit_function_call(&mut self, node: &FunctionCallNode) -> NumberResult {
+    let id = self.add(&format!("{}()", node.func_name));
+    self.children(id, &node.args)
+  }
+  fn visit_procedure_call(&mut self, node: &ProcedureCallNode) -> NumberResult {
+    let id = self.add(&format!("{}()", node.proc_name));
+    self.children(id, &node.args)
+  }
+  fn visit_noop(&mut self, _node: &NoOpNode) -> NumberResult {
+    self.add("NoOp");
+    Ok(Nil)
+  }
+}
+
+/// Writes a scope and the scopes nested in it as a Graphviz DOT graph,
+/// each scope a box listing its symbols in the order they were declared,
+/// with an arrow to each scope nested in it.
+pub struct ScopeGraph {
+  output: String,
+  // The number of scopes written so far
+  count: usize,
+}
+
+impl ScopeGraph {
+  pub fn new() -> Self {
+    ScopeGraph {
+      output: String::new(),
+      count: 0,
+    }
+  }
+  /// Returns the graph of the scopes visited.
+  pub fn graph(self) -> String {
+    format!(
+      "digraph scopes {{\n  \
+       node [shape=record, fontsize=12, fontname=\"Courier\"];\n  \
+       edge [arrowsize=.5]\n\n\
+       {}}}\n",
+      self.output
+    )
+  }
+  /// Writes a scope and the scopes nested in it, returning its number.
+  pub fn visit_scope(&mut self, scope: &SymbolTable) -> usize {
+    let id = self.count;
+    self.count += 1;
+    let symbols: String = scope
+      .symbols()
+      .iter()
+      .map(|symbol| format!("{}\\l", escape_field(&symbol.to_string())))
+      .collect();
+    let heading = format!("{} (level {})", scope.scope_name(), scope.scope_level());
+    writeln!(
+      self.output,
+      "  scope{} [label=\"{{{}|{}}}\"]",
+      id,
+      escape_field(&heading),
+      symbols
+    )
+    .unwrap();
+    for nested_scope in scope.nested_scopes() {
+      let nested = self.visit_scope(nested_scope);
+      writeln!(self.output, "  scope{} -> scope{}", id, nested).unwrap();
+    }
+    id
+  }
+}
@@ -1,4 +1,7 @@
 pub mod evaluator;
+pub mod graph;
 pub mod node;
+pub mod printer;
+pub mod serializer;
 pub mod table_builder;
 pub mod visitor;
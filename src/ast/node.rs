@@ -1,11 +1,13 @@
 use ast::visitor::NodeVisitor;
 use lexer::token::Token;
+use lexer::{Position, Span};
 use std::fmt;
+use std::sync::Arc;
 use utils::number::NumberResult;
 
 use mopa;
 
-pub trait Node: mopa::Any {
+pub trait Node: mopa::Any + Send + Sync {
   fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult;
 }
 
@@ -14,11 +16,14 @@ mopafy!(Node);
 #[derive(Debug)]
 pub struct IntegerNumNode {
   pub value: i32,
+  // The constant as written, such as $FF
+  pub spelling: String,
+  pub span: Span,
 }
 
 impl IntegerNumNode {
-  pub fn new(value: i32) -> Self {
-    IntegerNumNode { value }
+  pub fn new(value: i32, spelling: String, span: Span) -> Self {
+    IntegerNumNode { value, spelling, span }
   }
 }
 
@@ -31,11 +36,14 @@ impl Node for IntegerNumNode {
 #[derive(Debug)]
 pub struct RealNumNode {
   pub value: f32,
+  // The constant as written, such as 1.5e3
+  pub spelling: String,
+  pub span: Span,
 }
 
 impl RealNumNode {
-  pub fn new(value: f32) -> Self {
-    RealNumNode { value }
+  pub fn new(value: f32, spelling: String, span: Span) -> Self {
+    RealNumNode { value, spelling, span }
   }
 }
 
@@ -49,9 +57,10 @@ pub struct BinOpNode {
   pub left: Box<Node>,
   pub right: Box<Node>,
   pub operator: Token,
+  pub span: Span,
 }
 
-pub fn to_string(node: &Box<Node>) -> String {
+pub fn to_string(node: &Node) -> String {
   if node.is::<IntegerNumNode>() {
     node
       .downcast_ref::<IntegerNumNode>()
@@ -64,35 +73,47 @@ pub fn to_string(node: &Box<Node>) -> String {
       .unwrap()
       .value
       .to_string()
-  } else {
-    let BinOpNode {
-      left,
-      right,
-      operator,
-    } = node.downcast_ref::<BinOpNode>().unwrap();
+  } else if let Some(VarNode { identifier, .. }) = node.downcast_ref::<VarNode>() {
+    identifier.to_string()
+  } else if let Some(IndexNode { array, index, .. }) = node.downcast_ref::<IndexNode>() {
+    format!("{}[{}]", to_string(&**array), to_string(&**index))
+  } else if let Some(FieldNode { record, field, .. }) = node.downcast_ref::<FieldNode>() {
+    format!("{}.{}", to_string(&**record), field)
+  } else if let Some(DerefNode { pointer, .. }) = node.downcast_ref::<DerefNode>() {
+    format!("{}^", to_string(&**pointer))
+  } else if let Some(BinOpNode {
+    left,
+    right,
+    operator,
+    ..
+  }) = node.downcast_ref::<BinOpNode>()
+  {
     format!(
       "BinOpNode({} {} {}) ",
-      to_string(left),
-      to_string(right),
+      to_string(&**left),
+      to_string(&**right),
       operator
     )
+  } else {
+    "<expression>".to_string()
   }
 }
 
 impl fmt::Display for BinOpNode {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    let left = to_string(&self.left);
-    let right = to_string(&self.right);
+    let left = to_string(&*self.left);
+    let right = to_string(&*self.right);
     write!(f, "BinOpNode({} {} {}) ", left, right, self.operator)
   }
 }
 
 impl BinOpNode {
-  pub fn new(left: Box<Node>, right: Box<Node>, operator: Token) -> Self {
+  pub fn new(left: Box<Node>, right: Box<Node>, operator: Token, span: Span) -> Self {
     BinOpNode {
       left,
       right,
       operator,
+      span,
     }
   }
 }
@@ -106,11 +127,16 @@ impl Node for BinOpNode {
 pub struct UnaryOpNode {
   pub operator: Token,
   pub expr: Box<Node>,
+  pub span: Span,
 }
 
 impl UnaryOpNode {
-  pub fn new(operator: Token, expr: Box<Node>) -> Self {
-    UnaryOpNode { operator, expr }
+  pub fn new(operator: Token, expr: Box<Node>, span: Span) -> Self {
+    UnaryOpNode {
+      operator,
+      expr,
+      span,
+    }
   }
 }
 
@@ -122,11 +148,12 @@ impl Node for UnaryOpNode {
 
 pub struct CompoundNode {
   pub children: Vec<Box<Node>>,
+  pub span: Span,
 }
 
 impl CompoundNode {
-  pub fn new(children: Vec<Box<Node>>) -> Self {
-    CompoundNode { children }
+  pub fn new(children: Vec<Box<Node>>, span: Span) -> Self {
+    CompoundNode { children, span }
   }
 }
 
@@ -136,18 +163,289 @@ impl Node for CompoundNode {
   }
 }
 
+pub struct WhileNode {
+  pub condition: Box<Node>,
+  pub body: Box<Node>,
+  pub span: Span,
+}
+
+impl WhileNode {
+  pub fn new(condition: Box<Node>, body: Box<Node>, span: Span) -> Self {
+    WhileNode {
+      condition,
+      body,
+      span,
+    }
+  }
+}
+
+impl Node for WhileNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_while(self)
+  }
+}
+
+pub struct LabelDeclarationNode {
+  pub label: Token,
+  pub span: Span,
+}
+
+impl LabelDeclarationNode {
+  pub fn new(label: Token, span: Span) -> Self {
+    LabelDeclarationNode { label, span }
+  }
+}
+
+impl Node for LabelDeclarationNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_label_declaration(self)
+  }
+}
+
+pub struct LabelledStatementNode {
+  pub label: Token,
+  pub statement: Box<Node>,
+  pub span: Span,
+}
+
+impl LabelledStatementNode {
+  pub fn new(label: Token, statement: Box<Node>, span: Span) -> Self {
+    LabelledStatementNode {
+      label,
+      statement,
+      span,
+    }
+  }
+}
+
+impl Node for LabelledStatementNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_labelled_statement(self)
+  }
+}
+
+pub struct GotoNode {
+  pub label: Token,
+  pub span: Span,
+}
+
+impl GotoNode {
+  pub fn new(label: Token, span: Span) -> Self {
+    GotoNode { label, span }
+  }
+}
+
+impl Node for GotoNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_goto(self)
+  }
+}
+
+/// An ON clause of an EXCEPT block, handling exceptions of a class and
+/// the classes inheriting from it, optionally naming the exception.
+pub struct ExceptionHandler {
+  pub variable: Option<Token>,
+  pub class: Token,
+  pub statement: Box<Node>,
+  pub span: Span,
+}
+
+impl ExceptionHandler {
+  pub fn new(variable: Option<Token>, class: Token, statement: Box<Node>, span: Span) -> Self {
+    ExceptionHandler {
+      variable,
+      class,
+      statement,
+      span,
+    }
+  }
+}
+
+pub struct TryExceptNode {
+  pub body: Box<Node>,
+  pub handlers: Vec<ExceptionHandler>,
+  // Handles any exception no ON clause handles; an EXCEPT block without
+  // ON clauses is kept here
+  pub else_branch: Option<Box<Node>>,
+  pub span: Span,
+}
+
+impl TryExceptNode {
+  pub fn new(
+    body: Box<Node>,
+    handlers: Vec<ExceptionHandler>,
+    else_branch: Option<Box<Node>>,
+    span: Span,
+  ) -> Self {
+    TryExceptNode {
+      body,
+      handlers,
+      else_branch,
+      span,
+    }
+  }
+}
+
+impl Node for TryExceptNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_try_except(self)
+  }
+}
+
+pub struct TryFinallyNode {
+  pub body: Box<Node>,
+  pub finally: Box<Node>,
+  pub span: Span,
+}
+
+impl TryFinallyNode {
+  pub fn new(body: Box<Node>, finally: Box<Node>, span: Span) -> Self {
+    TryFinallyNode {
+      body,
+      finally,
+      span,
+    }
+  }
+}
+
+impl Node for TryFinallyNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_try_finally(self)
+  }
+}
+
+pub struct RaiseNode {
+  // The exception raised; without one, the exception being handled is
+  // raised again
+  pub exception: Option<Box<Node>>,
+  pub span: Span,
+}
+
+impl RaiseNode {
+  pub fn new(exception: Option<Box<Node>>, span: Span) -> Self {
+    RaiseNode { exception, span }
+  }
+}
+
+impl Node for RaiseNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_raise(self)
+  }
+}
+
+/// A new exception, created by Class.Create(message).
+pub struct ExceptionNode {
+  pub class: Token,
+  pub message: Box<Node>,
+  pub span: Span,
+}
+
+impl ExceptionNode {
+  pub fn new(class: Token, message: Box<Node>, span: Span) -> Self {
+    ExceptionNode {
+      class,
+      message,
+      span,
+    }
+  }
+}
+
+impl Node for ExceptionNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_exception(self)
+  }
+}
+
+pub struct IfNode {
+  pub condition: Box<Node>,
+  pub then_branch: Box<Node>,
+  pub else_branch: Option<Box<Node>>,
+  pub span: Span,
+}
+
+impl IfNode {
+  pub fn new(
+    condition: Box<Node>,
+    then_branch: Box<Node>,
+    else_branch: Option<Box<Node>>,
+    span: Span,
+  ) -> Self {
+    IfNode {
+      condition,
+      then_branch,
+      else_branch,
+      span,
+    }
+  }
+}
+
+impl Node for IfNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_if(self)
+  }
+}
+
+/// A group of case labels, each a range of constants, and the statement
+/// executed when the selector matches one of them.
+pub struct CaseBranch {
+  pub labels: Vec<(Token, Token)>,
+  pub statement: Box<Node>,
+  pub span: Span,
+}
+
+impl CaseBranch {
+  pub fn new(labels: Vec<(Token, Token)>, statement: Box<Node>, span: Span) -> Self {
+    CaseBranch {
+      labels,
+      statement,
+      span,
+    }
+  }
+}
+
+pub struct CaseNode {
+  pub selector: Box<Node>,
+  pub branches: Vec<CaseBranch>,
+  pub else_branch: Option<Box<Node>>,
+  pub span: Span,
+}
+
+impl CaseNode {
+  pub fn new(
+    selector: Box<Node>,
+    branches: Vec<CaseBranch>,
+    else_branch: Option<Box<Node>>,
+    span: Span,
+  ) -> Self {
+    CaseNode {
+      selector,
+      branches,
+      else_branch,
+      span,
+    }
+  }
+}
+
+impl Node for CaseNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_case(self)
+  }
+}
+
 pub struct AssignNode {
   pub identifier: Box<Node>,
   pub expr: Box<Node>,
   pub operator: Token,
+  pub span: Span,
 }
 
 impl AssignNode {
-  pub fn new(identifier: Box<Node>, expr: Box<Node>, operator: Token) -> Self {
+  pub fn new(identifier: Box<Node>, expr: Box<Node>, operator: Token, span: Span) -> Self {
     AssignNode {
       identifier,
       expr,
       operator,
+      span,
     }
   }
 }
@@ -160,11 +458,12 @@ impl Node for AssignNode {
 
 pub struct VarNode {
   pub identifier: Token,
+  pub span: Span,
 }
 
 impl VarNode {
-  pub fn new(identifier: Token) -> Self {
-    VarNode { identifier }
+  pub fn new(identifier: Token, span: Span) -> Self {
+    VarNode { identifier, span }
   }
 }
 
@@ -174,7 +473,242 @@ impl Node for VarNode {
   }
 }
 
-pub struct NoOpNode {}
+#[derive(Debug)]
+pub struct CharNode {
+  pub value: char,
+  pub span: Span,
+}
+
+impl CharNode {
+  pub fn new(value: char, span: Span) -> Self {
+    CharNode { value, span }
+  }
+}
+
+impl Node for CharNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_char(self)
+  }
+}
+
+#[derive(Debug)]
+pub struct StringNode {
+  pub value: String,
+  pub span: Span,
+}
+
+impl StringNode {
+  pub fn new(value: String, span: Span) -> Self {
+    StringNode { value, span }
+  }
+}
+
+impl Node for StringNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_string(self)
+  }
+}
+
+#[derive(Debug)]
+pub struct BooleanNode {
+  pub value: bool,
+  pub span: Span,
+}
+
+impl BooleanNode {
+  pub fn new(value: bool, span: Span) -> Self {
+    BooleanNode { value, span }
+  }
+}
+
+impl Node for BooleanNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_boolean(self)
+  }
+}
+
+pub struct IndexNode {
+  pub array: Box<Node>,
+  pub index: Box<Node>,
+  pub span: Span,
+}
+
+impl IndexNode {
+  pub fn new(array: Box<Node>, index: Box<Node>, span: Span) -> Self {
+    IndexNode { array, index, span }
+  }
+}
+
+impl Node for IndexNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_index(self)
+  }
+}
+
+pub struct ForNode {
+  pub identifier: VarNode,
+  pub start: Box<Node>,
+  pub end: Box<Node>,
+  pub direction: Token,
+  pub body: Box<Node>,
+  pub span: Span,
+}
+
+impl ForNode {
+  pub fn new(
+    identifier: VarNode,
+    start: Box<Node>,
+    end: Box<Node>,
+    direction: Token,
+    body: Box<Node>,
+    span: Span,
+  ) -> Self {
+    ForNode {
+      identifier,
+      start,
+      end,
+      direction,
+      body,
+      span,
+    }
+  }
+}
+
+impl Node for ForNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_for(self)
+  }
+}
+
+pub struct NilNode {
+  pub span: Span,
+}
+
+impl NilNode {
+  pub fn new(span: Span) -> Self {
+    NilNode { span }
+  }
+}
+
+impl Node for NilNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_nil(self)
+  }
+}
+
+/// The variable a pointer points to, as in p^
+pub struct DerefNode {
+  pub pointer: Box<Node>,
+  pub span: Span,
+}
+
+impl DerefNode {
+  pub fn new(pointer: Box<Node>, span: Span) -> Self {
+    DerefNode { pointer, span }
+  }
+}
+
+impl Node for DerefNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_deref(self)
+  }
+}
+
+/// A field of a record, as in r.name
+pub struct FieldNode {
+  pub record: Box<Node>,
+  pub field: Token,
+  pub span: Span,
+}
+
+impl FieldNode {
+  pub fn new(record: Box<Node>, field: Token, span: Span) -> Self {
+    FieldNode {
+      record,
+      field,
+      span,
+    }
+  }
+}
+
+impl Node for FieldNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_field(self)
+  }
+}
+
+/// A member of a set constructor: a single value, or the lower and
+/// upper bounds of a range.
+pub type SetMember = (Box<Node>, Option<Box<Node>>);
+
+/// A set constructor such as ['a'..'z', '_'], each member being
+/// a single value or the lower and upper bounds of a range.
+pub struct SetNode {
+  pub members: Vec<SetMember>,
+  pub span: Span,
+}
+
+impl SetNode {
+  pub fn new(members: Vec<SetMember>, span: Span) -> Self {
+    SetNode { members, span }
+  }
+}
+
+impl Node for SetNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_set(self)
+  }
+}
+
+pub struct FunctionCallNode {
+  pub func_name: Token,
+  pub args: Vec<Box<Node>>,
+  pub span: Span,
+}
+
+impl FunctionCallNode {
+  pub fn new(func_name: Token, args: Vec<Box<Node>>, span: Span) -> Self {
+    FunctionCallNode {
+      func_name,
+      args,
+      span,
+    }
+  }
+}
+
+impl Node for FunctionCallNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_function_call(self)
+  }
+}
+
+pub struct ProcedureCallNode {
+  pub proc_name: Token,
+  pub args: Vec<Box<Node>>,
+  pub position: Position,
+  pub span: Span,
+}
+
+impl ProcedureCallNode {
+  pub fn new(proc_name: Token, args: Vec<Box<Node>>, position: Position, span: Span) -> Self {
+    ProcedureCallNode {
+      proc_name,
+      args,
+      position,
+      span,
+    }
+  }
+}
+
+impl Node for ProcedureCallNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_procedure_call(self)
+  }
+}
+
+pub struct NoOpNode {
+  pub span: Span,
+}
 
 impl Node for NoOpNode {
   fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
@@ -185,11 +719,16 @@ impl Node for NoOpNode {
 pub struct ProgramNode {
   pub identifier: Token,
   pub block: Box<Node>,
+  pub span: Span,
 }
 
 impl ProgramNode {
-  pub fn new(identifier: Token, block: Box<Node>) -> Self {
-    ProgramNode { identifier, block }
+  pub fn new(identifier: Token, block: Box<Node>, span: Span) -> Self {
+    ProgramNode {
+      identifier,
+      block,
+      span,
+    }
   }
 }
 
@@ -202,15 +741,28 @@ impl Node for ProgramNode {
 pub struct ProcedureNode {
   pub proc_name: Token,
   pub params: Vec<Box<Node>>,
-  pub block: Box<Node>,
+  // The result type of a function; procedures have none
+  pub return_type: Option<TypeNode>,
+  // The body is shared so the evaluator can keep it for later calls;
+  // a FORWARD declaration has none
+  pub block: Option<Arc<Box<Node>>>,
+  pub span: Span,
 }
 
 impl ProcedureNode {
-  pub fn new(proc_name: Token, params: Vec<Box<Node>>, block: Box<Node>) -> Self {
+  pub fn new(
+    proc_name: Token,
+    params: Vec<Box<Node>>,
+    return_type: Option<TypeNode>,
+    block: Option<Arc<Box<Node>>>,
+    span: Span,
+  ) -> Self {
     ProcedureNode {
       proc_name,
       params,
+      return_type,
       block,
+      span,
     }
   }
 }
@@ -224,13 +776,15 @@ impl Node for ProcedureNode {
 pub struct ParameterNode {
   pub var_node: VarNode,
   pub type_node: TypeNode,
+  pub span: Span,
 }
 
 impl ParameterNode {
-  pub fn new(var_node: VarNode, type_node: TypeNode) -> Self {
+  pub fn new(var_node: VarNode, type_node: TypeNode, span: Span) -> Self {
     ParameterNode {
       var_node,
       type_node,
+      span,
     }
   }
 }
@@ -244,13 +798,15 @@ impl Node for ParameterNode {
 pub struct BlockNode {
   pub declarations: Vec<Box<Node>>,
   pub compound_statement: Box<Node>,
+  pub span: Span,
 }
 
 impl BlockNode {
-  pub fn new(declarations: Vec<Box<Node>>, compound_statement: Box<Node>) -> Self {
+  pub fn new(declarations: Vec<Box<Node>>, compound_statement: Box<Node>, span: Span) -> Self {
     BlockNode {
       declarations,
       compound_statement,
+      span,
     }
   }
 }
@@ -264,13 +820,15 @@ impl Node for BlockNode {
 pub struct DeclarationNode {
   pub var_node: VarNode,
   pub type_node: TypeNode,
+  pub span: Span,
 }
 
 impl DeclarationNode {
-  pub fn new(var_node: VarNode, type_node: TypeNode) -> Self {
+  pub fn new(var_node: VarNode, type_node: TypeNode, span: Span) -> Self {
     DeclarationNode {
       var_node,
       type_node,
+      span,
     }
   }
 }
@@ -281,14 +839,60 @@ impl Node for DeclarationNode {
   }
 }
 
+pub struct TypeDeclarationNode {
+  pub type_name: Token,
+  pub type_node: TypeNode,
+  pub span: Span,
+}
+
+impl TypeDeclarationNode {
+  pub fn new(type_name: Token, type_node: TypeNode, span: Span) -> Self {
+    TypeDeclarationNode {
+      type_name,
+      type_node,
+      span,
+    }
+  }
+}
+
+impl Node for TypeDeclarationNode {
+  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
+    visitor.visit_type_declaration(self)
+  }
+}
+
 #[derive(Clone)]
 pub struct TypeNode {
-  pub token: Token,
+  pub kind: TypeKind,
+  pub span: Span,
+}
+
+#[derive(Clone)]
+pub enum TypeKind {
+  Named(Token),
+  // The lower and upper bounds, each a constant
+  Subrange(Token, Token),
+  // The index type followed by the element type
+  Array(Box<TypeNode>, Box<TypeNode>),
+  // The identifiers naming each value, in order
+  Enumeration(Vec<Token>),
+  // The base type of the members
+  Set(Box<TypeNode>),
+  // The name of the type pointed to
+  Pointer(Token),
+  // The name and type of each field, and the span of the declaration
+  // it shares with any fields declared alongside it
+  Record(Vec<(Token, TypeNode, Span)>),
 }
 
 impl TypeNode {
-  pub fn new(token: Token) -> Self {
-    TypeNode { token }
+  pub fn new(kind: TypeKind, span: Span) -> Self {
+    TypeNode { kind, span }
+  }
+  /// Creates a type named by a token, which isn't written anywhere in
+  /// the source and so covers no text.
+  pub fn named(token: Token) -> Self {
+    TypeNode::new(TypeKind::Named(token), Span::default())
   }
 }
 
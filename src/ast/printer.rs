@@ -0,0 +1,941 @@
+use ast::node::*;
+use ast::visitor::NodeVisitor;
+use lexer::token::Token;
+use lexer::token::Token::*;
+use lexer::Span;
+use parser::Comments;
+use utils::number::{Number::Nil, NumberResult};
+
+/// The binding strength of each kind of expression, from relational
+/// operators, which bind least, to factors such as constants, variables
+/// and function calls, which need no parentheses.
+const RELATIONAL: u8 = 1;
+const ADDITIVE: u8 = 2;
+const MULTIPLICATIVE: u8 = 3;
+const UNARY: u8 = 4;
+const FACTOR: u8 = 5;
+
+fn operator_precedence(operator: &Token) -> u8 {
+  match operator {
+    Plus | Minus => ADDITIVE,
+    Multiply | IntegerDivision | RealDivision => MULTIPLICATIVE,
+    _ => RELATIONAL,
+  }
+}
+
+fn precedence(node: &Node) -> u8 {
+  if let Some(BinOpNode { operator, .. }) = node.downcast_ref::<BinOpNode>() {
+    operator_precedence(operator)
+  } else if node.is::<UnaryOpNode>() {
+    UNARY
+  } else {
+    FACTOR
+  }
+}
+
+/// Returns true if the statement ends with an IF statement that has no
+/// ELSE branch, which would take an ELSE written after it.
+fn ends_with_open_if(node: &Node) -> bool {
+  if let Some(IfNode { else_branch, .. }) = node.downcast_ref::<IfNode>() {
+    else_branch
+      .as_ref()
+      .is_none_or(|statement| ends_with_open_if(&**statement))
+  } else if let Some(WhileNode { body, .. }) = node.downcast_ref::<WhileNode>() {
+    ends_with_open_if(&**body)
+  } else if let Some(ForNode { body, .. }) = node.downcast_ref::<ForNode>() {
+    ends_with_open_if(&**body)
+  } else if let Some(LabelledStatementNode { statement, .. }) =
+    node.downcast_ref::<LabelledStatementNode>()
+  {
+    ends_with_open_if(&**statement)
+  } else {
+    false
+  }
+}
+
+/// Returns the name, type and span of a variable or parameter
+/// declaration. Variables declared together share a span.
+fn declared(node: &Node) -> (String, &TypeNode, Span) {
+  if let Some(node) = node.downcast_ref::<DeclarationNode>() {
+    (node.var_node.identifier.to_string(), &node.type_node, node.span)
+  } else if let Some(node) = node.downcast_ref::<ParameterNode>() {
+    (node.var_node.identifier.to_string(), &node.type_node, node.span)
+  } else {
+    panic!("Expected a variable or parameter declaration")
+  }
+}
+
+/// Splits variable or parameter declarations into those written
+/// together, as in `a, b: INTEGER`.
+fn declared_together(nodes: &[Box<Node>]) -> impl Iterator<Item = &[Box<Node>]> {
+  nodes.chunk_by(|a, b| declared(&**a).2 == declared(&**b).2)
+}
+
+/// Returns the names of variables declared together, as written.
+fn names(group: &[Box<Node>]) -> String {
+  let names: Vec<String> = group.iter().map(|node| declared(&**node).0).collect();
+  names.join(", ")
+}
+
+/// The statements of a statement sequence, which the parser keeps as a
+/// compound statement.
+fn statements(node: &Node) -> &[Box<Node>] {
+  match node.downcast_ref::<CompoundNode>() {
+    Some(compound) => &compound.children,
+    None => panic!("Expected a statement sequence"),
+  }
+}
+
+/// How reserved words are written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeywordCase {
+  Upper,
+  Lower,
+}
+
+/// Where the BEGIN of a compound statement controlled by another
+/// statement, such as WHILE or IF, is written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BeginPlacement {
+  /// At the end of the line of the controlling statement
+  SameLine,
+  /// On a line of its own, lined up with the controlling statement
+  NextLine,
+}
+
+/// The layout choices open to the printer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Style {
+  pub keyword_case: KeywordCase,
+  /// The number of spaces each level of nesting is indented by
+  pub indent_width: usize,
+  pub begin_placement: BeginPlacement,
+  /// Whether the colons of the variables declared in a VAR section
+  /// are lined up
+  pub align_colons: bool,
+}
+
+impl Default for Style {
+  fn default() -> Self {
+    Style {
+      keyword_case: KeywordCase::Upper,
+      indent_width: 2,
+      begin_placement: BeginPlacement::SameLine,
+      align_colons: false,
+    }
+  }
+}
+
+/// Turns a program back into Pascal source, laid out one statement and
+/// declaration to a line, with only the parentheses operator precedence
+/// requires. Parsing the source gives back the same program.
+pub struct Printer {
+  output: String,
+  indent: usize,
+  // The number of routines whose bodies enclose the current block
+  routines: usize,
+  style: Style,
+  // The comments of each statement, declaration, BEGIN and closing
+  // END, in the order the parser found them, and the number written so far
+  comments: Vec<Comments>,
+  anchors: usize,
+  // The comments within expressions, each with the end of the token it
+  // follows, and the number written so far
+  inline_comments: Vec<(usize, String)>,
+  inlined: usize,
+  // Comments to be written at the end of the current line
+  trailing: Vec<String>,
+  // The width the names in the current VAR section are padded to
+  name_width: usize,
+}
+
+impl Printer {
+  pub fn new() -> Self {
+    Printer {
+      output: String::new(),
+      indent: 0,
+      routines: 0,
+      style: Style::default(),
+      comments: vec![],
+      anchors: 0,
+      inline_comments: vec![],
+      inlined: 0,
+      trailing: vec![],
+      name_width: 0,
+    }
+  }
+  pub fn set_style(&mut self, style: Style) {
+    self.style = style;
+  }
+  /// Writes the comments the parser kept back in the places it found
+  /// them. The program printed must be the one they were parsed with.
+  pub fn set_comments(&mut self, comments: Vec<Comments>) {
+    self.comments = comments;
+  }
+  /// Writes the comments the parser found within expressions next to
+  /// the tokens they follow.
+  pub fn set_inline_comments(&mut self, comments: Vec<(usize, String)>) {
+    self.inline_comments = comments;
+  }
+  /// Returns the source of the program printed so far.
+  pub fn source(self) -> String {
+    self.output
+  }
+  /// Returns the text of a token, with reserved words in the case the
+  /// style asks for.
+  fn text(&self, token: &Token) -> String {
+    match token {
+      Id(_) | IntegerConst(_) | RealConst(_) | StringConst(_) => token.to_string(),
+      _ => self.word(&token.to_string()),
+    }
+  }
+  /// Returns a word in the case the style asks for reserved words.
+  fn word(&self, word: &str) -> String {
+    match self.style.keyword_case {
+      KeywordCase::Upper => word.to_uppercase(),
+      KeywordCase::Lower => word.to_lowercase(),
+    }
+  }
+  fn write<T: ToString>(&mut self, text: T) {
+    let text = text.to_string();
+    // Only the semicolon ending a statement goes before its comments
+    if text != Semi.to_string() {
+      self.flush_trailing();
+    }
+    self.output += &text;
+  }
+  /// Writes the comments waiting for the end of the line. A comment
+  /// running to the end of the line ends it, in which case this returns
+  /// true, having started a new line.
+  fn flush_trailing(&mut self) -> bool {
+    let trailing = self.trailing.split_off(0);
+    for comment in &trailing {
+      self.output.push(' ');
+      self.output += comment;
+    }
+    let line_ended = trailing
+      .last()
+      .is_some_and(|comment| comment.starts_with("//"));
+    if line_ended {
+      self.newline();
+    }
+    line_ended
+  }
+  /// Starts a new line at the current indentation.
+  fn newline(&mut self) {
+    if self.flush_trailing() {
+      return;
+    }
+    self.output.push('\n');
+    for _ in 0..self.indent * self.style.indent_width {
+      self.output.push(' ');
+    }
+  }
+  /// Leaves an empty line before the next line started.
+  fn blank_line(&mut self) {
+    self.flush_trailing();
+    let line = self.output.trim_end_matches(' ').len();
+    self.output.truncate(line);
+    self.output.push('\n');
+  }
+  /// Writes a token of an expression, with the comments written within
+  /// the expression before and after it. A comment following a token
+  /// not printed on its own, such as an operator, goes before the next.
+  fn token<T: ToString>(&mut self, span: Span, text: T) {
+    while let Some((after, comment)) = self.inline_comments.get(self.inlined).cloned() {
+      if after > span.start {
+        break;
+      }
+      self.inlined += 1;
+      self.write(&comment);
+      if comment.starts_with("//") {
+        self.newline();
+      } else {
+        self.write(' ');
+      }
+    }
+    self.write(text);
+    while let Some((after, comment)) = self.inline_comments.get(self.inlined).cloned() {
+      if after != span.end {
+        break;
+      }
+      self.inlined += 1;
+      self.trailing.push(comment);
+    }
+  }
+  /// Returns the comments of the next statement, declaration, BEGIN or
+  /// closing END.
+  fn next_comments(&mut self) -> Comments {
+    self.anchors += 1;
+    self
+      .comments
+      .get(self.anchors - 1)
+      .cloned()
+      .unwrap_or_default()
+  }
+  /// Writes the comments found before an END, each on its own line.
+  fn closing_comments(&mut self) {
+    let comments = self.next_comments();
+    self.indent += 1;
+    for comment in comments.leading {
+      self.newline();
+      self.write(comment);
+    }
+    self.indent -= 1;
+  }
+  /// Writes a statement, along with its comments.
+  fn statement(&mut self, node: &Node) {
+    let comments = self.next_comments();
+    for (i, comment) in comments.leading.iter().enumerate() {
+      if i > 0 {
+        self.newline();
+      }
+      self.write(comment);
+    }
+    if !comments.leading.is_empty() && !node.is::<NoOpNode>() {
+      self.newline();
+    }
+    self.visit(node).unwrap();
+    self.trailing.extend(comments.trailing);
+  }
+  /// Writes an operand, in parentheses if it binds less tightly than
+  /// the operator it belongs to.
+  fn operand(&mut self, node: &Node, precedence_needed: u8) {
+    if precedence(node) < precedence_needed {
+      self.write("(");
+      self.visit(node).unwrap();
+      self.write(")");
+    } else {
+      self.visit(node).unwrap();
+    }
+  }
+  fn list(&mut self, nodes: &[Box<Node>]) {
+    for (i, node) in nodes.iter().enumerate() {
+      if i > 0 {
+        self.write(", ");
+      }
+      self.visit(node).unwrap();
+    }
+  }
+  /// Returns true if the statement is empty and has no comments to be
+  /// written in its place.
+  fn is_blank(&self, node: &Node) -> bool {
+    node.is::<NoOpNode>()
+      && self
+        .comments
+        .get(self.anchors)
+        .is_none_or(|comments| comments.leading.is_empty())
+  }
+  /// Writes a sequence of statements, one to a line. An empty statement
+  /// ending the sequence is left as the semicolon before it.
+  fn statement_list(&mut self, nodes: &[Box<Node>]) {
+    for (i, node) in nodes.iter().enumerate() {
+      if i > 0 {
+        self.write(Semi);
+      }
+      if i + 1 < nodes.len() || !self.is_blank(&**node) {
+        self.newline();
+      }
+      self.statement(&**node);
+    }
+  }
+  /// Writes the statement controlled by WHILE, FOR, THEN, ELSE, ON or
+  /// a case label: a compound statement starts where the style places
+  /// BEGIN, any other statement is indented on the next line.
+  fn body(&mut self, node: &Node) {
+    if node.is::<CompoundNode>() {
+      self.begin_line();
+      self.statement(node);
+    } else if self.is_blank(node) {
+      self.statement(node);
+    } else {
+      self.indent += 1;
+      self.newline();
+      self.statement(node);
+      self.indent -= 1;
+    }
+  }
+  /// Moves to where the style places a BEGIN following another word.
+  fn begin_line(&mut self) {
+    match self.style.begin_placement {
+      BeginPlacement::SameLine => self.write(" "),
+      BeginPlacement::NextLine => self.newline(),
+    }
+  }
+  fn type_spec(&mut self, node: &TypeNode) {
+    match &node.kind {
+      TypeKind::Named(name) => {
+        let name = self.text(name);
+        self.write(name);
+      }
+      TypeKind::Subrange(low, high) => {
+        let range = format!("{}{}{}", self.text(low), Range, self.text(high));
+        self.write(range);
+      }
+      TypeKind::Array(index, element) => {
+        let array = format!("{}{}", self.text(&Array), LBracket);
+        self.write(array);
+        self.type_spec(index);
+        let of = format!("{} {} ", RBracket, self.text(&Of));
+        self.write(of);
+        self.type_spec(element);
+      }
+      TypeKind::Enumeration(names) => {
+        let names: Vec<String> = names.iter().map(Token::to_string).collect();
+        self.write(format!("({})", names.join(", ")));
+      }
+      TypeKind::Set(base) => {
+        let set = format!("{} {} ", self.text(&Set), self.text(&Of));
+        self.write(set);
+        self.type_spec(base);
+      }
+      TypeKind::Pointer(name) => {
+        let pointer = format!("{}{}", Caret, self.text(name));
+        self.write(pointer);
+      }
+      TypeKind::Record(fields) => {
+        let record = self.text(&Record);
+        self.write(record);
+        self.indent += 1;
+        // Fields declared together share a span, and are kept together
+        for group in fields.chunk_by(|a, b| a.2 == b.2) {
+          let names: Vec<String> = group.iter().map(|(name, _, _)| name.to_string()).collect();
+          self.newline();
+          self.write(format!("{}: ", names.join(", ")));
+          self.type_spec(&group[0].1);
+          self.write(Semi);
+        }
+        self.indent -= 1;
+        self.newline();
+        let end = self.text(&End);
+        self.write(end);
+      }
+    }
+  }
+  /// Writes the declarations of a block, each kind in its own section.
+  fn declarations(&mut self, declarations: &[Box<Node>]) {
+    // The reserved word starting each kind of declaration
+    let section = |node: &Node| {
+      if node.is::<LabelDeclarationNode>() {
+        Label
+      } else if node.is::<TypeDeclarationNode>() {
+        Type
+      } else if node.is::<DeclarationNode>() {
+        Var
+      } else {
+        Procedure
+      }
+    };
+    let mut start = 0;
+    while start < declarations.len() {
+      let kind = section(&*declarations[start]);
+      let length = declarations[start..]
+        .iter()
+        .take_while(|&node| section(&**node) == kind)
+        .count();
+      let group = &declarations[start..start + length];
+      if start > 0 {
+        self.blank_line();
+      }
+      match kind {
+        Label => {
+          let comments: Vec<Comments> = group.iter().map(|_| self.next_comments()).collect();
+          for comment in comments.iter().flat_map(|comments| &comments.leading) {
+            self.newline();
+            self.write(comment);
+          }
+          self.newline();
+          let label = format!("{} ", self.text(&Label));
+          self.write(label);
+          self.list(group);
+          self.write(Semi);
+          let trailing = comments.into_iter().flat_map(|comments| comments.trailing);
+          self.trailing.extend(trailing);
+        }
+        Type | Var => {
+          self.newline();
+          let word = self.text(&kind);
+          self.write(word);
+          self.indent += 1;
+          self.name_width = self.name_width(group);
+          let together: Vec<&[Box<Node>]> = if kind == Var {
+            declared_together(group).collect()
+          } else {
+            group.chunks(1).collect()
+          };
+          for nodes in together {
+            // Variables declared together share one line and its comments
+            let comments: Vec<Comments> = nodes.iter().map(|_| self.next_comments()).collect();
+            for comment in comments.iter().flat_map(|comments| &comments.leading) {
+              self.newline();
+              self.write(comment);
+            }
+            self.newline();
+            if kind == Var {
+              self.declaration(nodes);
+            } else {
+              self.visit(&nodes[0]).unwrap();
+            }
+            self.write(Semi);
+            let trailing = comments.into_iter().flat_map(|comments| comments.trailing);
+            self.trailing.extend(trailing);
+          }
+          self.name_width = 0;
+          self.indent -= 1;
+        }
+        _ => {
+          // Routines nested in another routine are indented under it
+          let nested = if self.routines > 0 { 1 } else { 0 };
+          self.indent += nested;
+          for (i, node) in group.iter().enumerate() {
+            if i > 0 {
+              self.blank_line();
+            }
+            self.visit(node).unwrap();
+          }
+          self.indent -= nested;
+        }
+      }
+      start += length;
+    }
+    if !declarations.is_empty() {
+      self.blank_line();
+    }
+  }
+  /// Returns the width the names of the variables declared with the
+  /// given declaration are padded to, if the style lines up their colons.
+  fn name_width(&self, declarations: &[Box<Node>]) -> usize {
+    if !self.style.align_colons || !declarations[0].is::<DeclarationNode>() {
+      return 0;
+    }
+    declared_together(declarations)
+      .map(|group| names(group).chars().count())
+      .max()
+      .unwrap_or(0)
+  }
+  /// Writes variables or parameters declared together, with their names
+  /// padded to the width of the current VAR section.
+  fn declaration(&mut self, group: &[Box<Node>]) {
+    let names = names(group);
+    self.write(format!("{:width$}: ", names, width = self.name_width));
+    self.type_spec(declared(&*group[0]).1);
+  }
+}
+
+impl NodeVisitor for Printer {
+  fn visit_program(&mut self, node: &ProgramNode) -> NumberResult {
+    let comments = self.next_comments();
+    for comment in comments.leading {
+      self.write(comment);
+      self.newline();
+    }
+    let program = self.text(&Program);
+    self.write(format!("{} {}{}", program, node.identifier, Semi));
+    self.trailing.extend(comments.trailing);
+    self.blank_line();
+    self.visit(&node.block)?;
+    let comments = self.next_comments();
+    self.trailing.extend(comments.leading);
+    self.write(Period);
+    self.trailing.extend(comments.trailing);
+    let comments = self.next_comments();
+    for comment in comments.leading {
+      self.newline();
+      self.write(comment);
+    }
+    self.flush_trailing();
+    self.output.push('\n');
+    Ok(Nil)
+  }
+  fn visit_procedure(&mut self, node: &ProcedureNode) -> NumberResult {
+    let kind = if node.return_type.is_some() {
+      Function
+    } else {
+      Procedure
+    };
+    let comments = self.next_comments();
+    for comment in comments.leading {
+      self.newline();
+      self.write(comment);
+    }
+    self.newline();
+    let heading = format!("{} {}", self.text(&kind), node.proc_name);
+    self.write(heading);
+    if !node.params.is_empty() {
+      self.write(LParen);
+      for (i, group) in declared_together(&node.params).enumerate() {
+        if i > 0 {
+          self.write("; ");
+        }
+        self.declaration(group);
+      }
+      self.write(RParen);
+    }
+    if let Some(return_type) = &node.return_type {
+      self.write(": ");
+      self.type_spec(return_type);
+    }
+    self.write(Semi);
+    self.trailing.extend(comments.trailing);
+    match &node.block {
+      Some(block) => {
+        self.routines += 1;
+        self.visit(&***block)?;
+        self.routines -= 1;
+      }
+      None => {
+        let forward = format!(" {}", self.text(&Forward));
+        self.write(forward);
+      }
+    }
+    let comments = self.next_comments();
+    self.trailing.extend(comments.leading);
+    self.write(Semi);
+    self.trailing.extend(comments.trailing);
+    Ok(Nil)
+  }
+  fn visit_parameter(&mut self, node: &ParameterNode) -> NumberResult {
+    self.write(format!("{}: ", node.var_node.identifier));
+    self.type_spec(&node.type_node);
+    Ok(Nil)
+  }
+  fn visit_block(&mut self, node: &BlockNode) -> NumberResult {
+    self.declarations(&node.declarations);
+    self.newline();
+    self.visit(&node.compound_statement)
+  }
+  fn visit_declaration(&mut self, node: &DeclarationNode) -> NumberResult {
+    self.write(format!(
+      "{:width$}: ",
+      node.var_node.identifier.to_string(),
+      width = self.name_width
+    ));
+    self.type_spec(&node.type_node);
+    Ok(Nil)
+  }
+  fn visit_type_declaration(&mut self, node: &TypeDeclarationNode) -> NumberResult {
+    self.write(format!("{} {} ", node.type_name, Equal));
+    self.type_spec(&node.type_node);
+    Ok(Nil)
+  }
+  fn visit_type(&mut self, node: &TypeNode) -> NumberResult {
+    self.type_spec(node);
+    Ok(Nil)
+  }
+  fn visit_integer(&mut self, node: &IntegerNumNode) -> NumberResult {
+    self.token(node.span, &node.spelling);
+    Ok(Nil)
+  }
+  fn visit_real(&mut self, node: &RealNumNode) -> NumberResult {
+    self.token(node.span, &node.spelling);
+    Ok(Nil)
+  }
+  fn visit_char(&mut self, node: &CharNode) -> NumberResult {
+    self.token(node.span, StringConst(node.value.to_string()));
+    Ok(Nil)
+  }
+  fn visit_string(&mut self, node: &StringNode) -> NumberResult {
+    self.token(node.span, StringConst(node.value.clone()));
+    Ok(Nil)
+  }
+  fn visit_boolean(&mut self, node: &BooleanNode) -> NumberResult {
+    let value = self.text(if node.value { &True } else { &False });
+    self.token(node.span, value);
+    Ok(Nil)
+  }
+  fn visit_binop(&mut self, node: &BinOpNode) -> NumberResult {
+    let precedence = operator_precedence(&node.operator);
+    // Operators of the same precedence group to the left, except the
+    // relational operators, which don't group at all
+    let left_precedence = if precedence == RELATIONAL {
+      precedence + 1
+    } else {
+      precedence
+    };
+    self.operand(&*node.left, left_precedence);
+    let operator = format!(" {} ", self.text(&node.operator));
+    self.write(operator);
+    self.operand(&*node.right, precedence + 1);
+    Ok(Nil)
+  }
+  fn visit_unaryop(&mut self, node: &UnaryOpNode) -> NumberResult {
+    self.write(&node.operator);
+    self.operand(&*node.expr, UNARY);
+    Ok(Nil)
+  }
+  fn visit_compound(&mut self, node: &CompoundNode) -> NumberResult {
+    let comments = self.next_comments();
+    for comment in comments.leading {
+      self.write(comment);
+      self.newline();
+    }
+    let begin = self.text(&Begin);
+    self.write(begin);
+    self.trailing.extend(comments.trailing);
+    self.indent += 1;
+    self.statement_list(&node.children);
+    self.indent -= 1;
+    self.closing_comments();
+    self.newline();
+    let end = self.text(&End);
+    self.write(end);
+    Ok(Nil)
+  }
+  fn visit_assign(&mut self, node: &AssignNode) -> NumberResult {
+    self.visit(&node.identifier)?;
+    self.write(format!(" {} ", node.operator));
+    self.visit(&node.expr)
+  }
+  fn visit_nil(&mut self, node: &NilNode) -> NumberResult {
+    let nil = self.text(&Token::Nil);
+    self.token(node.span, nil);
+    Ok(Nil)
+  }
+  fn visit_deref(&mut self, node: &DerefNode) -> NumberResult {
+    self.visit(&node.pointer)?;
+    self.write(Caret);
+    Ok(Nil)
+  }
+  fn visit_field(&mut self, node: &FieldNode) -> NumberResult {
+    self.visit(&node.record)?;
+    self.write(format!("{}{}", Period, node.field));
+    Ok(Nil)
+  }
+  fn visit_while(&mut self, node: &WhileNode) -> NumberResult {
+    let keyword = format!("{} ", self.text(&While));
+    self.write(keyword);
+    self.visit(&node.condition)?;
+    let keyword = format!(" {}", self.text(&Do));
+    self.write(keyword);
+    self.body(&*node.body);
+    Ok(Nil)
+  }
+  fn visit_label_declaration(&mut self, node: &LabelDeclarationNode) -> NumberResult {
+    self.write(&node.label);
+    Ok(Nil)
+  }
+  fn visit_labelled_statement(&mut self, node: &LabelledStatementNode) -> NumberResult {
+    self.write(format!("{}{}", node.label, Colon));
+    if !self.is_blank(&*node.statement) {
+      self.write(" ");
+    }
+    self.statement(&*node.statement);
+    Ok(Nil)
+  }
+  fn visit_goto(&mut self, node: &GotoNode) -> NumberResult {
+    let goto = format!("{} {}", self.text(&Goto), node.label);
+    self.write(goto);
+    Ok(Nil)
+  }
+  fn visit_try_except(&mut self, node: &TryExceptNode) -> NumberResult {
+    let keyword = self.text(&Try);
+    self.write(keyword);
+    self.indent += 1;
+    self.statement_list(statements(&*node.body));
+    self.indent -= 1;
+    self.newline();
+    let keyword = self.text(&Except);
+    self.write(keyword);
+    self.indent += 1;
+    for handler in &node.handlers {
+      self.newline();
+      // ON is not a reserved word, but is written like one
+      let on = format!("{} ", self.word("ON"));
+      self.write(on);
+      if let Some(variable) = &handler.variable {
+        self.write(format!("{}: ", variable));
+      }
+      let class = format!("{} {}", handler.class, self.text(&Do));
+      self.write(class);
+      self.body(&*handler.statement);
+      self.write(Semi);
+    }
+    self.indent -= 1;
+    if let Some(else_branch) = &node.else_branch {
+      if !node.handlers.is_empty() {
+        self.newline();
+        let keyword = self.text(&Else);
+        self.write(keyword);
+      }
+      self.indent += 1;
+      self.statement_list(statements(&**else_branch));
+      self.indent -= 1;
+    }
+    self.closing_comments();
+    self.newline();
+    let end = self.text(&End);
+    self.write(end);
+    Ok(Nil)
+  }
+  fn visit_try_finally(&mut self, node: &TryFinallyNode) -> NumberResult {
+    let keyword = self.text(&Try);
+    self.write(keyword);
+    self.indent += 1;
+    self.statement_list(statements(&*node.body));
+    self.indent -= 1;
+    self.newline();
+    let keyword = self.text(&Finally);
+    self.write(keyword);
+    self.indent += 1;
+    self.statement_list(statements(&*node.finally));
+    self.indent -= 1;
+    self.closing_comments();
+    self.newline();
+    let end = self.text(&End);
+    self.write(end);
+    Ok(Nil)
+  }
+  fn visit_raise(&mut self, node: &RaiseNode) -> NumberResult {
+    let keyword = self.text(&Raise);
+    self.write(keyword);
+    if let Some(exception) = &node.exception {
+      self.write(" ");
+      self.visit(exception)?;
+    }
+    Ok(Nil)
+  }
+  fn visit_exception(&mut self, node: &ExceptionNode) -> NumberResult {
+    self.write(format!("{}.Create(", node.class));
+    self.visit(&node.message)?;
+    self.write(RParen);
+    Ok(Nil)
+  }
+  fn visit_set(&mut self, node: &SetNode) -> NumberResult {
+    self.write(LBracket);
+    for (i, (low, high)) in node.members.iter().enumerate() {
+      if i > 0 {
+        self.write(", ");
+      }
+      self.visit(low)?;
+      if let Some(high) = high {
+        self.write(Range);
+        self.visit(high)?;
+      }
+    }
+    self.write(RBracket);
+    Ok(Nil)
+  }
+  fn visit_if(&mut self, node: &IfNode) -> NumberResult {
+    let keyword = format!("{} ", self.text(&If));
+    self.write(keyword);
+    self.visit(&node.condition)?;
+    let keyword = format!(" {}", self.text(&Then));
+    self.write(keyword);
+    // An ELSE after an open IF would belong to it instead
+    let wrap = node.else_branch.is_some() && ends_with_open_if(&*node.then_branch);
+    if wrap {
+      self.begin_line();
+      let begin = self.text(&Begin);
+      self.write(begin);
+      self.indent += 1;
+      self.newline();
+      self.statement(&*node.then_branch);
+      self.indent -= 1;
+      self.newline();
+      let end = self.text(&End);
+      self.write(end);
+    } else {
+      self.body(&*node.then_branch);
+    }
+    if let Some(else_branch) = &node.else_branch {
+      let after_end = wrap || node.then_branch.is::<CompoundNode>();
+      if after_end && self.style.begin_placement == BeginPlacement::SameLine {
+        self.write(" ");
+      } else {
+        self.newline();
+      }
+      let keyword = self.text(&Else);
+      self.write(keyword);
+      if else_branch.is::<IfNode>() {
+        self.write(" ");
+        self.statement(&**else_branch);
+      } else {
+        self.body(&**else_branch);
+      }
+    }
+    Ok(Nil)
+  }
+  fn visit_case(&mut self, node: &CaseNode) -> NumberResult {
+    let keyword = format!("{} ", self.text(&Case));
+    self.write(keyword);
+    self.visit(&node.selector)?;
+    let keyword = format!(" {}", self.text(&Of));
+    self.write(keyword);
+    self.indent += 1;
+    for branch in &node.branches {
+      self.newline();
+      let labels: Vec<String> = branch
+        .labels
+        .iter()
+        .map(|(low, high)| {
+          if low == high {
+            self.text(low)
+          } else {
+            format!("{}{}{}", self.text(low), Range, self.text(high))
+          }
+        })
+        .collect();
+      self.write(format!("{}{}", labels.join(", "), Colon));
+      self.body(&*branch.statement);
+      self.write(Semi);
+    }
+    self.indent -= 1;
+    if let Some(else_branch) = &node.else_branch {
+      self.newline();
+      let keyword = self.text(&Else);
+      self.write(keyword);
+      self.indent += 1;
+      self.statement_list(statements(&**else_branch));
+      self.indent -= 1;
+    }
+    self.closing_comments();
+    self.newline();
+    let end = self.text(&End);
+    self.write(end);
+    Ok(Nil)
+  }
+  fn visit_for(&mut self, node: &ForNode) -> NumberResult {
+    let keyword = format!("{} ", self.text(&For));
+    self.write(keyword);
+    self.write(format!("{} {} ", node.identifier.identifier, Assign));
+    self.visit(&node.start)?;
+    let direction = format!(" {} ", self.text(&node.direction));
+    self.write(direction);
+    self.visit(&node.end)?;
+    let keyword = format!(" {}", self.text(&Do));
+    self.write(keyword);
+    self.body(&*node.body);
+    Ok(Nil)
+  }
+  fn visit_var(&mut self, node: &VarNode) -> NumberResult {
+    self.token(node.span, &node.identifier);
+    Ok(Nil)
+  }
+  fn visit_index(&mut self, node: &IndexNode) -> NumberResult {
+    self.visit(&node.array)?;
+    self.write(LBracket);
+    self.visit(&node.index)?;
+    self.write(RBracket);
+    Ok(Nil)
+  }
+  fn visit_function_call(&mut self, node: &FunctionCallNode) -> NumberResult {
+    let name = Span {
+      end: node.span.start + node.func_name.to_string().len(),
+      ..node.span
+    };
+    self.token(name, &node.func_name);
+    self.write(LParen);
+    self.list(&node.args);
+    self.write(RParen);
+    Ok(Nil)
+  }
+  fn visit_procedure_call(&mut self, node: &ProcedureCallNode) -> NumberResult {
+    self.write(&node.proc_name);
+    if !node.args.is_empty() {
+      self.write(LParen);
+      self.list(&node.args);
+      self.write(RParen);
+    }
+    Ok(Nil)
+  }
+}
@@ -0,0 +1,614 @@
+use ast::node::*;
+use ast::visitor::NodeVisitor;
+use lexer::token::Token;
+use lexer::{integer_value, Span};
+use std::fmt::Write;
+use symbols::symbol::{Symbol, SymbolTable, Type};
+use utils::number::{Number::Nil, NumberResult};
+
+/// The notations a syntax tree can be written in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AstFormat {
+  /// An object for each node, holding its kind, its span and its fields
+  /// by name
+  Json,
+  /// A list for each node, holding its kind and then the values of its
+  /// fields, always in the same order, without spans
+  SExpr,
+}
+
+/// A node as it is written: its kind, which is the name of its type
+/// without the Node suffix, where it is in the source text, and its
+/// fields.
+struct Tree {
+  kind: &'static str,
+  span: Option<Span>,
+  fields: Vec<(&'static str, Value)>,
+}
+
+/// The value of a field.
+enum Value {
+  Tree(Tree),
+  List(Vec<Value>),
+  // An identifier, an operator or a reserved word
+  Name(String),
+  // The value of a string or character constant
+  Text(String),
+  Number(String),
+  Boolean(bool),
+  Null,
+}
+
+/// The value of a token kept by a node, such as a name, an operator or
+/// a constant case label.
+fn token(token: &Token) -> Value {
+  match token {
+    Token::IntegerConst(value) => Value::Number(integer_value(value).unwrap().to_string()),
+    Token::StringConst(value) => Value::Text(value.clone()),
+    Token::True => Value::Boolean(true),
+    Token::False => Value::Boolean(false),
+    token => Value::Name(token.to_string()),
+  }
+}
+
+fn type_tree(type_node: &TypeNode) -> Tree {
+  let (kind, fields) = match &type_node.kind {
+    TypeKind::Named(name) => ("NamedType", vec![("name", token(name))]),
+    TypeKind::Subrange(low, high) => (
+      "SubrangeType",
+      vec![("low", token(low)), ("high", token(high))],
+    ),
+    TypeKind::Array(index_type, element_type) => (
+      "ArrayType",
+      vec![
+        ("index_type", Value::Tree(type_tree(index_type))),
+        ("element_type", Value::Tree(type_tree(element_type))),
+      ],
+    ),
+    TypeKind::Enumeration(values) => (
+      "EnumeratedType",
+      vec![("values", Value::List(values.iter().map(token).collect()))],
+    ),
+    TypeKind::Set(base_type) => (
+      "SetType",
+      vec![("base_type", Value::Tree(type_tree(base_type)))],
+    ),
+    TypeKind::Pointer(target) => ("PointerType", vec![("target", token(target))]),
+    TypeKind::Record(fields) => {
+      let fields = fields
+        .iter()
+        .map(|(name, type_node, span)| {
+          Value::Tree(Tree {
+            kind: "RecordField",
+            span: Some(*span),
+            fields: vec![
+              ("name", token(name)),
+              ("type_node", Value::Tree(type_tree(type_node))),
+            ],
+          })
+        })
+        .collect();
+      ("RecordType", vec![("fields", Value::List(fields))])
+    }
+  };
+  Tree {
+    kind,
+    span: Some(type_node.span),
+    fields,
+  }
+}
+
+fn json_string(output: &mut String, text: &str) {
+  output.push('"');
+  for c in text.chars() {
+    match c {
+      '"' => output.push_str("\\\""),
+      '\\' => output.push_str("\\\\"),
+      '\n' => output.push_str("\\n"),
+      '\r' => output.push_str("\\r"),
+      '\t' => output.push_str("\\t"),
+      c if (c as u32) < 0x20 => write!(output, "\\u{:04x}", c as u32).unwrap(),
+      c => output.push(c),
+    }
+  }
+  output.push('"');
+}
+
+impl Value {
+  fn json(&self, output: &mut String) {
+    match self {
+      Value::Tree(tree) => tree.json(output),
+      Value::List(values) => {
+        output.push('[');
+        for (i, value) in values.iter().enumerate() {
+          if i > 0 {
+            output.push(',');
+          }
+          value.json(output);
+        }
+        output.push(']');
+      }
+      Value::Name(text) | Value::Text(text) => json_string(output, text),
+      Value::Number(value) => output.push_str(value),
+      Value::Boolean(value) => write!(output, "{}", value).unwrap(),
+      Value::Null => output.push_str("null"),
+    }
+  }
+  fn sexpr(&self, output: &mut String) {
+    match self {
+      Value::Tree(tree) => tree.sexpr(output),
+      Value::List(values) => {
+        output.push('(');
+        for (i, value) in values.iter().enumerate() {
+          if i > 0 {
+            output.push(' ');
+          }
+          value.sexpr(output);
+        }
+        output.push(')');
+      }
+      Value::Name(text) | Value::Number(text) => output.push_str(text),
+      Value::Text(text) => write!(
+        output,
+        "\"{}\"",
+        text.replace('\\', "\\\\").replace('"', "\\\"")
+      )
+      .unwrap(),
+      Value::Boolean(value) => write!(output, "{}", value).unwrap(),
+      Value::Null => output.push_str("nil"),
+    }
+  }
+}
+
+impl Tree {
+  fn json(&self, output: &mut String) {
+    output.push_str("{\"kind\":");
+    json_string(output, self.kind);
+    if let Some(span) = self.span {
+      write!(
+        output,
+        ",\"span\":{{\"start\":{},\"end\":{}}}",
+        span.start, span.end
+      )
+      .unwrap();
+    }
+    for (name, value) in &self.fields {
+      output.push(',');
+      json_string(output, name);
+      output.push(':');
+      value.json(output);
+    }
+    output.push('}');
+  }
+  fn sexpr(&self, output: &mut String) {
+    output.push('(');
+    output.push_str(self.kind);
+    for (_, value) in &self.fields {
+      output.push(' ');
+      value.sexpr(output);
+    }
+    output.push(')');
+  }
+}
+
+fn params(params: &[(String, Type)]) -> Value {
+  Value::List(
+    params
+      .iter()
+      .map(|(name, kind)| {
+        Value::Tree(Tree {
+          kind: "Param",
+          span: None,
+          fields: vec![
+            ("name", Value::Name(name.clone())),
+            ("type", Value::Name(kind.to_string())),
+          ],
+        })
+      })
+      .collect(),
+  )
+}
+
+/// A symbol, whose kind is the name of its variant without the Symbol
+/// suffix.
+fn symbol_tree(symbol: &Symbol) -> Tree {
+  let name = Value::Name(symbol.name());
+  let (kind, fields) = match symbol {
+    Symbol::BuiltInSymbol(_) => ("BuiltIn", vec![("name", name)]),
+    Symbol::VarSymbol(_, kind) => (
+      "Var",
+      vec![("name", name), ("type", Value::Name(kind.to_string()))],
+    ),
+    Symbol::ConstSymbol(_, kind) => (
+      "Const",
+      vec![("name", name), ("type", Value::Name(kind.to_string()))],
+    ),
+    Symbol::TypeSymbol(_, kind) => (
+      "Type",
+      vec![("name", name), ("type", Value::Name(kind.to_string()))],
+    ),
+    Symbol::ProcedureSymbol(_, parameters) => (
+      "Procedure",
+      vec![("name", name), ("params", params(parameters))],
+    ),
+    Symbol::FunctionSymbol(_, parameters, result) => (
+      "Function",
+      vec![
+        ("name", name),
+        ("params", params(parameters)),
+        ("type", Value::Name(result.to_string())),
+      ],
+    ),
+    Symbol::BuiltInFunctionSymbol(_) => ("BuiltInFunction", vec![("name", name)]),
+    Symbol::BuiltInProcedureSymbol(_) => ("BuiltInProcedure", vec![("name", name)]),
+  };
+  Tree {
+    kind,
+    span: None,
+    fields,
+  }
+}
+
+/// Writes a syntax tree out in full, for tools outside Rust to inspect.
+/// In JSON each node has a span, the byte offsets into the source text
+/// from the start of its first token up to the end of its last. The
+/// S-expression form is kept compact, so it leaves spans out.
+///
+/// A tree of scopes can be written the same way, each scope holding its
+/// symbols in the order they were declared, then the scopes nested in it.
+pub struct Serializer {
+  // The node visited last
+  tree: Option<Tree>,
+}
+
+impl Serializer {
+  pub fn new() -> Self {
+    Serializer { tree: None }
+  }
+  /// Returns the tree visited, written in the given notation.
+  pub fn serialize(self, format: AstFormat) -> String {
+    let mut output = String::new();
+    let tree = self.tree.expect("No syntax tree has been visited");
+    match format {
+      AstFormat::Json => tree.json(&mut output),
+      AstFormat::SExpr => tree.sexpr(&mut output),
+    }
+    output
+  }
+  /// Visits a scope and the scopes nested in it.
+  pub fn visit_scope(&mut self, scope: &SymbolTable) {
+    let symbols = scope
+      .symbols()
+      .into_iter()
+      .map(|symbol| Value::Tree(symbol_tree(symbol)))
+      .collect();
+    let nested_scopes = scope
+      .nested_scopes()
+      .iter()
+      .map(|nested_scope| {
+        self.visit_scope(nested_scope);
+        Value::Tree(self.tree.take().unwrap())
+      })
+      .collect();
+    self.tree = Some(Tree {
+      kind: "Scope",
+      span: None,
+      fields: vec![
+        ("name", Value::Name(scope.scope_name().to_string())),
+        ("level", Value::Number(scope.scope_level().to_string())),
+        ("symbols", Value::List(symbols)),
+        ("scopes", Value::List(nested_scopes)),
+      ],
+    });
+  }
+  fn finish(
+    &mut self,
+    kind: &'static str,
+    span: Span,
+    fields: Vec<(&'static str, Value)>,
+  ) -> NumberResult {
+    self.tree = Some(Tree {
+      kind,
+      span: Some(span),
+      fields,
+    });
+    Ok(Nil)
+  }
+  fn node(&mut self, node: &Node) -> Value {
+    self.visit(node).unwrap();
+    Value::Tree(self.tree.take().unwrap())
+  }
+  fn optional(&mut self, node: &Option<Box<Node>>) -> Value {
+    match node {
+      Some(node) => self.node(&**node),
+      None => Value::Null,
+    }
+  }
+  fn list(&mut self, nodes: &[Box<Node>]) -> Value {
+    Value::List(nodes.iter().map(|node| self.node(&**node)).collect())
+  }
+  fn var(&mut self, node: &VarNode) -> Value {
+    self.visit_var(node).unwrap();
+    Value::Tree(self.tree.take().unwrap())
+  }
+}
+
+impl NodeVisitor for Serializer {
+  fn visit_program(&mut self, node: &ProgramNode) -> NumberResult {
+    let fields = vec![
+      ("identifier", token(&node.identifier)),
+      ("block", self.node(&*node.block)),
+    ];
+    self.finish("Program", node.span, fields)
+  }
+  fn visit_procedure(&mut self, node: &ProcedureNode) -> NumberResult {
+    let return_type = match &node.return_type {
+      Some(type_node) => Value::Tree(type_tree(type_node)),
+      None => Value::Null,
+    };
+    let block = match &node.block {
+      Some(block) => self.node(&***block),
+      None => Value::Null,
+    };
+    let fields = vec![
+      ("proc_name", token(&node.proc_name)),
+      ("params", self.list(&node.params)),
+      ("return_type", return_type),
+      ("block", block),
+    ];
+    self.finish("Procedure", node.span, fields)
+  }
+  fn visit_parameter(&mut self, node: &ParameterNode) -> NumberResult {
+    let fields = vec![
+      ("var_node", self.var(&node.var_node)),
+      ("type_node", Value::Tree(type_tree(&node.type_node))),
+    ];
+    self.finish("Parameter", node.span, fields)
+  }
+  fn visit_block(&mut self, node: &BlockNode) -> NumberResult {
+    let fields = vec![
+      ("declarations", self.list(&node.declarations)),
+      ("compound_statement", self.node(&*node.compound_statement)),
+    ];
+    self.finish("Block", node.span, fields)
+  }
+  fn visit_declaration(&mut self, node: &DeclarationNode) -> NumberResult {
+    let fields = vec![
+      ("var_node", self.var(&node.var_node)),
+      ("type_node", Value::Tree(type_tree(&node.type_node))),
+    ];
+    self.finish("Declaration", node.span, fields)
+  }
+  fn visit_type_declaration(&mut self, node: &TypeDeclarationNode) -> NumberResult {
+    let fields = vec![
+      ("type_name", token(&node.type_name)),
+      ("type_node", Value::Tree(type_tree(&node.type_node))),
+    ];
+    self.finish("TypeDeclaration", node.span, fields)
+  }
+  fn visit_type(&mut self, node: &TypeNode) -> NumberResult {
+    self.tree = Some(type_tree(node));
+    Ok(Nil)
+  }
+  fn visit_integer(&mut self, node: &IntegerNumNode) -> NumberResult {
+    let fields = vec![("value", Value::Number(node.value.to_string()))];
+    self.finish("IntegerNum", node.span, fields)
+  }
+  fn visit_real(&mut self, node: &RealNumNode) -> NumberResult {
+    // JSON has no way to write an infinite number
+    let value = if node.value.is_finite() {
+      Value::Number(format!("{:?}", node.value))
+    } else {
+      Value::Name(node.value.to_string())
+    };
+    self.finish("RealNum", node.span, vec![("value", value)])
+  }
+  fn visit_char(&mut self, node: &CharNode) -> NumberResult {
+    let fields = vec![("value", Value::Text(node.value.to_string()))];
+    self.finish("Char", node.span, fields)
+  }
+  fn visit_string(&mut self, node: &StringNode) -> NumberResult {
+    let fields = vec![("value", Value::Text(node.value.clone()))];
+    self.finish("String", node.span, fields)
+  }
+  fn visit_boolean(&mut self, node: &BooleanNode) -> NumberResult {
+    let fields = vec![("value", Value::Boolean(node.value))];
+    self.finish("Boolean", node.span, fields)
+  }
+  fn visit_binop(&mut self, node: &BinOpNode) -> NumberResult {
+    let fields = vec![
+      ("operator", token(&node.operator)),
+      ("left", self.node(&*node.left)),
+      ("right", self.node(&*node.right)),
+    ];
+    self.finish("BinOp", node.span, fields)
+  }
+  fn visit_unaryop(&mut self, node: &UnaryOpNode) -> NumberResult {
+    let fields = vec![
+      ("operator", token(&node.operator)),
+      ("expr", self.node(&*node.expr)),
+    ];
+    self.finish("UnaryOp", node.span, fields)
+  }
+  fn visit_compound(&mut self, node: &CompoundNode) -> NumberResult {
+    let fields = vec![("children", self.list(&node.children))];
+    self.finish("Compound", node.span, fields)
+  }
+  fn visit_assign(&mut self, node: &AssignNode) -> NumberResult {
+    let fields = vec![
+      ("identifier", self.node(&*node.identifier)),
+      ("expr", self.node(&*node.expr)),
+    ];
+    // The operator is always :=
+    self.finish("Assign", node.span, fields)
+  }
+  fn visit_nil(&mut self, node: &NilNode) -> NumberResult {
+    self.finish("Nil", node.span, vec![])
+  }
+  fn visit_deref(&mut self, node: &DerefNode) -> NumberResult {
+    let fields = vec![("pointer", self.node(&*node.pointer))];
+    self.finish("Deref", node.span, fields)
+  }
+  fn visit_field(&mut self, node: &FieldNode) -> NumberResult {
+    let fields = vec![
+      ("record", self.node(&*node.record)),
+      ("field", token(&node.field)),
+    ];
+    self.finish("Field", node.span, fields)
+  }
+  fn visit_while(&mut self, node: &WhileNode) -> NumberResult {
+    let fields = vec![
+      ("condition", self.node(&*node.condition)),
+      ("body", self.node(&*node.body)),
+    ];
+    self.finish("While", node.span, fields)
+  }
+  fn visit_label_declaration(&mut self, node: &LabelDeclarationNode) -> NumberResult {
+    let fields = vec![("label", token(&node.label))];
+    self.finish("LabelDeclaration", node.span, fields)
+  }
+  fn visit_labelled_statement(&mut self, node: &LabelledStatementNode) -> NumberResult {
+    let fields = vec![
+      ("label", token(&node.label)),
+      ("statement", self.node(&*node.statement)),
+    ];
+    self.finish("LabelledStatement", node.span, fields)
+  }
+  fn visit_goto(&mut self, node: &GotoNode) -> NumberResult {
+    let fields = vec![("label", token(&node.label))];
+    self.finish("Goto", node.span, fields)
+  }
+  fn visit_try_except(&mut self, node: &TryExceptNode) -> NumberResult {
+    let mut handlers = vec![];
+    for handler in &node.handlers {
+      let variable = match &handler.variable {
+        Some(variable) => token(variable),
+        None => Value::Null,
+      };
+      let fields = vec![
+        ("variable", variable),
+        ("class", token(&handler.class)),
+        ("statement", self.node(&*handler.statement)),
+      ];
+      handlers.push(Value::Tree(Tree {
+        kind: "ExceptionHandler",
+        span: Some(handler.span),
+        fields,
+      }));
+    }
+    let fields = vec![
+      ("body", self.node(&*node.body)),
+      ("handlers", Value::List(handlers)),
+      ("else_branch", self.optional(&node.else_branch)),
+    ];
+    self.finish("TryExcept", node.span, fields)
+  }
+  fn visit_try_finally(&mut self, node: &TryFinallyNode) -> NumberResult {
+    let fields = vec![
+      ("body", self.node(&*node.body)),
+      ("finally", self.node(&*node.finally)),
+    ];
+    self.finish("TryFinally", node.span, fields)
+  }
+  fn visit_raise(&mut self, node: &RaiseNode) -> NumberResult {
+    let fields = vec![("exception", self.optional(&node.exception))];
+    self.finish("Raise", node.span, fields)
+  }
+  fn visit_exception(&mut self, node: &ExceptionNode) -> NumberResult {
+    let fields = vec![
+      ("class", token(&node.class)),
+      ("message", self.node(&*node.message)),
+    ];
+    self.finish("Exception", node.span, fields)
+  }
+  fn visit_set(&mut self, node: &SetNode) -> NumberResult {
+    let mut members = vec![];
+    for (low, high) in &node.members {
+      let fields = vec![("low", self.node(&**low)), ("high", self.optional(high))];
+      members.push(Value::Tree(Tree {
+        kind: "SetMember",
+        span: None,
+        fields,
+      }));
+    }
+    self.finish("Set", node.span, vec![("members", Value::List(members))])
+  }
+  fn visit_if(&mut self, node: &IfNode) -> NumberResult {
+    let fields = vec![
+      ("condition", self.node(&*node.condition)),
+      ("then_branch", self.node(&*node.then_branch)),
+      ("else_branch", self.optional(&node.else_branch)),
+    ];
+    self.finish("If", node.span, fields)
+  }
+  fn visit_case(&mut self, node: &CaseNode) -> NumberResult {
+    let mut branches = vec![];
+    for branch in &node.branches {
+      let labels = branch
+        .labels
+        .iter()
+        .map(|(low, high)| {
+          Value::Tree(Tree {
+            kind: "CaseLabel",
+            span: None,
+            fields: vec![("low", token(low)), ("high", token(high))],
+          })
+        })
+        .collect();
+      let fields = vec![
+        ("labels", Value::List(labels)),
+        ("statement", self.node(&*branch.statement)),
+      ];
+      branches.push(Value::Tree(Tree {
+        kind: "CaseBranch",
+        span: Some(branch.span),
+        fields,
+      }));
+    }
+    let fields = vec![
+      ("selector", self.node(&*node.selector)),
+      ("branches", Value::List(branches)),
+      ("else_branch", self.optional(&node.else_branch)),
+    ];
+    self.finish("Case", node.span, fields)
+  }
+  fn visit_for(&mut self, node: &ForNode) -> NumberResult {
+    let fields = vec![
+      ("identifier", self.var(&node.identifier)),
+      ("start", self.node(&*node.start)),
+      ("end", self.node(&*node.end)),
+      ("direction", token(&node.direction)),
+      ("body", self.node(&*node.body)),
+    ];
+    self.finish("For", node.span, fields)
+  }
+  fn visit_var(&mut self, node: &VarNode) -> NumberResult {
+    let fields = vec![("identifier", token(&node.identifier))];
+    self.finish("Var", node.span, fields)
+  }
+  fn visit_index(&mut self, node: &IndexNode) -> NumberResult {
+    let fields = vec![
+      ("array", self.node(&*node.array)),
+      ("index", self.node(&*node.index)),
+    ];
+    self.finish("Index", node.span, fields)
+  }
+  fn visit_function_call(&mut self, node: &FunctionCallNode) -> NumberResult {
+    let fields = vec![
+      ("func_name", token(&node.func_name)),
+      ("args", self.list(&node.args)),
+    ];
+    self.finish("FunctionCall", node.span, fields)
+  }
+  fn visit_procedure_call(&mut self, node: &ProcedureCallNode) -> NumberResult {
+    // The position the call is reported at is where its span starts
+    let fields = vec![
+      ("proc_name", token(&node.proc_name)),
+      ("args", self.list(&node.args)),
+    ];
+    self.finish("ProcedureCall", node.span, fields)
+  }
+  fn visit_noop(&mut self, node: &NoOpNode) -> NumberResult {
+    self.finish("NoOp", node.span, vec![])
+  }
+}
@@ -1,89 +1,863 @@
 use ast::node::*;
 use ast::visitor::NodeVisitor;
-use lexer::token::Token::Id;
-use symbols::symbol::{BuiltIn, Symbol::*, SymbolTable};
+use lexer::token::Token;
+use lexer::token::Token::*;
+use lexer::integer_value;
+use symbols::symbol::{BuiltIn, Symbol, Symbol::*, SymbolTable, Type};
+use utils::exception;
+use utils::names::same_name;
 use utils::number::{Number::Nil, NumberResult};
 
+use std::mem;
+
+/// The most values an array may hold, counting each element of the
+/// arrays and records it is made of, so that declaring one can't
+/// exhaust the memory of the interpreter.
+const MAX_ARRAY_SIZE: i64 = 1 << 20;
+
+/// The labels of the block being checked, and the GOTO statements
+/// that jump to them.
+#[derive(Clone, Default)]
+struct Labels {
+  // Each declared label, with the statement sequence it is set in
+  declared: Vec<(String, Option<usize>)>,
+  // The label of each GOTO, with the statement sequences enclosing it
+  gotos: Vec<(String, Vec<usize>)>,
+}
+
 #[derive(Clone)]
 pub struct TableBuilder {
-  global_scope: SymbolTable,
   current_scope: SymbolTable,
+  // Procedures declared FORWARD in the current block whose bodies
+  // have not been declared yet
+  forward_declarations: Vec<String>,
+  // The procedures and functions whose bodies enclose the current
+  // statement, innermost last
+  routines: Vec<Symbol>,
+  // The number of loops enclosing the current statement, within the
+  // innermost routine
+  loops: usize,
+  labels: Labels,
+  // The statement sequences enclosing the current statement within
+  // the current block, innermost last, and the number created so far
+  sequences: Vec<usize>,
+  sequence_count: usize,
+  // The number of exception handlers enclosing the current statement
+  handlers: usize,
+}
+
+fn simple(token: Token) -> Type {
+  Type::Simple(BuiltIn::new(token))
+}
+
+/// Returns true if a value of the second type may be passed where
+/// a value of the first type is expected.
+fn assignable(target: &Type, value: &Type) -> bool {
+  let (target, value) = (target.base(), value.base());
+  match (&target, &value) {
+    (Type::Pointer(Some(_)), Type::Pointer(None)) | (Type::Set(Some(_)), Type::Set(None)) => true,
+    _ => target == value || (target == simple(Real) && value == simple(Integer)),
+  }
+}
+
+/// Returns true if the heading of a routine's body repeats its FORWARD
+/// declaration, spelling the names of the parameters in any case.
+fn same_heading(forward: &Symbol, body: &Symbol) -> bool {
+  let same_params = |left: &Vec<(String, Type)>, right: &Vec<(String, Type)>| {
+    left.len() == right.len()
+      && left
+        .iter()
+        .zip(right)
+        .all(|((left, left_type), (right, right_type))| {
+          same_name(left, right) && left_type == right_type
+        })
+  };
+  match (forward, body) {
+    (ProcedureSymbol(_, left), ProcedureSymbol(_, right)) => same_params(left, right),
+    (FunctionSymbol(_, left, left_type), FunctionSymbol(_, right, right_type)) => {
+      same_params(left, right) && left_type == right_type
+    }
+    _ => false,
+  }
+}
+
+/// Returns the type of an exception, whose class name and message
+/// can be read like the fields of a record.
+fn exception_type() -> Type {
+  Type::Record(vec![
+    ("ClassName".to_string(), Type::Str),
+    ("Message".to_string(), Type::Str),
+  ])
+}
+
+/// Returns true if the node denotes a variable, rather than a value.
+fn is_variable(node: &Node) -> bool {
+  node.is::<VarNode>() || node.is::<IndexNode>() || node.is::<FieldNode>() || node.is::<DerefNode>()
 }
 
 impl TableBuilder {
   pub fn new() -> Self {
     TableBuilder {
-      global_scope: Default::default(),
       current_scope: Default::default(),
+      forward_declarations: vec![],
+      routines: vec![],
+      loops: 0,
+      labels: Default::default(),
+      sequences: vec![],
+      sequence_count: 0,
+      handlers: 0,
+    }
+  }
+  /// Returns the scope of the built-in symbols, holding the scopes of
+  /// the program checked, and the scopes nested in them, in turn.
+  pub fn into_outermost_scope(self) -> SymbolTable {
+    self.current_scope
+  }
+  /// Makes a global scope, nested inside the scope of the built-in
+  /// symbols, the current scope.
+  pub fn enter_global_scope(&mut self) {
+    let builtin_scope = SymbolTable::new("Built-in Scope", 0);
+    self.current_scope = SymbolTable::nested("Global Scope", builtin_scope);
+  }
+  pub fn current_scope(&self) -> &SymbolTable {
+    &self.current_scope
+  }
+  /// Declares a global variable whose value is given from outside the
+  /// program.
+  pub fn bind(&mut self, name: &str, kind: Type) -> Result<(), String> {
+    self.declare(VarSymbol(name.to_string(), kind))
+  }
+  /// Checks an expression, returning its type.
+  pub fn check_expression(&mut self, node: &Node) -> Result<Type, String> {
+    self.visit(node)?;
+    self.type_of(node)
+  }
+  /// Makes a new scope, nested inside the current one, the current scope.
+  fn enter_scope(&mut self, scope_name: &str) {
+    let enclosing_scope = mem::take(&mut self.current_scope);
+    self.current_scope = SymbolTable::nested(scope_name, enclosing_scope);
+  }
+  /// Discards the current scope, returning to the scope enclosing it.
+  fn leave_scope(&mut self) {
+    let scope = mem::take(&mut self.current_scope);
+    self.current_scope = scope.into_enclosing_scope().unwrap_or_default();
+  }
+  /// Adds a symbol to the current scope, rejecting any name already
+  /// declared in that scope.
+  fn declare(&mut self, symbol: Symbol) -> Result<(), String> {
+    let name = symbol.name();
+    if self.current_scope.lookup_local(&name).is_some() {
+      return Err(format!("Found duplicate declaration for '{}'!", name));
+    }
+    self.current_scope.insert(symbol);
+    Ok(())
+  }
+  /// Returns true if the current statement is inside the body of the
+  /// named function, so may set its result.
+  fn in_function(&self, name: &str) -> bool {
+    self
+      .routines
+      .iter()
+      .any(|routine| matches!(routine, FunctionSymbol(function, _, _) if same_name(function, name)))
+  }
+  /// Checks a statement that is part of a structured statement in a
+  /// new statement sequence, so that a label set within it can only be
+  /// reached by a GOTO within it.
+  fn visit_nested(&mut self, statement: &Node) -> NumberResult {
+    self.sequence_count += 1;
+    self.sequences.push(self.sequence_count);
+    let result = self.visit(statement);
+    self.sequences.pop();
+    result
+  }
+  /// Checks the statement of an exception handler, in a new scope
+  /// declaring the variable naming the exception, if any.
+  fn visit_handler(&mut self, variable: Option<&Token>, statement: &Node) -> NumberResult {
+    self.enter_scope("Exception Handler");
+    let mut result = Ok(Nil);
+    if let Some(variable) = variable {
+      result = self
+        .declare(VarSymbol(variable.to_string(), exception_type()))
+        .map(|_| Nil);
+    }
+    if result.is_ok() {
+      self.handlers += 1;
+      result = self.visit_nested(statement);
+      self.handlers -= 1;
     }
+    self.leave_scope();
+    result
+  }
+  /// Checks the labels of a block once all of its statements are known.
+  fn check_labels(&self) -> Result<(), String> {
+    let Labels { declared, gotos } = &self.labels;
+    for (label, enclosing) in gotos {
+      match declared.iter().find(|(name, _)| name == label) {
+        None => return Err(format!("Undeclared label {} found.", label)),
+        Some((_, Some(sequence))) if !enclosing.contains(sequence) => {
+          return Err(format!("GOTO {} jumps into a structured statement.", label))
+        }
+        _ => (),
+      }
+    }
+    for (label, sequence) in declared {
+      if sequence.is_none() {
+        return Err(format!(
+          "Label {} is declared but not set on any statement.",
+          label
+        ));
+      }
+      if !gotos.iter().any(|(name, _)| name == label) {
+        return Err(format!("Label {} is declared but never used.", label));
+      }
+    }
+    Ok(())
+  }
+  /// Converts a type specification into the type it denotes.
+  fn resolve_type(&self, type_node: &TypeNode) -> Result<Type, String> {
+    match &type_node.kind {
+      TypeKind::Named(token) => match self.current_scope.lookup(&token.to_string()) {
+        Some(BuiltInSymbol(builtin)) => Ok(Type::Simple(builtin)),
+        Some(TypeSymbol(_, kind)) => Ok(kind),
+        _ => Err(format!("Unknown type {} found.", token)),
+      },
+      TypeKind::Subrange(low, high) => {
+        let (low_type, low) = self.constant(low)?;
+        let (high_type, high) = self.constant(high)?;
+        if low_type != high_type {
+          return Err(format!(
+            "Subrange bounds {} and {} have different types.",
+            low_type, high_type
+          ));
+        }
+        if low > high {
+          return Err(format!("Subrange {}..{} is empty.", low, high));
+        }
+        Ok(Type::Subrange(Box::new(low_type), low, high))
+      }
+      TypeKind::Array(index, element) => {
+        let index = self.resolve_type(index)?;
+        let element = self.resolve_type(element)?;
+        let array = Type::Array(Box::new(index), Box::new(element));
+        if array.size() > MAX_ARRAY_SIZE {
+          return Err(format!(
+            "Array type {} has more than {} elements.",
+            array, MAX_ARRAY_SIZE
+          ));
+        }
+        Ok(array)
+      }
+      TypeKind::Enumeration(identifiers) => Ok(Type::Enumeration(
+        identifiers.iter().map(|id| id.to_string()).collect(),
+      )),
+      TypeKind::Set(base) => {
+        let base = self.resolve_type(base)?;
+        match base.bounds() {
+          Some((low, high)) if high as i64 - low as i64 <= 255 => {
+            Ok(Type::Set(Some(Box::new(base))))
+          }
+          Some(_) => Err(format!("SET base type {} has too many values.", base)),
+          None => Err(format!("SET base type {} is not an ordinal type.", base)),
+        }
+      }
+      // The type pointed to is looked up when the pointer is used, since
+      // it may be declared later in the same TYPE section
+      TypeKind::Pointer(name) => Ok(Type::Pointer(Some(name.to_string()))),
+      TypeKind::Record(fields) => {
+        let mut record: Vec<(String, Type)> = vec![];
+        for (name, type_node, _) in fields {
+          let name = name.to_string();
+          if record.iter().any(|(field, _)| same_name(field, &name)) {
+            return Err(format!("Found duplicate field '{}'!", name));
+          }
+          record.push((name, self.resolve_type(type_node)?));
+        }
+        Ok(Type::Record(record))
+      }
+    }
+  }
+  /// Returns the type and ordinal value of a constant.
+  fn constant(&self, token: &Token) -> Result<(Type, i32), String> {
+    match token {
+      IntegerConst(value) => integer_value(value)
+        .map(|value| (simple(Integer), value))
+        .ok_or_else(|| format!("Invalid integer constant {}.", value)),
+      StringConst(value) if value.chars().count() == 1 => {
+        Ok((simple(Char), value.chars().next().unwrap() as i32))
+      }
+      True => Ok((simple(Boolean), 1)),
+      False => Ok((simple(Boolean), 0)),
+      Id(name) => match self.current_scope.lookup(name) {
+        Some(ConstSymbol(_, Type::Enumeration(names))) => {
+          let ordinal = names
+            .iter()
+            .position(|value| same_name(value, name))
+            .unwrap();
+          Ok((Type::Enumeration(names), ordinal as i32))
+        }
+        _ => Err(format!("Undeclared constant {} found.", name)),
+      },
+      token => Err(format!("Invalid constant {} found.", token)),
+    }
+  }
+  /// Determines the static type of an expression.
+  fn type_of(&self, node: &Node) -> Result<Type, String> {
+    if node.is::<IntegerNumNode>() {
+      Ok(simple(Integer))
+    } else if node.is::<RealNumNode>() {
+      Ok(simple(Real))
+    } else if node.is::<CharNode>() {
+      Ok(simple(Char))
+    } else if node.is::<BooleanNode>() {
+      Ok(simple(Boolean))
+    } else if node.is::<StringNode>() {
+      Ok(Type::Str)
+    } else if node.is::<SetNode>() {
+      self.set_type(node.downcast_ref().unwrap())
+    } else if node.is::<NilNode>() {
+      Ok(Type::Pointer(None))
+    } else if node.is::<ExceptionNode>() {
+      Ok(exception_type())
+    } else if node.is::<DerefNode>() {
+      self.deref_type(node.downcast_ref().unwrap())
+    } else if node.is::<FieldNode>() {
+      self.field_type(node.downcast_ref().unwrap())
+    } else if node.is::<VarNode>() {
+      let VarNode { identifier, .. } = node.downcast_ref().unwrap();
+      match self.current_scope.lookup(&identifier.to_string()) {
+        Some(VarSymbol(_, kind)) | Some(ConstSymbol(_, kind)) => Ok(kind),
+        Some(TypeSymbol(_, kind)) => Err(format!("Type {} cannot be used as a value.", kind)),
+        // A function without parameters is called just by naming it
+        Some(FunctionSymbol(_, params, kind)) => {
+          self.check_arguments(identifier, &params, &[])?;
+          Ok(kind)
+        }
+        Some(ProcedureSymbol(name, _)) => {
+          Err(format!("Procedure {} does not return a value.", name))
+        }
+        _ => Err(format!("Undeclared variable {} found.", identifier)),
+      }
+    } else if node.is::<IndexNode>() {
+      self.element_type(node.downcast_ref().unwrap())
+    } else if node.is::<UnaryOpNode>() {
+      let UnaryOpNode { operator, expr, .. } = node.downcast_ref().unwrap();
+      let kind = self.type_of(&**expr)?;
+      if kind != simple(Integer) && kind != simple(Real) {
+        return Err(format!(
+          "Invalid operand type {} for unary {}",
+          kind, operator
+        ));
+      }
+      Ok(kind)
+    } else if node.is::<BinOpNode>() {
+      self.binop_type(node.downcast_ref().unwrap())
+    } else if node.is::<FunctionCallNode>() {
+      let FunctionCallNode {
+        func_name, args, ..
+      } = node.downcast_ref().unwrap();
+      self.function_type(func_name, args)
+    } else if node.is::<TypeNode>() {
+      Err(format!(
+        "Type {} cannot be used as a value.",
+        self.resolve_type(node.downcast_ref().unwrap())?
+      ))
+    } else {
+      Err(format!(
+        "Unable to determine the type of {}",
+        to_string(node)
+      ))
+    }
+  }
+  /// Checks the operands of a binary operator, returning the result type.
+  fn binop_type(&self, node: &BinOpNode) -> Result<Type, String> {
+    let BinOpNode {
+      left,
+      right,
+      operator,
+      ..
+    } = node;
+    let (left, right) = (
+      self.type_of(&**left)?.base(),
+      self.type_of(&**right)?.base(),
+    );
+    let numeric = |kind: &Type| *kind == simple(Integer) || *kind == simple(Real);
+    if *operator == In {
+      return match right {
+        Type::Set(None) if left.is_ordinal() => Ok(simple(Boolean)),
+        Type::Set(Some(ref base)) if base.base() == left => Ok(simple(Boolean)),
+        _ => Err(format!(
+          "Invalid operand types {} and {} for {}",
+          left, right, operator
+        )),
+      };
+    }
+    if let (Type::Pointer(ref left_name), Type::Pointer(ref right_name)) = (&left, &right) {
+      let compatible = match (left_name, right_name) {
+        (Some(left_name), Some(right_name)) => same_name(left_name, right_name),
+        _ => true,
+      };
+      return match operator {
+        Equal | NotEqual if compatible => Ok(simple(Boolean)),
+        _ => Err(format!(
+          "Invalid operand types {} and {} for {}",
+          left, right, operator
+        )),
+      };
+    }
+    if let (Type::Set(ref left_base), Type::Set(ref right_base)) = (&left, &right) {
+      let compatible = match (left_base, right_base) {
+        (Some(left_base), Some(right_base)) => left_base.base() == right_base.base(),
+        _ => true,
+      };
+      return match operator {
+        Plus | Minus | Multiply if compatible => {
+          Ok(Type::Set(left_base.clone().or_else(|| right_base.clone())))
+        }
+        Equal | NotEqual | LessEqual | GreaterEqual if compatible => Ok(simple(Boolean)),
+        _ => Err(format!(
+          "Invalid operand types {} and {} for {}",
+          left, right, operator
+        )),
+      };
+    }
+    if operator.is_relational() {
+      let comparable = (numeric(&left) && numeric(&right))
+        || (left.is_ordinal() && left == right)
+        || (left == Type::Str && right == Type::Str);
+      if !comparable {
+        return Err(format!(
+          "Cannot compare values of types {} and {}",
+          left, right
+        ));
+      }
+      return Ok(simple(Boolean));
+    }
+    if !numeric(&left) || !numeric(&right) {
+      return Err(format!(
+        "Invalid operand types {} and {} for {}",
+        left, right, operator
+      ));
+    }
+    if *operator == RealDivision || left == simple(Real) || right == simple(Real) {
+      Ok(simple(Real))
+    } else {
+      Ok(simple(Integer))
+    }
+  }
+  /// Checks the members of a set constructor, returning the set type.
+  fn set_type(&self, node: &SetNode) -> Result<Type, String> {
+    let mut base: Option<Type> = None;
+    for (low, high) in &node.members {
+      let mut kinds = vec![self.type_of(&**low)?];
+      if let Some(high) = high {
+        kinds.push(self.type_of(&**high)?);
+      }
+      for kind in kinds {
+        if !kind.is_ordinal() {
+          return Err(format!(
+            "Set members must be ordinal values, found {}.",
+            kind
+          ));
+        }
+        match base {
+          Some(ref base) if *base != kind.base() => {
+            return Err(format!(
+              "Set members of types {} and {} cannot be mixed.",
+              base, kind
+            ))
+          }
+          _ => base = Some(kind.base()),
+        }
+      }
+    }
+    Ok(Type::Set(base.map(Box::new)))
+  }
+  /// Checks a pointer dereference, returning the type pointed to.
+  fn deref_type(&self, node: &DerefNode) -> Result<Type, String> {
+    match self.type_of(&*node.pointer)? {
+      Type::Pointer(Some(name)) => self
+        .resolve_type(&TypeNode::named(Id(name.to_string())))
+        .map_err(|_| format!("Unknown type {} found for pointer.", name)),
+      kind => Err(format!("Cannot dereference a value of type {}.", kind)),
+    }
+  }
+  /// Checks a field selection, returning the type of the field.
+  fn field_type(&self, node: &FieldNode) -> Result<Type, String> {
+    match self.type_of(&*node.record)? {
+      Type::Record(fields) => {
+        let name = node.field.to_string();
+        fields
+          .into_iter()
+          .find(|(field, _)| same_name(field, &name))
+          .map(|(_, kind)| kind)
+          .ok_or_else(|| format!("Unknown field {} found.", name))
+      }
+      kind => Err(format!(
+        "Cannot select field {} from type {}.",
+        node.field, kind
+      )),
+    }
+  }
+  /// Checks an array index, returning the type of the indexed element.
+  fn element_type(&self, node: &IndexNode) -> Result<Type, String> {
+    match self.type_of(&*node.array)? {
+      Type::Array(index, element) => {
+        let kind = self.type_of(&*node.index)?;
+        if kind != index.base() {
+          return Err(format!(
+            "Invalid index type {}, expected {}.",
+            kind,
+            index.base()
+          ));
+        }
+        Ok(*element)
+      }
+      kind => Err(format!("Cannot index into a value of type {}.", kind)),
+    }
+  }
+  /// Returns the type named by an argument to Low or High, which may
+  /// be a type or a variable.
+  fn type_argument(&self, node: &Node) -> Result<Type, String> {
+    if node.is::<TypeNode>() {
+      return self.resolve_type(node.downcast_ref().unwrap());
+    }
+    if let Some(VarNode { identifier, .. }) = node.downcast_ref::<VarNode>() {
+      if let Some(TypeSymbol(_, kind)) = self.current_scope.lookup(&identifier.to_string()) {
+        return Ok(kind);
+      }
+    }
+    self.type_of(node)
+  }
+  /// Type checks a function call, returning its result type.
+  fn function_type(&self, func_name: &Token, args: &[Box<Node>]) -> Result<Type, String> {
+    // A user-defined function hides a standard one of the same name
+    match self.current_scope.lookup(&func_name.to_string()) {
+      Some(FunctionSymbol(_, params, kind)) => {
+        self.check_arguments(func_name, &params, args)?;
+        return Ok(kind);
+      }
+      Some(ProcedureSymbol(name, _)) => {
+        return Err(format!("Procedure {} does not return a value.", name))
+      }
+      _ => (),
+    }
+    // Standard functions are case-insensitive, like the reserved words.
+    let name = func_name.to_string().to_uppercase();
+    match self.current_scope.lookup(&name) {
+      Some(BuiltInFunctionSymbol(_)) => (),
+      _ => return Err(format!("Undeclared function {} found.", func_name)),
+    }
+    if args.len() != 1 {
+      return Err(format!(
+        "Function {} expects 1 argument, found {}.",
+        func_name,
+        args.len()
+      ));
+    }
+    let arg = if name == "LOW" || name == "HIGH" {
+      self.type_argument(&*args[0])?
+    } else {
+      self.type_of(&*args[0])?
+    };
+    let (integer, real) = (simple(Integer), simple(Real));
+    let result = match name.as_str() {
+      "ABS" | "SQR" if arg == integer || arg == real => Some(arg.clone()),
+      "SIN" | "COS" | "EXP" | "LN" | "SQRT" | "ARCTAN" if arg == integer || arg == real => {
+        Some(real)
+      }
+      "TRUNC" | "ROUND" if arg == integer || arg == real => Some(integer),
+      "ODD" if arg == integer => Some(simple(Boolean)),
+      "ORD" if arg.is_ordinal() => Some(integer),
+      "CHR" if arg == integer => Some(simple(Char)),
+      "SUCC" | "PRED" if arg.is_ordinal() => Some(arg.clone()),
+      "LOW" | "HIGH" => match arg {
+        Type::Array(ref index, _) => Some(index.base()),
+        ref kind if kind.is_ordinal() => Some(kind.base()),
+        _ => None,
+      },
+      _ => None,
+    };
+    result.ok_or_else(|| format!("Invalid argument type {} for function {}.", arg, func_name))
+  }
+  /// Type checks the arguments of a call to a user-defined procedure
+  /// or function.
+  fn check_arguments(
+    &self,
+    name: &Token,
+    params: &[(String, Type)],
+    args: &[Box<Node>],
+  ) -> Result<(), String> {
+    if args.len() != params.len() {
+      return Err(format!(
+        "{} expects {} argument(s), found {}.",
+        name,
+        params.len(),
+        args.len()
+      ));
+    }
+    for ((param, kind), arg) in params.iter().zip(args) {
+      let arg_type = self.type_of(&**arg)?;
+      if !assignable(kind, &arg_type) {
+        return Err(format!(
+          "Invalid argument type {} for parameter {} of {}.",
+          arg_type, param, name
+        ));
+      }
+    }
+    Ok(())
+  }
+  /// Type checks a procedure call.
+  fn check_procedure_call(&self, proc_name: &Token, args: &[Box<Node>]) -> Result<(), String> {
+    // A user-defined procedure hides a standard one of the same name
+    match self.current_scope.lookup(&proc_name.to_string()) {
+      Some(ProcedureSymbol(_, params)) => return self.check_arguments(proc_name, &params, args),
+      Some(FunctionSymbol(name, _, _)) => {
+        return Err(format!(
+          "Function {} cannot be called as a procedure.",
+          name
+        ))
+      }
+      _ => (),
+    }
+    let name = proc_name.to_string().to_uppercase();
+    match self.current_scope.lookup(&name) {
+      Some(BuiltInProcedureSymbol(_)) => (),
+      _ => return Err(format!("Undeclared procedure {} found.", proc_name)),
+    }
+    if name == "WRITE" || name == "WRITELN" {
+      for arg in args {
+        let kind = self.type_of(&**arg)?;
+        match kind {
+          Type::Array(_, _) | Type::Set(_) | Type::Pointer(_) | Type::Record(_) => {
+            return Err(format!(
+              "Cannot pass a value of type {} to procedure {}.",
+              kind, proc_name
+            ))
+          }
+          Type::Simple(_) | Type::Subrange(_, _, _) | Type::Enumeration(_) | Type::Str => (),
+        }
+      }
+      return Ok(());
+    }
+    if name == "BREAK" || name == "CONTINUE" {
+      if !args.is_empty() {
+        return Err(format!("Procedure {} takes no arguments.", proc_name));
+      }
+      if self.loops == 0 {
+        return Err(format!(
+          "Procedure {} can only be called inside a loop.",
+          proc_name
+        ));
+      }
+      return Ok(());
+    }
+    if name == "EXIT" {
+      // A function may give its result as the argument to EXIT
+      return match (args, self.routines.last()) {
+        ([], _) => Ok(()),
+        ([value], Some(FunctionSymbol(function, _, kind))) => {
+          let value_type = self.type_of(&**value)?;
+          if assignable(kind, &value_type) {
+            Ok(())
+          } else {
+            Err(format!(
+              "Invalid result type {} for function {}.",
+              value_type, function
+            ))
+          }
+        }
+        ([_], _) => Err(format!(
+          "Procedure {} can only return a value from a function.",
+          proc_name
+        )),
+        _ => Err(format!(
+          "Procedure {} expects at most 1 argument, found {}.",
+          proc_name,
+          args.len()
+        )),
+      };
+    }
+    if name == "HALT" {
+      return match args {
+        [] => Ok(()),
+        [code] if self.type_of(&**code)?.base() == simple(Integer) => Ok(()),
+        _ => Err(format!(
+          "Procedure {} expects an optional INTEGER exit code.",
+          proc_name
+        )),
+      };
+    }
+    if name == "NEW" || name == "DISPOSE" {
+      if args.len() != 1 || !is_variable(&*args[0]) {
+        return Err(format!(
+          "Procedure {} expects a single pointer variable.",
+          proc_name
+        ));
+      }
+      return match self.type_of(&*args[0])? {
+        Type::Pointer(Some(_)) => Ok(()),
+        kind => Err(format!(
+          "Invalid argument type {} for procedure {}.",
+          kind, proc_name
+        )),
+      };
+    }
+    if args.is_empty() || args.len() > 2 {
+      return Err(format!(
+        "Procedure {} expects 1 or 2 arguments, found {}.",
+        proc_name,
+        args.len()
+      ));
+    }
+    if !is_variable(&*args[0]) {
+      return Err(format!(
+        "Procedure {} requires a variable argument.",
+        proc_name
+      ));
+    }
+    let kind = self.type_of(&*args[0])?;
+    if !kind.is_ordinal() {
+      return Err(format!(
+        "Invalid argument type {} for procedure {}.",
+        kind, proc_name
+      ));
+    }
+    if args.len() == 2 && self.type_of(&*args[1])? != simple(Integer) {
+      return Err(format!(
+        "The increment passed to {} must be an INTEGER.",
+        proc_name
+      ));
+    }
+    Ok(())
   }
 }
 
 impl NodeVisitor for TableBuilder {
   fn visit_program(&mut self, node: &ProgramNode) -> NumberResult {
-    let global_scope = SymbolTable::new("Global Scope", 1);
-    self.global_scope = global_scope.clone();
-    self.current_scope = global_scope;
-
+    self.enter_global_scope();
     let result = self.visit(&node.block);
 
-    println!("{}", self.global_scope);
+    self.leave_scope();
     result
   }
   fn visit_procedure(&mut self, node: &ProcedureNode) -> NumberResult {
     let proc_name = node.proc_name.to_string();
-    let procedure_scope = SymbolTable::new(&proc_name, 2);
-    self.current_scope = procedure_scope;
 
-    let params: Vec<(String, BuiltIn)> = node
-      .params
+    let mut params: Vec<(String, Type)> = vec![];
+    for param in &node.params {
+      let ParameterNode {
+        var_node: VarNode { identifier, .. },
+        type_node,
+        ..
+      } = param.downcast_ref().unwrap();
+      params.push((identifier.to_string(), self.resolve_type(type_node)?));
+    }
+    let symbol = match node.return_type {
+      Some(ref return_type) => FunctionSymbol(
+        proc_name.clone(),
+        params.clone(),
+        self.resolve_type(return_type)?,
+      ),
+      None => ProcedureSymbol(proc_name.clone(), params.clone()),
+    };
+    let forward = self
+      .forward_declarations
       .iter()
-      .map(|boxed_node| boxed_node.downcast_ref().unwrap())
-      .map(
-        |ParameterNode {
-           var_node: VarNode { identifier },
-           type_node: TypeNode { token },
-         }| {
-          self.current_scope.insert(VarSymbol(
-            identifier.to_string(),
-            BuiltIn::new(token.clone()),
+      .position(|name| same_name(name, &proc_name));
+    match forward {
+      // The body of a routine declared FORWARD must repeat its heading
+      Some(index) if node.block.is_some() => {
+        let declared = self.current_scope.lookup_local(&proc_name);
+        if !declared.is_some_and(|declared| same_heading(&declared, &symbol)) {
+          return Err(format!(
+            "The heading of {} does not match its FORWARD declaration.",
+            proc_name
           ));
-          (identifier.to_string(), BuiltIn::new(token.clone()))
-        },
-      )
-      .collect();
-    let _procedure_symbol = ProcedureSymbol(proc_name, params);
-    println!("{}", self.current_scope);
+        }
+        self.forward_declarations.remove(index);
+      }
+      _ => self.declare(symbol.clone())?,
+    }
+    let block = match node.block {
+      Some(ref block) => block,
+      None => {
+        self.forward_declarations.push(proc_name);
+        return Ok(Nil);
+      }
+    };
 
-    self.visit(&node.block)
+    self.enter_scope(&proc_name);
+    let mut result = Ok(Nil);
+    for (name, kind) in params {
+      if let Err(msg) = self.declare(VarSymbol(name, kind)) {
+        result = Err(msg);
+      }
+    }
+    if result.is_ok() {
+      // Loops outside the routine can't be left from inside it
+      let loops = mem::replace(&mut self.loops, 0);
+      self.routines.push(symbol);
+      result = self.visit(&***block);
+      self.routines.pop();
+      self.loops = loops;
+    }
+    self.leave_scope();
+    result
   }
   fn visit_parameter(&mut self, _node: &ParameterNode) -> NumberResult {
     Ok(Nil)
   }
   fn visit_block(&mut self, node: &BlockNode) -> NumberResult {
+    let enclosing_forwards = mem::take(&mut self.forward_declarations);
+    let enclosing_labels = mem::take(&mut self.labels);
+    let enclosing_sequences = mem::take(&mut self.sequences);
     for declaration in &node.declarations {
-      self.visit(&declaration)?;
+      self.visit(declaration)?;
+    }
+    // Every FORWARD declaration needs a body in the same block
+    if let Some(name) = self.forward_declarations.first() {
+      return Err(format!("{} was declared FORWARD but never defined.", name));
     }
-    self.visit(&node.compound_statement)
+    self.forward_declarations = enclosing_forwards;
+    self.visit(&node.compound_statement)?;
+    // A GOTO can only jump to a label in its own block
+    self.check_labels()?;
+    self.labels = enclosing_labels;
+    self.sequences = enclosing_sequences;
+    Ok(Nil)
   }
   fn visit_declaration(&mut self, node: &DeclarationNode) -> NumberResult {
     let DeclarationNode {
-      var_node: VarNode { identifier },
-      type_node: TypeNode { token },
+      var_node: VarNode { identifier, .. },
+      type_node,
+      ..
     } = node;
 
     if let Id(name) = identifier {
-      if self.global_scope.lookup(name) != None {
+      if self.current_scope.lookup_local(name).is_some() {
         return Err(format!(
           "Found duplicate variable declaration for '{}'!",
           name
         ));
       }
-      if let BuiltInSymbol(builtin) = self.global_scope.get(&token) {
-        let variable = VarSymbol(name.to_string(), builtin);
-        self.global_scope.insert(variable);
-      } else {
-        panic!("Invalid builtin type {}", token);
+      let variable = VarSymbol(name.to_string(), self.resolve_type(type_node)?);
+      self.current_scope.insert(variable);
+    }
+    Ok(Nil)
+  }
+  fn visit_type_declaration(&mut self, node: &TypeDeclarationNode) -> NumberResult {
+    let TypeDeclarationNode {
+      type_name,
+      type_node,
+      ..
+    } = node;
+
+    let kind = self.resolve_type(type_node)?;
+    self.declare(TypeSymbol(type_name.to_string(), kind.clone()))?;
+    // The values of an enumerated type are constants in the same scope
+    if let Type::Enumeration(ref names) = kind {
+      for name in names {
+        self.declare(ConstSymbol(name.to_string(), kind.clone()))?;
       }
     }
     Ok(Nil)
@@ -97,34 +871,312 @@ impl NodeVisitor for TableBuilder {
   fn visit_real(&mut self, _node: &RealNumNode) -> NumberResult {
     Ok(Nil)
   }
+  fn visit_char(&mut self, _node: &CharNode) -> NumberResult {
+    Ok(Nil)
+  }
+  fn visit_boolean(&mut self, _node: &BooleanNode) -> NumberResult {
+    Ok(Nil)
+  }
+  fn visit_string(&mut self, _node: &StringNode) -> NumberResult {
+    Ok(Nil)
+  }
   fn visit_binop(&mut self, node: &BinOpNode) -> NumberResult {
     self.visit(&node.left)?;
-    self.visit(&node.right)
+    self.visit(&node.right)?;
+    self.binop_type(node)?;
+    Ok(Nil)
   }
   fn visit_unaryop(&mut self, node: &UnaryOpNode) -> NumberResult {
     self.visit(&node.expr)
   }
   fn visit_compound(&mut self, node: &CompoundNode) -> NumberResult {
+    self.sequence_count += 1;
+    self.sequences.push(self.sequence_count);
     for child in &node.children {
       self.visit(child)?;
     }
+    self.sequences.pop();
+    Ok(Nil)
+  }
+  fn visit_label_declaration(&mut self, node: &LabelDeclarationNode) -> NumberResult {
+    let label = node.label.to_string();
+    if self.labels.declared.iter().any(|(name, _)| *name == label) {
+      return Err(format!(
+        "Found duplicate label declaration for '{}'!",
+        label
+      ));
+    }
+    self.labels.declared.push((label, None));
+    Ok(Nil)
+  }
+  fn visit_labelled_statement(&mut self, node: &LabelledStatementNode) -> NumberResult {
+    let label = node.label.to_string();
+    let sequence = self.sequences.last().cloned();
+    match self
+      .labels
+      .declared
+      .iter_mut()
+      .find(|(name, _)| *name == label)
+    {
+      None => return Err(format!("Undeclared label {} found.", label)),
+      Some((_, Some(_))) => {
+        return Err(format!(
+          "Label {} is set on more than one statement.",
+          label
+        ))
+      }
+      Some(declared) => declared.1 = sequence,
+    }
+    self.visit(&node.statement)
+  }
+  fn visit_goto(&mut self, node: &GotoNode) -> NumberResult {
+    let enclosing = self.sequences.clone();
+    self.labels.gotos.push((node.label.to_string(), enclosing));
+    Ok(Nil)
+  }
+  fn visit_try_except(&mut self, node: &TryExceptNode) -> NumberResult {
+    self.visit_nested(&*node.body)?;
+    for handler in &node.handlers {
+      if exception::class_name(&handler.class.to_string()).is_none() {
+        return Err(format!("Unknown exception class {} found.", handler.class));
+      }
+      self.visit_handler(handler.variable.as_ref(), &*handler.statement)?;
+    }
+    if let Some(ref statement) = node.else_branch {
+      self.visit_handler(None, &**statement)?;
+    }
     Ok(Nil)
   }
+  fn visit_try_finally(&mut self, node: &TryFinallyNode) -> NumberResult {
+    self.visit_nested(&*node.body)?;
+    self.visit_nested(&*node.finally)
+  }
+  fn visit_raise(&mut self, node: &RaiseNode) -> NumberResult {
+    match node.exception {
+      Some(ref exception) => {
+        self.visit(exception)?;
+        let kind = self.type_of(&**exception)?;
+        if kind != exception_type() {
+          return Err(format!("RAISE expects an exception, found {}.", kind));
+        }
+        Ok(Nil)
+      }
+      // Only the exception being handled can be raised again
+      None if self.handlers == 0 => {
+        Err("RAISE without an exception can only be used in an exception handler.".to_string())
+      }
+      None => Ok(Nil),
+    }
+  }
+  fn visit_exception(&mut self, node: &ExceptionNode) -> NumberResult {
+    if exception::class_name(&node.class.to_string()).is_none() {
+      return Err(format!("Unknown exception class {} found.", node.class));
+    }
+    self.visit(&node.message)?;
+    match self.type_of(&*node.message)?.base() {
+      kind if kind == Type::Str || kind == simple(Char) => Ok(Nil),
+      kind => Err(format!(
+        "The message of an exception must be a string, found {}.",
+        kind
+      )),
+    }
+  }
   fn visit_assign(&mut self, node: &AssignNode) -> NumberResult {
-    let var_node: &VarNode = node.identifier.downcast_ref().unwrap();
-    if let Id(name) = &var_node.identifier {
-      if self.global_scope.lookup(&name) == None {
-        return Err(format!("Undeclared variable {} found.", name));
+    let target = if let Some(VarNode { identifier, .. }) = node.identifier.downcast_ref::<VarNode>() {
+      match self.current_scope.lookup(&identifier.to_string()) {
+        None => return Err(format!("Undeclared variable {} found.", identifier)),
+        Some(ConstSymbol(name, _)) => return Err(format!("Cannot assign to constant {}.", name)),
+        Some(FunctionSymbol(ref name, _, _)) if !self.in_function(name) => {
+          return Err(format!(
+            "Cannot assign to function {} outside its body.",
+            name
+          ))
+        }
+        // A function's result is set by assigning to its name
+        Some(FunctionSymbol(_, _, kind)) => kind,
+        Some(ProcedureSymbol(name, _)) => {
+          return Err(format!("Cannot assign to procedure {}.", name))
+        }
+        Some(TypeSymbol(name, _)) => return Err(format!("Cannot assign to type {}.", name)),
+        _ => self.type_of(&*node.identifier)?,
       }
+    } else {
+      self.visit(&node.identifier)?;
+      self.type_of(&*node.identifier)?
+    };
+    self.visit(&node.expr)?;
+    let value = self.type_of(&*node.expr)?;
+    if !assignable(&target, &value) {
+      return Err(format!(
+        "Cannot assign a value of type {} to a variable of type {}.",
+        value, target
+      ));
     }
-    self.visit(&node.expr)
+    Ok(Nil)
+  }
+  fn visit_nil(&mut self, _node: &NilNode) -> NumberResult {
+    Ok(Nil)
+  }
+  fn visit_deref(&mut self, node: &DerefNode) -> NumberResult {
+    self.visit(&node.pointer)?;
+    self.deref_type(node)?;
+    Ok(Nil)
+  }
+  fn visit_field(&mut self, node: &FieldNode) -> NumberResult {
+    self.visit(&node.record)?;
+    self.field_type(node)?;
+    Ok(Nil)
+  }
+  fn visit_while(&mut self, node: &WhileNode) -> NumberResult {
+    self.visit(&node.condition)?;
+    let kind = self.type_of(&*node.condition)?;
+    if kind.base() != simple(Boolean) {
+      return Err(format!(
+        "WHILE condition must be a BOOLEAN, found {}.",
+        kind
+      ));
+    }
+    self.loops += 1;
+    let result = self.visit_nested(&*node.body);
+    self.loops -= 1;
+    result
+  }
+  fn visit_set(&mut self, node: &SetNode) -> NumberResult {
+    for (low, high) in &node.members {
+      self.visit(low)?;
+      if let Some(high) = high {
+        self.visit(high)?;
+      }
+    }
+    self.set_type(node)?;
+    Ok(Nil)
+  }
+  fn visit_if(&mut self, node: &IfNode) -> NumberResult {
+    self.visit(&node.condition)?;
+    let kind = self.type_of(&*node.condition)?;
+    if kind.base() != simple(Boolean) {
+      return Err(format!("IF condition must be a BOOLEAN, found {}.", kind));
+    }
+    self.visit_nested(&*node.then_branch)?;
+    if let Some(ref statement) = node.else_branch {
+      self.visit_nested(&**statement)?;
+    }
+    Ok(Nil)
+  }
+  fn visit_case(&mut self, node: &CaseNode) -> NumberResult {
+    let CaseNode {
+      selector,
+      branches,
+      else_branch,
+      ..
+    } = node;
+
+    self.visit(selector)?;
+    let kind = self.type_of(&**selector)?;
+    if !kind.is_ordinal() {
+      return Err(format!(
+        "CASE selector must be of an ordinal type, found {}.",
+        kind
+      ));
+    }
+    let mut ranges: Vec<(i32, i32)> = vec![];
+    for branch in branches {
+      for (low, high) in &branch.labels {
+        let (low_type, low_value) = self.constant(low)?;
+        let (high_type, high_value) = self.constant(high)?;
+        for label_type in &[low_type, high_type] {
+          if label_type.base() != kind.base() {
+            return Err(format!(
+              "CASE label of type {} does not match selector type {}.",
+              label_type, kind
+            ));
+          }
+        }
+        if low_value > high_value {
+          return Err(format!("CASE label range {}..{} is empty.", low, high));
+        }
+        let overlaps = ranges
+          .iter()
+          .any(|&(other_low, other_high)| low_value <= other_high && other_low <= high_value);
+        if overlaps {
+          return Err(format!("Duplicate CASE label {} found.", low));
+        }
+        ranges.push((low_value, high_value));
+      }
+      self.visit_nested(&*branch.statement)?;
+    }
+    if let Some(statement) = else_branch {
+      self.visit_nested(&**statement)?;
+    }
+    Ok(Nil)
+  }
+  fn visit_for(&mut self, node: &ForNode) -> NumberResult {
+    let ForNode {
+      identifier,
+      start,
+      end,
+      body,
+      ..
+    } = node;
+
+    let VarNode { identifier, .. } = identifier;
+    let kind = match self.current_scope.lookup(&identifier.to_string()) {
+      Some(VarSymbol(_, kind)) => kind,
+      _ => return Err(format!("Invalid FOR loop control variable {}.", identifier)),
+    };
+    if !kind.is_ordinal() {
+      return Err(format!(
+        "FOR loop control variable {} must be of an ordinal type, found {}.",
+        identifier, kind
+      ));
+    }
+    for bound in [start, end] {
+      self.visit(bound)?;
+      let bound_type = self.type_of(&**bound)?;
+      if bound_type.base() != kind.base() {
+        return Err(format!(
+          "FOR loop bound of type {} does not match control variable type {}.",
+          bound_type, kind
+        ));
+      }
+    }
+    self.loops += 1;
+    let result = self.visit_nested(&**body);
+    self.loops -= 1;
+    result
   }
   fn visit_var(&mut self, node: &VarNode) -> NumberResult {
-    if let Id(name) = &node.identifier {
-      if self.global_scope.lookup(&name) == None {
-        return Err(format!("Undeclared variable {} found.", name));
+    match self.current_scope.lookup(&node.identifier.to_string()) {
+      None => Err(format!("Undeclared variable {} found.", node.identifier)),
+      // Naming a function without parameters calls it
+      Some(FunctionSymbol(_, params, _)) => {
+        self.check_arguments(&node.identifier, &params, &[])?;
+        Ok(Nil)
       }
+      Some(ProcedureSymbol(name, _)) => Err(format!("Procedure {} does not return a value.", name)),
+      _ => Ok(Nil),
+    }
+  }
+  fn visit_index(&mut self, node: &IndexNode) -> NumberResult {
+    self.visit(&node.array)?;
+    self.visit(&node.index)?;
+    self.element_type(node)?;
+    Ok(Nil)
+  }
+  fn visit_function_call(&mut self, node: &FunctionCallNode) -> NumberResult {
+    for arg in &node.args {
+      if !arg.is::<TypeNode>() {
+        self.visit(arg)?;
+      }
+    }
+    self.function_type(&node.func_name, &node.args)?;
+    Ok(Nil)
+  }
+  fn visit_procedure_call(&mut self, node: &ProcedureCallNode) -> NumberResult {
+    for arg in &node.args {
+      self.visit(arg)?;
     }
+    self.check_procedure_call(&node.proc_name, &node.args)?;
     Ok(Nil)
   }
 }
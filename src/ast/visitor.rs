@@ -1,8 +1,14 @@
 use ast::node::*;
+use std::borrow::Borrow;
 use utils::number::{Number::Nil, NumberResult};
 
 pub trait NodeVisitor {
-  fn visit(&mut self, node: &Box<Node>) -> NumberResult {
+  /// Visits a node, given either boxed or as a reference to the node.
+  fn visit<N: Borrow<Node> + ?Sized>(&mut self, node: &N) -> NumberResult
+  where
+    Self: Sized,
+  {
+    let node = node.borrow();
     if node.is::<ProgramNode>() {
       self.visit_program(node.downcast_ref().unwrap())
     } else if node.is::<ProcedureNode>() {
@@ -13,12 +19,20 @@ pub trait NodeVisitor {
       self.visit_block(node.downcast_ref().unwrap())
     } else if node.is::<DeclarationNode>() {
       self.visit_declaration(node.downcast_ref().unwrap())
+    } else if node.is::<TypeDeclarationNode>() {
+      self.visit_type_declaration(node.downcast_ref().unwrap())
     } else if node.is::<TypeNode>() {
       self.visit_type(node.downcast_ref().unwrap())
     } else if node.is::<IntegerNumNode>() {
       self.visit_integer(node.downcast_ref().unwrap())
     } else if node.is::<RealNumNode>() {
       self.visit_real(node.downcast_ref().unwrap())
+    } else if node.is::<CharNode>() {
+      self.visit_char(node.downcast_ref().unwrap())
+    } else if node.is::<StringNode>() {
+      self.visit_string(node.downcast_ref().unwrap())
+    } else if node.is::<BooleanNode>() {
+      self.visit_boolean(node.downcast_ref().unwrap())
     } else if node.is::<BinOpNode>() {
       self.visit_binop(node.downcast_ref().unwrap())
     } else if node.is::<UnaryOpNode>() {
@@ -27,8 +41,44 @@ pub trait NodeVisitor {
       self.visit_compound(node.downcast_ref().unwrap())
     } else if node.is::<AssignNode>() {
       self.visit_assign(node.downcast_ref().unwrap())
+    } else if node.is::<NilNode>() {
+      self.visit_nil(node.downcast_ref().unwrap())
+    } else if node.is::<DerefNode>() {
+      self.visit_deref(node.downcast_ref().unwrap())
+    } else if node.is::<FieldNode>() {
+      self.visit_field(node.downcast_ref().unwrap())
+    } else if node.is::<WhileNode>() {
+      self.visit_while(node.downcast_ref().unwrap())
+    } else if node.is::<LabelDeclarationNode>() {
+      self.visit_label_declaration(node.downcast_ref().unwrap())
+    } else if node.is::<LabelledStatementNode>() {
+      self.visit_labelled_statement(node.downcast_ref().unwrap())
+    } else if node.is::<GotoNode>() {
+      self.visit_goto(node.downcast_ref().unwrap())
+    } else if node.is::<TryExceptNode>() {
+      self.visit_try_except(node.downcast_ref().unwrap())
+    } else if node.is::<TryFinallyNode>() {
+      self.visit_try_finally(node.downcast_ref().unwrap())
+    } else if node.is::<RaiseNode>() {
+      self.visit_raise(node.downcast_ref().unwrap())
+    } else if node.is::<ExceptionNode>() {
+      self.visit_exception(node.downcast_ref().unwrap())
+    } else if node.is::<SetNode>() {
+      self.visit_set(node.downcast_ref().unwrap())
+    } else if node.is::<IfNode>() {
+      self.visit_if(node.downcast_ref().unwrap())
+    } else if node.is::<CaseNode>() {
+      self.visit_case(node.downcast_ref().unwrap())
+    } else if node.is::<ForNode>() {
+      self.visit_for(node.downcast_ref().unwrap())
     } else if node.is::<VarNode>() {
       self.visit_var(node.downcast_ref().unwrap())
+    } else if node.is::<IndexNode>() {
+      self.visit_index(node.downcast_ref().unwrap())
+    } else if node.is::<FunctionCallNode>() {
+      self.visit_function_call(node.downcast_ref().unwrap())
+    } else if node.is::<ProcedureCallNode>() {
+      self.visit_procedure_call(node.downcast_ref().unwrap())
     } else if node.is::<NoOpNode>() {
       self.visit_noop(node.downcast_ref().unwrap())
     } else {
@@ -40,14 +90,36 @@ pub trait NodeVisitor {
   fn visit_parameter(&mut self, node: &ParameterNode) -> NumberResult;
   fn visit_block(&mut self, node: &BlockNode) -> NumberResult;
   fn visit_declaration(&mut self, node: &DeclarationNode) -> NumberResult;
+  fn visit_type_declaration(&mut self, node: &TypeDeclarationNode) -> NumberResult;
   fn visit_type(&mut self, node: &TypeNode) -> NumberResult;
   fn visit_integer(&mut self, node: &IntegerNumNode) -> NumberResult;
   fn visit_real(&mut self, node: &RealNumNode) -> NumberResult;
+  fn visit_char(&mut self, node: &CharNode) -> NumberResult;
+  fn visit_string(&mut self, node: &StringNode) -> NumberResult;
+  fn visit_boolean(&mut self, node: &BooleanNode) -> NumberResult;
   fn visit_binop(&mut self, node: &BinOpNode) -> NumberResult;
   fn visit_unaryop(&mut self, node: &UnaryOpNode) -> NumberResult;
   fn visit_compound(&mut self, node: &CompoundNode) -> NumberResult;
   fn visit_assign(&mut self, node: &AssignNode) -> NumberResult;
+  fn visit_nil(&mut self, node: &NilNode) -> NumberResult;
+  fn visit_deref(&mut self, node: &DerefNode) -> NumberResult;
+  fn visit_field(&mut self, node: &FieldNode) -> NumberResult;
+  fn visit_while(&mut self, node: &WhileNode) -> NumberResult;
+  fn visit_label_declaration(&mut self, node: &LabelDeclarationNode) -> NumberResult;
+  fn visit_labelled_statement(&mut self, node: &LabelledStatementNode) -> NumberResult;
+  fn visit_goto(&mut self, node: &GotoNode) -> NumberResult;
+  fn visit_try_except(&mut self, node: &TryExceptNode) -> NumberResult;
+  fn visit_try_finally(&mut self, node: &TryFinallyNode) -> NumberResult;
+  fn visit_raise(&mut self, node: &RaiseNode) -> NumberResult;
+  fn visit_exception(&mut self, node: &ExceptionNode) -> NumberResult;
+  fn visit_set(&mut self, node: &SetNode) -> NumberResult;
+  fn visit_if(&mut self, node: &IfNode) -> NumberResult;
+  fn visit_case(&mut self, node: &CaseNode) -> NumberResult;
+  fn visit_for(&mut self, node: &ForNode) -> NumberResult;
   fn visit_var(&mut self, node: &VarNode) -> NumberResult;
+  fn visit_index(&mut self, node: &IndexNode) -> NumberResult;
+  fn visit_function_call(&mut self, node: &FunctionCallNode) -> NumberResult;
+  fn visit_procedure_call(&mut self, node: &ProcedureCallNode) -> NumberResult;
   fn visit_noop(&mut self, _node: &NoOpNode) -> NumberResult {
     Ok(Nil)
   }
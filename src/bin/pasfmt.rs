@@ -0,0 +1,101 @@
+extern crate basic_interpreter;
+
+use std::env;
+use std::fs;
+use std::panic;
+use std::process;
+
+use basic_interpreter::formatter::{format, set_option, Style};
+use basic_interpreter::parser::recover;
+
+const USAGE: &str = "Usage: pasfmt [--check] [--keywords=upper|lower] [--indent=<width>] \
+                     [--begin=same-line|next-line] [--align-colons] <filename>...";
+
+/// Reads the style and the files to format from the arguments.
+fn parse_args(args: &[String]) -> Result<(Style, bool, Vec<String>), String> {
+  let mut style = Style::default();
+  let mut check = false;
+  let mut filenames = vec![];
+  for arg in args {
+    if arg == "--check" {
+      check = true;
+    } else if arg.starts_with("--") {
+      set_option(&mut style, arg)?;
+    } else {
+      filenames.push(arg.clone());
+    }
+  }
+  if filenames.is_empty() {
+    return Err(USAGE.to_string());
+  }
+  Ok((style, check, filenames))
+}
+
+///
+///  Format Pascal files.
+///
+/// Use:
+///   cargo run --bin pasfmt -- [options] <filename>...
+///
+/// Each file is rewritten with its reserved words in one case, its
+/// statements indented by nesting, and its comments kept in place.
+///
+/// --check leaves the files unchanged, listing those that are not
+///   formatted, and exits with 1 if there are any.
+/// --keywords=upper|lower sets the case of reserved words (upper).
+/// --indent=<width> sets the spaces per level of nesting (2).
+/// --begin=same-line|next-line places the BEGIN of a statement
+///   controlled by WHILE, IF and the like (same-line).
+/// --align-colons lines up the colons of a VAR section.
+///
+/// A file that can't be read or parsed exits with 2.
+///
+fn main() {
+  let args: Vec<String> = env::args().skip(1).collect();
+  let (style, check, filenames) = match parse_args(&args) {
+    Ok(options) => options,
+    Err(msg) => {
+      eprintln!("{}", msg);
+      process::exit(2);
+    }
+  };
+  // Syntax errors are reported below, with the file they are in
+  panic::set_hook(Box::new(|_| {}));
+  let mut unformatted = false;
+  let mut failed = false;
+  for filename in &filenames {
+    let source = match fs::read_to_string(filename) {
+      Ok(source) => source,
+      Err(err) => {
+        eprintln!("{}: {}", filename, err);
+        failed = true;
+        continue;
+      }
+    };
+    // The parser panics on a syntax error
+    let formatted = match recover(|| format(&source, &style)) {
+      Ok(formatted) => formatted,
+      Err(msg) => {
+        eprintln!("{}: {}", filename, msg);
+        failed = true;
+        continue;
+      }
+    };
+    if formatted == source {
+      continue;
+    }
+    if check {
+      println!("{}", filename);
+      unformatted = true;
+    } else if let Err(err) = fs::write(filename, formatted) {
+      eprintln!("{}: {}", filename, err);
+      failed = true;
+    }
+  }
+  if failed {
+    process::exit(2);
+  }
+  if unformatted {
+    process::exit(1);
+  }
+}
@@ -0,0 +1,46 @@
+use ast::printer::Printer;
+use ast::visitor::NodeVisitor;
+use interpreter::Dialect;
+use parser::Parser;
+
+pub use ast::printer::{BeginPlacement, KeywordCase, Style};
+
+/// Lays out a program in the given style, keeping its comments next to
+/// the statements, declarations and expression tokens they were written
+/// with. Like parsing, this panics if the text is not a valid program.
+///
+/// Identifiers are read as Delphi reads them, so that programs in any
+/// dialect can be formatted.
+pub fn format(text: &str, style: &Style) -> String {
+  let mut parser = Parser::with_comments(text, Dialect::Delphi);
+  let program = parser.parse();
+  let mut printer = Printer::new();
+  printer.set_style(style.clone());
+  printer.set_comments(parser.comments().to_vec());
+  printer.set_inline_comments(parser.inline_comments().to_vec());
+  printer.visit(&program).unwrap();
+  printer.source()
+}
+
+/// Changes a style by a command line option, one of `--keywords=upper|lower`,
+/// `--indent=<width>`, `--begin=same-line|next-line` or `--align-colons`.
+pub fn set_option(style: &mut Style, arg: &str) -> Result<(), String> {
+  let (option, value) = match arg.find('=') {
+    Some(i) => (&arg[..i], Some(&arg[i + 1..])),
+    None => (arg, None),
+  };
+  match (option, value) {
+    ("--align-colons", None) => style.align_colons = true,
+    ("--keywords", Some("upper")) => style.keyword_case = KeywordCase::Upper,
+    ("--keywords", Some("lower")) => style.keyword_case = KeywordCase::Lower,
+    ("--begin", Some("same-line")) => style.begin_placement = BeginPlacement::SameLine,
+    ("--begin", Some("next-line")) => style.begin_placement = BeginPlacement::NextLine,
+    ("--indent", Some(width)) => {
+      style.indent_width = width
+        .parse()
+        .map_err(|_| format!("Invalid indentation width: {}", width))?
+    }
+    _ => return Err(format!("Unknown option: {}", arg)),
+  }
+  Ok(())
+}
@@ -1,27 +1,239 @@
+use ast::graph::{AstGraph, ScopeGraph};
 use ast::node::Node;
+use ast::serializer::Serializer;
 use ast::visitor::NodeVisitor;
-use ast::{evaluator::Evaluator, table_builder::TableBuilder};
+use ast::{evaluator::Evaluator, printer::Printer, table_builder::TableBuilder};
+use lexer::token::Token;
+use lexer::Lexer;
 use parser::Parser;
-use utils::number::NumberResult;
+use std::collections::HashMap;
+use std::io::Write;
+use std::panic;
+use std::thread;
+use symbols::symbol::{BuiltIn, SymbolTable, Type};
+use utils::heap::LeakReport;
+use utils::number::{Number, NumberResult};
+
+mod repl;
+
+pub use self::repl::{is_complete, Repl};
+pub use ast::serializer::AstFormat;
+
+/// The Pascal dialect whose rules are followed where implementations differ.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dialect {
+  /// ISO 7185 Standard Pascal
+  Iso,
+  /// Borland Turbo Pascal
+  Turbo,
+  /// Delphi, which follows Turbo Pascal but also allows identifiers
+  /// to contain letters outside ASCII
+  Delphi,
+}
+
+/// The number of nested procedure and function calls a program may
+/// make, unless changed with `Interpreter::set_max_call_depth`.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 5_000;
+
+/// The native stack reserved for each nested Pascal call. A call passes
+/// through several visitor methods for every statement and expression
+/// it contains, so this allows for fairly deeply nested routine bodies.
+const STACK_PER_CALL: usize = 32 * 1024;
+
+/// The native stack kept back from the evaluator for the work done
+/// between one call and the next.
+const STACK_RESERVE: usize = 1024 * 1024;
+
+/// Runs the evaluator on a thread of its own, with a stack large enough
+/// for the maximum call depth, so that a call which would overflow the
+/// stack fails with a runtime error instead.
+fn run_with_stack<F>(evaluator: &mut Evaluator, max_call_depth: usize, run: F) -> NumberResult
+where
+  F: FnOnce(&mut Evaluator) -> NumberResult + Send,
+{
+  let stack_size = max_call_depth
+    .saturating_mul(STACK_PER_CALL)
+    .saturating_add(2 * STACK_RESERVE);
+  evaluator.set_max_call_depth(max_call_depth);
+  evaluator.set_stack_size(stack_size - 2 * STACK_RESERVE);
+  thread::scope(|scope| {
+    let program = thread::Builder::new()
+      .stack_size(stack_size)
+      .spawn_scoped(scope, move || run(evaluator))
+      .map_err(|err| format!("Runtime error: unable to start the program, {}", err))?;
+    program
+      .join()
+      .unwrap_or_else(|payload| panic::resume_unwind(payload))
+  })
+}
+
+/// Evaluates an expression on its own, outside any program, such as a
+/// spreadsheet formula. Its variables are the names bound to values,
+/// which may be integers, reals, booleans, characters or strings, and
+/// the expression is checked against the types of those values before
+/// it is evaluated. The value is returned with its type.
+///
+/// Each name must be an identifier, and no two may differ only in case.
+pub fn evaluate_expression(
+  text: &str,
+  bindings: &HashMap<String, Number>,
+) -> Result<(Number, Type), String> {
+  let expression = Parser::new(text).parse_expression()?;
+  let mut table_builder = TableBuilder::new();
+  table_builder.enter_global_scope();
+  let mut evaluator = Evaluator::new();
+  // Bound in order of name, so that of two names differing only in
+  // case, the same one is always reported
+  let mut bindings: Vec<(&String, &Number)> = bindings.iter().collect();
+  bindings.sort_by_key(|(name, _)| *name);
+  for (name, value) in bindings {
+    if !is_identifier(name) {
+      return Err(format!("Cannot bind '{}', which is not an identifier.", name));
+    }
+    let kind = match value {
+      Number::Int(_) => Type::Simple(BuiltIn::new(Token::Integer)),
+      Number::Real(_) => Type::Simple(BuiltIn::new(Token::Real)),
+      Number::Bool(_) => Type::Simple(BuiltIn::new(Token::Boolean)),
+      Number::Char(_) => Type::Simple(BuiltIn::new(Token::Char)),
+      Number::Str(_) => Type::Str,
+      value => {
+        return Err(format!(
+          "Cannot bind {} to {}, only integers, reals, booleans, characters and strings can be bound.",
+          name,
+          value.to_string()
+        ))
+      }
+    };
+    table_builder.bind(name, kind)?;
+    evaluator.bind(name, value.clone());
+  }
+  let kind = table_builder.check_expression(&*expression)?;
+  let value = evaluator.execute(&*expression)?;
+  Ok((value, kind))
+}
+
+/// Returns true if a name is an identifier, and not a reserved word.
+fn is_identifier(name: &str) -> bool {
+  let tokens: Vec<Token> = Lexer::new(name)
+    .map_while(|token| token.ok().map(|token| token.token))
+    .collect();
+  match tokens.as_slice() {
+    [Token::Id(id), Token::EOF] => id == name,
+    _ => false,
+  }
+}
 
 pub struct Interpreter {
   pub root_node: Box<Node>,
+  evaluator: Evaluator,
+  check_leaks: bool,
+  max_call_depth: usize,
 }
 
 impl Interpreter {
   pub fn new(text: &str) -> Self {
-    let mut parser = Parser::new(&text);
+    Interpreter::with_dialect(text, Dialect::Iso)
+  }
+  /// Parses a program following the rules of the given dialect, which
+  /// unlike `set_dialect` also applies to how the source text is read.
+  pub fn with_dialect(text: &str, dialect: Dialect) -> Self {
+    let mut parser = Parser::with_dialect(text, dialect);
     let root_node = parser.parse();
-    Interpreter { root_node }
+    let mut evaluator = Evaluator::new();
+    evaluator.set_dialect(dialect);
+    Interpreter {
+      root_node,
+      evaluator,
+      check_leaks: false,
+      max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+    }
   }
+  /// Checks the program, then runs it. The program runs on its own
+  /// thread, with a stack large enough for the maximum call depth, and
+  /// a call that would overflow the stack fails with a runtime error.
+  ///
+  /// A program that finishes normally gives `Nil`, while one that calls
+  /// Halt gives its exit code as an `Int`, from 0 to 255. Halt with any
+  /// other code is a runtime error.
   pub fn interpret(&mut self) -> NumberResult {
-    self.accept(&mut TableBuilder::new())?;
-    self.accept(&mut Evaluator::new())
-  }
-}
+    self.check()?;
 
-impl Node for Interpreter {
-  fn accept(&mut self, visitor: &mut NodeVisitor) -> NumberResult {
-    self.root_node.accept(visitor)
+    let root_node = &mut self.root_node;
+    run_with_stack(&mut self.evaluator, self.max_call_depth, move |evaluator| {
+      root_node.accept(evaluator)
+    })
+  }
+  /// Limits how deeply procedure and function calls may be nested,
+  /// which also sets the size of the stack the program runs with.
+  pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+    self.max_call_depth = max_call_depth;
+  }
+  pub fn set_dialect(&mut self, dialect: Dialect) {
+    self.evaluator.set_dialect(dialect);
+  }
+  /// Enables the heap leak report returned by `leak_report`.
+  pub fn set_leak_check(&mut self, check_leaks: bool) {
+    self.check_leaks = check_leaks;
+  }
+  /// Lists the blocks still allocated when `interpret` finished,
+  /// if leak checking was enabled.
+  pub fn leak_report(&self) -> Option<LeakReport> {
+    if self.check_leaks {
+      Some(self.evaluator.leaks())
+    } else {
+      None
+    }
+  }
+  /// Sends the output of Write and WriteLn somewhere other than stdout.
+  pub fn set_output(&mut self, output: Box<Write + Send>) {
+    self.evaluator.set_output(output);
+  }
+  /// Returns the source of the parsed program, in the canonical layout
+  /// of `Printer`, which parses back to the same program.
+  pub fn to_source(&self) -> String {
+    let mut printer = Printer::new();
+    printer.visit(&self.root_node).unwrap();
+    printer.source()
+  }
+  /// Returns the syntax tree of the parsed program, each node with its
+  /// kind and its fields. In JSON each node also has the span of source
+  /// text it was parsed from; S-expressions leave spans out.
+  pub fn dump_ast(&self, format: AstFormat) -> String {
+    let mut serializer = Serializer::new();
+    serializer.visit(&self.root_node).unwrap();
+    serializer.serialize(format)
+  }
+  /// Returns the syntax tree of the parsed program as a Graphviz DOT
+  /// graph.
+  pub fn ast_graph(&self) -> String {
+    let mut graph = AstGraph::new();
+    graph.visit(&self.root_node).unwrap();
+    graph.graph()
+  }
+  /// Checks the program, returning its scopes as a Graphviz DOT graph,
+  /// from the scope of the built-in symbols down to the innermost.
+  pub fn scope_graph(&self) -> Result<String, String> {
+    let mut graph = ScopeGraph::new();
+    graph.visit_scope(&self.check()?);
+    Ok(graph.graph())
+  }
+  /// Checks the program without running it, returning the scope of the
+  /// built-in symbols, which holds the global scope of the program, and
+  /// the scopes of its routines nested in turn.
+  pub fn check(&self) -> Result<SymbolTable, String> {
+    let mut table_builder = TableBuilder::new();
+    table_builder.visit(&self.root_node)?;
+    Ok(table_builder.into_outermost_scope())
+  }
+  /// Checks the program, returning its scopes written in the given
+  /// notation, each with its symbols and the scopes nested in it.
+  pub fn dump_symbols(&self, format: AstFormat) -> Result<String, String> {
+    let mut serializer = Serializer::new();
+    serializer.visit_scope(&self.check()?);
+    Ok(serializer.serialize(format))
+  }
+  /// Returns the value a global variable held when the program finished.
+  pub fn lookup(&self, name: &str) -> Option<Number> {
+    self.evaluator.lookup(name)
   }
 }
@@ -0,0 +1,310 @@
+use ast::evaluator::Evaluator;
+use ast::node::*;
+use ast::serializer::{AstFormat, Serializer};
+use ast::table_builder::TableBuilder;
+use ast::visitor::NodeVisitor;
+use lexer::token::Token;
+use lexer::Lexer;
+use parser::Parser;
+use std::io::Write;
+use symbols::symbol::{BuiltIn, Symbol, Type};
+use utils::number::Number;
+
+use super::{run_with_stack, DEFAULT_MAX_CALL_DEPTH};
+
+const HELP: &str = "\
+Enter declarations, statements or an expression, which may span several
+lines. Variables, types and routines are kept from one entry to the next.
+
+  :vars         List the variables declared, with their values
+  :type <expr>  Show the type of an expression, without evaluating it
+  :ast <expr>   Show the syntax tree of an expression
+  :history      List the entries made so far
+  :help         Show this help
+  :quit         Leave";
+
+/// Returns false while the text entered so far is unfinished: a BEGIN,
+/// CASE, RECORD or TRY is left without its END, a comment is left open,
+/// or a procedure or function heading is still waiting for its body.
+pub fn is_complete(input: &str) -> bool {
+  let mut depth = 0;
+  let mut headings = 0;
+  for token in Lexer::new(input) {
+    let token = match token {
+      Ok(token) => token.token,
+      // Only a comment can be finished on a later line
+      Err(err) => return err.message != "Unterminated comment",
+    };
+    match token {
+      Token::Begin => {
+        if depth == 0 && headings > 0 {
+          headings -= 1;
+        }
+        depth += 1;
+      }
+      Token::Case | Token::Record | Token::Try => depth += 1,
+      Token::End => depth -= 1,
+      Token::Procedure | Token::Function => headings += 1,
+      Token::Forward if headings > 0 => headings -= 1,
+      _ => (),
+    }
+  }
+  depth <= 0 && headings == 0
+}
+
+/// Writes a value the way a constant of its type is written in Pascal.
+/// Values never assigned are written as a question mark.
+fn show(value: &Number, kind: &Type) -> String {
+  match (value, kind.base()) {
+    (Number::Nil, _) => "?".to_string(),
+    (Number::Bool(value), _) => value.to_string().to_uppercase(),
+    (Number::Char(value), _) => format!("'{}'", value.to_string().replace('\'', "''")),
+    (Number::Str(value), _) => format!("'{}'", value.replace('\'', "''")),
+    (Number::Enum(ordinal, names), _) => names[*ordinal as usize].clone(),
+    (Number::Array(_, elements), Type::Array(_, element_type)) => {
+      let elements: Vec<String> = elements
+        .iter()
+        .map(|element| show(element, &element_type))
+        .collect();
+      format!("({})", elements.join(", "))
+    }
+    (Number::Set(set), Type::Set(member_type)) => {
+      let members: Vec<String> = set
+        .ordinals()
+        .into_iter()
+        .map(
+          |ordinal| match member_type.as_ref().map(|kind| kind.base()) {
+            Some(Type::Enumeration(names)) => names[ordinal as usize].clone(),
+            Some(Type::Simple(ref builtin)) if *builtin == BuiltIn::new(Token::Char) => {
+              show(&Number::Char(ordinal as u8 as char), &Type::Str)
+            }
+            Some(Type::Simple(ref builtin)) if *builtin == BuiltIn::new(Token::Boolean) => {
+              show(&Number::Bool(ordinal != 0), &Type::Str)
+            }
+            _ => ordinal.to_string(),
+          },
+        )
+        .collect();
+      format!("[{}]", members.join(", "))
+    }
+    (Number::Record(fields), Type::Record(field_types)) => {
+      let fields: Vec<String> = fields
+        .iter()
+        .zip(&field_types)
+        .map(|((name, value), (_, kind))| format!("{}: {}", name, show(value, kind)))
+        .collect();
+      format!("({})", fields.join("; "))
+    }
+    (Number::Pointer(None), _) => "NIL".to_string(),
+    (Number::Pointer(Some(address)), _) => format!("^{}", address),
+    (value, _) => value.to_text().unwrap_or_else(|_| value.to_string()),
+  }
+}
+
+/// An interactive session, which checks and runs declarations and
+/// statements entered one at a time, and evaluates expressions. What is
+/// declared and the values of variables are kept from one entry to the
+/// next, as though each were added to the same program.
+pub struct Repl {
+  table_builder: TableBuilder,
+  evaluator: Evaluator,
+  max_call_depth: usize,
+  history: Vec<String>,
+}
+
+impl Default for Repl {
+  fn default() -> Self {
+    Repl::new()
+  }
+}
+
+impl Repl {
+  pub fn new() -> Self {
+    let mut table_builder = TableBuilder::new();
+    table_builder.enter_global_scope();
+    Repl {
+      table_builder,
+      evaluator: Evaluator::new(),
+      max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+      history: vec![],
+    }
+  }
+  /// Sends the output of Write and WriteLn somewhere other than stdout.
+  pub fn set_output(&mut self, output: Box<Write + Send>) {
+    self.evaluator.set_output(output);
+  }
+  /// Returns the entries made so far, oldest first.
+  pub fn history(&self) -> &[String] {
+    &self.history
+  }
+  /// Reads one complete entry, returning the text to show for it, if
+  /// any. An entry is either declarations, statements, an expression,
+  /// whose value and type are shown, or a command starting with a colon.
+  ///
+  /// An entry that fails to check changes nothing, while one that fails
+  /// as it runs keeps the effects of the statements run before it failed.
+  pub fn eval(&mut self, input: &str) -> Result<String, String> {
+    let input = input.trim();
+    if input.is_empty() {
+      return Ok(String::new());
+    }
+    self.history.push(input.to_string());
+    if input.starts_with(':') {
+      return self.command(input);
+    }
+    let first_token = Lexer::new(input)
+      .next()
+      .and_then(|token| token.ok())
+      .map(|token| token.token);
+    match first_token {
+      Some(Token::Label)
+      | Some(Token::Type)
+      | Some(Token::Var)
+      | Some(Token::Procedure)
+      | Some(Token::Function) => self.declare(input),
+      _ => match Parser::new(input).parse_expression() {
+        Ok(expression) if !self.is_procedure_call(&*expression) => self.evaluate(&*expression),
+        Ok(_) => self.run(input),
+        // The statement's error is the one to report, if it could be one
+        Err(msg) => match first_token {
+          Some(Token::Id(_)) | Some(Token::Begin) | Some(Token::If) | Some(Token::Case)
+          | Some(Token::For) | Some(Token::While) | Some(Token::Try) | Some(Token::Raise)
+          | Some(Token::Goto) => self.run(input),
+          _ => Err(msg),
+        },
+      },
+    }
+  }
+  /// Runs a command starting with a colon.
+  fn command(&mut self, input: &str) -> Result<String, String> {
+    let (command, argument) = match input.find(char::is_whitespace) {
+      Some(i) => (&input[..i], input[i..].trim()),
+      None => (input, ""),
+    };
+    match (command, argument) {
+      (":vars", "") => Ok(self.variables()),
+      (":type", expression) if !expression.is_empty() => {
+        let expression = Parser::new(expression).parse_expression()?;
+        let kind = self.table_builder.clone().check_expression(&*expression)?;
+        Ok(kind.to_string())
+      }
+      (":ast", expression) if !expression.is_empty() => {
+        let expression = Parser::new(expression).parse_expression()?;
+        let mut serializer = Serializer::new();
+        serializer.visit(&expression)?;
+        Ok(serializer.serialize(AstFormat::SExpr))
+      }
+      (":history", "") => {
+        let entries: Vec<String> = self
+          .history
+          .iter()
+          .zip(1..)
+          .map(|(entry, number)| format!("{:>4}  {}", number, entry.replace('\n', "\n      ")))
+          .collect();
+        Ok(entries.join("\n"))
+      }
+      (":help", "") => Ok(HELP.to_string()),
+      _ => Err(format!(
+        "Unknown command {}, :help lists the commands.",
+        input
+      )),
+    }
+  }
+  /// Lists the global variables, in the order they were declared.
+  fn variables(&self) -> String {
+    let variables: Vec<String> = self
+      .table_builder
+      .current_scope()
+      .symbols()
+      .into_iter()
+      .filter_map(|symbol| match symbol {
+        Symbol::VarSymbol(name, kind) => Some(match self.evaluator.lookup(name) {
+          Some(value) => format!("{}: {} = {}", name, kind, show(&value, kind)),
+          None => format!("{}: {}", name, kind),
+        }),
+        _ => None,
+      })
+      .collect();
+    if variables.is_empty() {
+      return "No variables have been declared.".to_string();
+    }
+    variables.join("\n")
+  }
+  /// Returns true if an expression is the name of a procedure, perhaps
+  /// with arguments, so is a statement rather than a value.
+  fn is_procedure_call(&self, expression: &Node) -> bool {
+    let name = if let Some(VarNode { identifier, .. }) = expression.downcast_ref() {
+      identifier
+    } else if let Some(FunctionCallNode { func_name, .. }) = expression.downcast_ref() {
+      func_name
+    } else {
+      return false;
+    };
+    matches!(
+      self.table_builder.current_scope().lookup(&name.to_string()),
+      Some(Symbol::ProcedureSymbol(_, _)) | Some(Symbol::BuiltInProcedureSymbol(_))
+    )
+  }
+  fn declare(&mut self, input: &str) -> Result<String, String> {
+    let declarations = Parser::new(input).parse_declarations()?;
+    let mut table_builder = self.table_builder.clone();
+    for declaration in &declarations {
+      table_builder.visit(declaration)?;
+    }
+    self.table_builder = table_builder;
+    run_with_stack(&mut self.evaluator, self.max_call_depth, |evaluator| {
+      for declaration in &declarations {
+        evaluator.execute(&**declaration)?;
+      }
+      Ok(Number::Nil)
+    })?;
+    Ok(String::new())
+  }
+  /// Runs statements, showing the value given to each variable assigned
+  /// by one of them, other than those within structured statements.
+  fn run(&mut self, input: &str) -> Result<String, String> {
+    let statements = Parser::new(input).parse_statements()?;
+    let mut table_builder = self.table_builder.clone();
+    table_builder.visit(&statements)?;
+    self.table_builder = table_builder;
+    let result = run_with_stack(&mut self.evaluator, self.max_call_depth, |evaluator| {
+      evaluator.execute(&*statements)
+    })?;
+    if let Number::Int(code) = result {
+      return Ok(format!("Halted with exit code {}", code));
+    }
+    let CompoundNode { children, .. } = statements.downcast_ref().unwrap();
+    let mut assigned: Vec<String> = vec![];
+    for child in children {
+      if let Some(AssignNode { identifier, .. }) = child.downcast_ref() {
+        if let Some(VarNode { identifier, .. }) = identifier.downcast_ref() {
+          let name = identifier.to_string();
+          if !assigned.contains(&name) {
+            assigned.push(name);
+          }
+        }
+      }
+    }
+    let scope = self.table_builder.current_scope();
+    let values: Vec<String> = assigned
+      .iter()
+      .filter_map(
+        |name| match (scope.lookup(name), self.evaluator.lookup(name)) {
+          (Some(Symbol::VarSymbol(_, kind)), Some(value)) => {
+            Some(format!("{} = {}", name, show(&value, &kind)))
+          }
+          _ => None,
+        },
+      )
+      .collect();
+    Ok(values.join("\n"))
+  }
+  /// Evaluates an expression, showing its value and its type.
+  fn evaluate(&mut self, expression: &Node) -> Result<String, String> {
+    let kind = self.table_builder.clone().check_expression(expression)?;
+    let value = run_with_stack(&mut self.evaluator, self.max_call_depth, |evaluator| {
+      evaluator.execute(expression)
+    })?;
+    Ok(format!("{} : {}", show(&value, &kind), kind))
+  }
+}
@@ -3,172 +3,517 @@ pub mod token;
 use self::token::Token;
 use self::token::Token::*;
 
+use interpreter::Dialect;
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::LazyLock;
 
-lazy_static! {
-  static ref RESERVED_WORDS: HashMap<&'static str, Token> = {
-    let mut reserved_words = HashMap::new();
-    reserved_words.insert("PROGRAM", Program);
-    reserved_words.insert("PROCEDURE", Procedure);
-    reserved_words.insert("VAR", Var);
-    reserved_words.insert("INTEGER", Integer);
-    reserved_words.insert("REAL", Real);
-    reserved_words.insert("BEGIN", Begin);
-    reserved_words.insert("END", End);
-    reserved_words.insert("DIV", IntegerDivision);
-    reserved_words
+static RESERVED_WORDS: LazyLock<HashMap<&'static str, Token>> = LazyLock::new(|| {
+  let mut reserved_words = HashMap::new();
+  reserved_words.insert("PROGRAM", Program);
+  reserved_words.insert("PROCEDURE", Procedure);
+  reserved_words.insert("FUNCTION", Function);
+  reserved_words.insert("LABEL", Label);
+  reserved_words.insert("GOTO", Goto);
+  reserved_words.insert("FORWARD", Forward);
+  reserved_words.insert("TRY", Try);
+  reserved_words.insert("EXCEPT", Except);
+  reserved_words.insert("FINALLY", Finally);
+  reserved_words.insert("RAISE", Raise);
+  reserved_words.insert("VAR", Var);
+  reserved_words.insert("TYPE", Type);
+  reserved_words.insert("FOR", For);
+  reserved_words.insert("TO", To);
+  reserved_words.insert("DOWNTO", Downto);
+  reserved_words.insert("DO", Do);
+  reserved_words.insert("CASE", Case);
+  reserved_words.insert("ELSE", Else);
+  reserved_words.insert("OTHERWISE", Otherwise);
+  reserved_words.insert("IF", If);
+  reserved_words.insert("THEN", Then);
+  reserved_words.insert("SET", Set);
+  reserved_words.insert("IN", In);
+  reserved_words.insert("WHILE", While);
+  reserved_words.insert("RECORD", Record);
+  reserved_words.insert("NIL", Nil);
+  reserved_words.insert("INTEGER", Integer);
+  reserved_words.insert("REAL", Real);
+  reserved_words.insert("BOOLEAN", Boolean);
+  reserved_words.insert("CHAR", Char);
+  reserved_words.insert("ARRAY", Array);
+  reserved_words.insert("OF", Of);
+  reserved_words.insert("TRUE", True);
+  reserved_words.insert("FALSE", False);
+  reserved_words.insert("BEGIN", Begin);
+  reserved_words.insert("END", End);
+  reserved_words.insert("DIV", IntegerDivision);
+  reserved_words
+});
+
+/// A line and column in the source text, both counted from 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Position {
+  pub line: usize,
+  pub column: usize,
+}
+
+impl fmt::Display for Position {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}, column {}", self.line, self.column)
+  }
+}
+
+/// Where a token lies in the source text, both as byte offsets, for
+/// slicing the text, and as character offsets, for counting characters.
+/// Each range runs from the token's first character up to, but not
+/// including, the character after its last.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+  pub char_start: usize,
+  pub char_end: usize,
+}
+
+/// A token, along with where it starts and the text it covers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpannedToken {
+  pub token: Token,
+  pub position: Position,
+  pub span: Span,
+}
+
+/// Text that can't be read as a token, such as an unterminated comment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LexError {
+  pub message: String,
+  /// Where the offending token starts
+  pub position: Position,
+  /// The text read before the error was found
+  pub span: Span,
+}
+
+impl fmt::Display for LexError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} at {}", self.message, self.position)
+  }
+}
+
+/// Gives the value of an integer constant as the lexer spells it, in
+/// decimal or with a $, % or & radix prefix, possibly negated.
+pub fn integer_value(text: &str) -> Option<i32> {
+  if let Some(text) = text.strip_prefix('-') {
+    return integer_value(text).and_then(|value| value.checked_neg());
+  }
+  let radix = match text.chars().next() {
+    Some('$') => 16,
+    Some('%') => 2,
+    Some('&') => 8,
+    _ => return text.parse().ok(),
   };
+  i32::from_str_radix(&text[1..], radix).ok()
 }
 
 #[derive(Clone)]
 pub struct Lexer {
-  text: String,
+  chars: Vec<char>,
+  // The index of the current character, and its byte offset
   position: usize,
+  offset: usize,
   current_char: Option<char>,
+  line: usize,
+  column: usize,
+  token_position: Position,
+  token_span: Span,
+  dialect: Dialect,
+  // Whether whitespace and comments are returned as tokens
+  keep_trivia: bool,
+  // Set once the iterator has returned EOF or an error
+  finished: bool,
 }
 
 impl Lexer {
   pub fn new(text: &str) -> Self {
+    Lexer::with_dialect(text, Dialect::Iso)
+  }
+  /// Creates a lexer following the identifier rules of the given
+  /// dialect; only Delphi allows letters outside ASCII in identifiers.
+  pub fn with_dialect(text: &str, dialect: Dialect) -> Self {
     let chars: Vec<char> = text.chars().collect();
     Lexer {
-      text: text.to_string(),
+      current_char: chars.first().cloned(),
+      chars,
       position: 0,
-      current_char: Some(chars[0]),
+      offset: 0,
+      line: 1,
+      column: 1,
+      token_position: Position { line: 1, column: 1 },
+      token_span: Span::default(),
+      dialect,
+      keep_trivia: false,
+      finished: false,
     }
   }
+  /// Returns whitespace and comments as tokens too, rather than
+  /// skipping them, so the source text can be rebuilt from the tokens.
+  pub fn set_keep_trivia(&mut self, keep_trivia: bool) {
+    self.keep_trivia = keep_trivia;
+  }
+  /// Returns where the most recently read token starts.
+  pub fn token_position(&self) -> Position {
+    self.token_position
+  }
+  /// Returns the extent of the most recently read token.
+  pub fn token_span(&self) -> Span {
+    self.token_span
+  }
   /// Returns an option to the character following
   /// the current token.
   pub fn peek(&self) -> Option<char> {
-    let position = self.position + 1;
-    if position > self.text.len() - 1 {
-      None
-    } else {
-      Some(self.text.as_bytes()[position] as char)
-    }
+    self.chars.get(self.position + 1).cloned()
   }
   /// Advances the lexer position within the input text,
   /// setting the `current_char` to value found at that
   /// location.
   fn advance(&mut self) {
+    match self.current_char {
+      Some('\n') => {
+        self.line += 1;
+        self.column = 1;
+      }
+      _ => self.column += 1,
+    }
+    if let Some(char) = self.current_char {
+      self.offset += char.len_utf8();
+    }
     self.position += 1;
-    if self.position > self.text.len() - 1 {
-      self.current_char = None
-    } else {
-      self.current_char = Some(self.text.as_bytes()[self.position] as char)
+    self.current_char = self.chars.get(self.position).cloned();
+  }
+  /// Returns true if the character may appear in an identifier.
+  fn is_identifier_char(&self, char: char) -> bool {
+    match self.dialect {
+      Dialect::Delphi => char.is_alphanumeric() || char == '_',
+      _ => char.is_ascii_alphanumeric() || char == '_',
     }
   }
-  fn skip_comment(&mut self) {
-    while self.current_char != Some('}') {
-      self.advance()
+  /// Returns true if the text from the current character onwards
+  /// starts with the given text.
+  fn at(&self, text: &str) -> bool {
+    text
+      .chars()
+      .enumerate()
+      .all(|(i, char)| self.chars.get(self.position + i) == Some(&char))
+  }
+  /// Returns an error at the start of the current token.
+  fn error(&self, message: String) -> LexError {
+    LexError {
+      message,
+      position: self.token_position,
+      span: Span {
+        end: self.offset,
+        char_end: self.position,
+        ..self.token_span
+      },
+    }
+  }
+  /// Reads a comment starting at the current character, opened and
+  /// closed by the given delimiters. Comments of the same kind may be
+  /// nested inside it.
+  fn comment(&mut self, open: &str, close: &str) -> Result<Token, LexError> {
+    let mut text = String::new();
+    let mut depth = 0;
+    loop {
+      let delimiter = if self.at(open) {
+        depth += 1;
+        open
+      } else if self.at(close) {
+        depth -= 1;
+        close
+      } else {
+        match self.current_char {
+          Some(char) => {
+            text.push(char);
+            self.advance();
+            continue;
+          }
+          None => return Err(self.error("Unterminated comment".to_string())),
+        }
+      };
+      text += delimiter;
+      delimiter.chars().for_each(|_| self.advance());
+      if depth == 0 {
+        return Ok(Comment(text));
+      }
     }
-    self.advance()
   }
-  fn skip_whitespace(&mut self) {
-    while self.current_char != None && self.current_char.unwrap().is_whitespace() {
-      self.advance()
+  /// Reads a comment running to the end of the line.
+  fn line_comment(&mut self) -> Token {
+    let mut text = String::new();
+    while let Some(char) = self.current_char.filter(|&c| c != '\n') {
+      text.push(char);
+      self.advance();
+    }
+    Comment(text)
+  }
+  fn whitespace(&mut self) -> Token {
+    let mut text = String::new();
+    while let Some(char) = self.current_char.filter(|c| c.is_whitespace()) {
+      text.push(char);
+      self.advance();
     }
+    Whitespace(text)
   }
   /// Handles identifiers and reserved keywords
-  fn id(&mut self) -> Option<Token> {
+  fn id(&mut self) -> Token {
     let mut result = String::new();
-    while self.current_char != None && self.current_char.unwrap().is_alphanumeric() {
-      result.push(self.current_char.unwrap());
+    while let Some(char) = self.current_char.filter(|&c| self.is_identifier_char(c)) {
+      result.push(char);
       self.advance();
     }
     let uppercase_result = result.to_uppercase();
-    if RESERVED_WORDS.contains_key(uppercase_result.as_str()) {
-      RESERVED_WORDS.get(uppercase_result.as_str()).cloned()
-    } else {
-      Some(Id(result))
+    match RESERVED_WORDS.get(uppercase_result.as_str()) {
+      Some(token) => token.clone(),
+      None => Id(result),
     }
   }
 
-  fn number(&mut self) -> Option<Token> {
+  /// Reads the digits of a number in the given radix.
+  fn digits(&mut self, radix: u32) -> String {
     let mut digits = String::new();
-    while self.current_char != None && self.current_char.unwrap().is_digit(10) {
-      digits.push(self.current_char.unwrap());
+    while let Some(char) = self.current_char.filter(|c| c.is_digit(radix)) {
+      digits.push(char);
       self.advance();
-
-      if self.current_char == Some('.') {
-        digits.push('.');
+    }
+    digits
+  }
+  /// Handles integer and real constants, where a real may have a
+  /// fraction, an exponent or both, as in 1.5, 2E10 or 1.5E-3.
+  fn number(&mut self) -> Result<Token, LexError> {
+    let mut digits = self.digits(10);
+    let mut real = false;
+    // A second period marks a subrange, as in 1..10
+    if self.current_char == Some('.') && self.peek() != Some('.') {
+      real = true;
+      digits.push('.');
+      self.advance();
+      digits += &self.digits(10);
+    }
+    if let Some(exponent) = self.current_char.filter(|&c| c == 'E' || c == 'e') {
+      real = true;
+      digits.push(exponent);
+      self.advance();
+      if let Some(sign) = self.current_char.filter(|&c| c == '+' || c == '-') {
+        digits.push(sign);
         self.advance();
-
-        while self.current_char != None && self.current_char.unwrap().is_digit(10) {
-          digits.push(self.current_char.unwrap());
-          self.advance();
-        }
-        return Some(RealConst(digits));
       }
+      let exponent = self.digits(10);
+      if exponent.is_empty() {
+        return Err(self.error(format!("Invalid real constant {}", digits)));
+      }
+      digits += &exponent;
+    }
+    if real {
+      return Ok(RealConst(digits));
+    }
+    match digits.parse::<i32>() {
+      Ok(_) => Ok(IntegerConst(digits)),
+      Err(_) => Err(self.error(format!("Integer constant {} is out of range", digits))),
+    }
+  }
+  /// Handles integer constants written in hexadecimal ($FF), binary
+  /// (%1010) or octal (&17), which keep their spelling; `integer_value`
+  /// gives their value.
+  fn radix_number(&mut self, radix: u32) -> Result<Token, LexError> {
+    let prefix = self.current_char.unwrap();
+    self.advance();
+    let digits = self.digits(radix);
+    match i32::from_str_radix(&digits, radix) {
+      Ok(_) => Ok(IntegerConst(format!("{}{}", prefix, digits))),
+      Err(_) if digits.is_empty() => {
+        Err(self.error(format!("Invalid integer constant {}", prefix)))
+      }
+      Err(_) => Err(self.error(format!(
+        "Integer constant {}{} is out of range",
+        prefix, digits
+      ))),
     }
-    Some(IntegerConst(digits))
-  }
-  pub fn get_next_token(&mut self) -> Option<Token> {
-    while self.current_char != None {
-      return match self.current_char.unwrap() {
-        char if char.is_whitespace() => {
-          self.skip_whitespace();
-          continue;
+  }
+  /// Handles quoted string constants, where a doubled quote
+  /// represents a single quote character.
+  fn string(&mut self) -> Result<Token, LexError> {
+    let mut result = String::new();
+    self.advance();
+    loop {
+      match self.current_char {
+        None => return Err(self.error(format!("Unterminated string constant '{}", result))),
+        Some('\'') if self.peek() == Some('\'') => {
+          result.push('\'');
+          self.advance();
+          self.advance();
         }
-        '{' => {
+        Some('\'') => {
           self.advance();
-          self.skip_comment();
-          continue;
+          return Ok(StringConst(result));
         }
-        char if char.is_digit(10) => self.number(),
-        '+' => {
+        Some(char) => {
+          result.push(char);
           self.advance();
-          Some(Plus)
         }
-        char if char.is_alphanumeric() => self.id(),
-        '_' if self.peek().unwrap().is_alphanumeric() => {
+      }
+    }
+  }
+  /// Reads the next token, along with where it lies in the text.
+  pub fn get_next_token(&mut self) -> Result<Token, LexError> {
+    let token = self.read_token();
+    self.token_span.end = self.offset;
+    self.token_span.char_end = self.position;
+    token
+  }
+  /// Marks the current character as the start of the next token.
+  fn start_token(&mut self) {
+    self.token_position = Position {
+      line: self.line,
+      column: self.column,
+    };
+    self.token_span.start = self.offset;
+    self.token_span.char_start = self.position;
+  }
+  fn read_token(&mut self) -> Result<Token, LexError> {
+    while let Some(char) = self.current_char {
+      self.start_token();
+      let token = match char {
+        char if char.is_whitespace() => self.whitespace(),
+        '{' => self.comment("{", "}")?,
+        '(' if self.peek() == Some('*') => self.comment("(*", "*)")?,
+        '/' if self.peek() == Some('/') => self.line_comment(),
+        char if char.is_ascii_digit() => self.number()?,
+        '$' => self.radix_number(16)?,
+        '%' => self.radix_number(2)?,
+        '&' => self.radix_number(8)?,
+        '+' => {
           self.advance();
-          self.id()
+          Plus
+        }
+        char if self.is_identifier_char(char) => self.id(),
+        char if char.is_alphabetic() => {
+          return Err(self.error(format!(
+            "Identifiers may only contain ASCII letters in this dialect, found {}",
+            char
+          )))
         }
         ':' if self.peek() == Some('=') => {
           self.advance();
           self.advance();
-          Some(Assign)
+          Assign
         }
         ':' => {
           self.advance();
-          Some(Colon)
+          Colon
         }
         ';' => {
           self.advance();
-          Some(Semi)
+          Semi
+        }
+        '\'' => self.string()?,
+        '.' if self.peek() == Some('.') => {
+          self.advance();
+          self.advance();
+          Range
         }
         '.' => {
           self.advance();
-          Some(Period)
+          Period
         }
         ',' => {
           self.advance();
-          Some(Comma)
+          Comma
         }
         '-' => {
           self.advance();
-          Some(Minus)
+          Minus
         }
         '*' => {
           self.advance();
-          Some(Multiply)
+          Multiply
         }
         '/' => {
           self.advance();
-          Some(RealDivision)
+          RealDivision
+        }
+        '=' => {
+          self.advance();
+          Equal
+        }
+        '<' if self.peek() == Some('>') => {
+          self.advance();
+          self.advance();
+          NotEqual
+        }
+        '<' if self.peek() == Some('=') => {
+          self.advance();
+          self.advance();
+          LessEqual
+        }
+        '<' => {
+          self.advance();
+          LessThan
+        }
+        '>' if self.peek() == Some('=') => {
+          self.advance();
+          self.advance();
+          GreaterEqual
+        }
+        '>' => {
+          self.advance();
+          GreaterThan
         }
         '(' => {
           self.advance();
-          Some(LParen)
+          LParen
+        }
+        '^' => {
+          self.advance();
+          Caret
         }
         ')' => {
           self.advance();
-          Some(RParen)
+          RParen
+        }
+        '[' => {
+          self.advance();
+          LBracket
+        }
+        ']' => {
+          self.advance();
+          RBracket
         }
-        unknown => panic!("Unknown token found: {}", unknown),
+        unknown => return Err(self.error(format!("Unknown token found: {}", unknown))),
       };
+      if self.keep_trivia || !token.is_trivia() {
+        return Ok(token);
+      }
+    }
+    self.start_token();
+    Ok(Token::EOF)
+  }
+}
+
+/// Yields every token up to and including EOF, then stops. Reading
+/// stops early at the first error.
+impl Iterator for Lexer {
+  type Item = Result<SpannedToken, LexError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.finished {
+      return None;
     }
-    Some(Token::EOF)
+    let token = self.get_next_token();
+    self.finished = token.as_ref().map_or(true, |token| *token == Token::EOF);
+    Some(token.map(|token| SpannedToken {
+      token,
+      position: self.token_position,
+      span: self.token_span,
+    }))
   }
 }
 
@@ -178,7 +523,7 @@ mod tests {
 
   #[test]
   fn add_two_single_digit_numbers() {
-    let mut lexer = Lexer::new("4 + 7".into());
+    let mut lexer = Lexer::new("4 + 7");
 
     assert_eq!(lexer.get_next_token().unwrap(), IntegerConst("4".into()));
     assert_eq!(lexer.get_next_token().unwrap(), Plus);
@@ -188,7 +533,7 @@ mod tests {
 
   #[test]
   fn multiply_two_single_digit_numbers() {
-    let mut lexer = Lexer::new("4 * 7".into());
+    let mut lexer = Lexer::new("4 * 7");
 
     assert_eq!(lexer.get_next_token().unwrap(), IntegerConst("4".into()));
     assert_eq!(lexer.get_next_token().unwrap(), Multiply);
@@ -198,7 +543,7 @@ mod tests {
 
   #[test]
   fn divide_two_integers() {
-    let mut lexer = Lexer::new("14 DIV 7".into());
+    let mut lexer = Lexer::new("14 DIV 7");
 
     assert_eq!(lexer.get_next_token().unwrap(), IntegerConst("14".into()));
     assert_eq!(lexer.get_next_token().unwrap(), IntegerDivision);
@@ -208,7 +553,7 @@ mod tests {
 
   #[test]
   fn divide_two_real_numbers() {
-    let mut lexer = Lexer::new("14.0 / 7.0".into());
+    let mut lexer = Lexer::new("14.0 / 7.0");
 
     assert_eq!(lexer.get_next_token().unwrap(), RealConst("14.0".into()));
     assert_eq!(lexer.get_next_token().unwrap(), RealDivision);
@@ -218,7 +563,7 @@ mod tests {
 
   #[test]
   fn multiply_two_real_numbers() {
-    let mut lexer = Lexer::new("4.125 * 3.3333".into());
+    let mut lexer = Lexer::new("4.125 * 3.3333");
 
     assert_eq!(lexer.get_next_token().unwrap(), RealConst("4.125".into()));
     assert_eq!(lexer.get_next_token().unwrap(), Multiply);
@@ -228,7 +573,7 @@ mod tests {
 
   #[test]
   fn lex_expression_in_parens() {
-    let mut lexer = Lexer::new("(4 - 7)".into());
+    let mut lexer = Lexer::new("(4 - 7)");
 
     assert_eq!(lexer.get_next_token().unwrap(), LParen);
     assert_eq!(lexer.get_next_token().unwrap(), IntegerConst("4".into()));
@@ -245,7 +590,7 @@ mod tests {
     PROGRAM Part10;
     VAR number : INTEGER;
     BEGIN
-    END."#.into(),
+    END."#,
     );
 
     let keywords = vec![
@@ -270,7 +615,7 @@ mod tests {
 
   #[test]
   fn lex_assignment() {
-    let mut lexer = Lexer::new("a := 10;".into());
+    let mut lexer = Lexer::new("a := 10;");
 
     assert_eq!(lexer.get_next_token().unwrap(), Id("a".to_string()));
     assert_eq!(lexer.get_next_token().unwrap(), Assign);
@@ -279,9 +624,330 @@ mod tests {
     assert_eq!(lexer.get_next_token().unwrap(), EOF);
   }
 
+  #[test]
+  fn lex_array_type() {
+    let mut lexer = Lexer::new("ARRAY [1..10] OF CHAR");
+
+    assert_eq!(lexer.get_next_token().unwrap(), Array);
+    assert_eq!(lexer.get_next_token().unwrap(), LBracket);
+    assert_eq!(lexer.get_next_token().unwrap(), IntegerConst("1".into()));
+    assert_eq!(lexer.get_next_token().unwrap(), Range);
+    assert_eq!(lexer.get_next_token().unwrap(), IntegerConst("10".into()));
+    assert_eq!(lexer.get_next_token().unwrap(), RBracket);
+    assert_eq!(lexer.get_next_token().unwrap(), Of);
+    assert_eq!(lexer.get_next_token().unwrap(), Char);
+    assert_eq!(lexer.get_next_token().unwrap(), EOF);
+  }
+
+  #[test]
+  fn lex_relational_operators() {
+    let mut lexer = Lexer::new("= <> < > <= >=");
+
+    assert_eq!(lexer.get_next_token().unwrap(), Equal);
+    assert_eq!(lexer.get_next_token().unwrap(), NotEqual);
+    assert_eq!(lexer.get_next_token().unwrap(), LessThan);
+    assert_eq!(lexer.get_next_token().unwrap(), GreaterThan);
+    assert_eq!(lexer.get_next_token().unwrap(), LessEqual);
+    assert_eq!(lexer.get_next_token().unwrap(), GreaterEqual);
+    assert_eq!(lexer.get_next_token().unwrap(), EOF);
+  }
+
+  #[test]
+  fn lex_string_constants() {
+    let mut lexer = Lexer::new("'a' 'it''s'");
+
+    assert_eq!(lexer.get_next_token().unwrap(), StringConst("a".into()));
+    assert_eq!(lexer.get_next_token().unwrap(), StringConst("it's".into()));
+    assert_eq!(lexer.get_next_token().unwrap(), EOF);
+  }
+
   #[test]
   fn lex_comment() {
-    let mut lexer = Lexer::new(r#"{ This is how you write a comment }"#.into());
+    let mut lexer = Lexer::new(r#"{ This is how you write a comment }"#);
     assert_eq!(lexer.get_next_token().unwrap(), EOF);
   }
+
+  #[test]
+  fn lex_comment_styles() {
+    let mut lexer = Lexer::new(
+      "(* old { style } *) a { outer { nested } still outer } // to the end\n(* (* b *) *) c",
+    );
+    assert_eq!(lexer.get_next_token().unwrap(), Id("a".to_string()));
+    assert_eq!(lexer.get_next_token().unwrap(), Id("c".to_string()));
+    assert_eq!(lexer.get_next_token().unwrap(), EOF);
+  }
+
+  #[test]
+  fn reject_unterminated_comment() {
+    let mut lexer = Lexer::new("a\n  (* never { closed } ");
+    lexer.get_next_token().unwrap();
+    let error = lexer.get_next_token().unwrap_err();
+
+    assert_eq!(error.message, "Unterminated comment");
+    assert_eq!(Position { line: 2, column: 3 }, error.position);
+    assert_eq!((4, 24), (error.span.start, error.span.end));
+    assert_eq!(
+      "Unterminated comment at line 2, column 3",
+      error.to_string()
+    );
+  }
+
+  #[test]
+  fn lex_real_exponents() {
+    let mut lexer = Lexer::new("1.5E-3 2e10 6.02E+23 1..2");
+
+    assert_eq!(lexer.get_next_token().unwrap(), RealConst("1.5E-3".into()));
+    assert_eq!(lexer.get_next_token().unwrap(), RealConst("2e10".into()));
+    assert_eq!(
+      lexer.get_next_token().unwrap(),
+      RealConst("6.02E+23".into())
+    );
+    assert_eq!(lexer.get_next_token().unwrap(), IntegerConst("1".into()));
+    assert_eq!(lexer.get_next_token().unwrap(), Range);
+    assert_eq!(lexer.get_next_token().unwrap(), IntegerConst("2".into()));
+    assert_eq!(lexer.get_next_token().unwrap(), EOF);
+  }
+
+  #[test]
+  fn lex_hex_binary_and_octal_integers() {
+    let mut lexer = Lexer::new("$FF $7fffffff %1010 &17");
+
+    assert_eq!(lexer.get_next_token().unwrap(), IntegerConst("$FF".into()));
+    assert_eq!(
+      lexer.get_next_token().unwrap(),
+      IntegerConst("$7fffffff".into())
+    );
+    assert_eq!(lexer.get_next_token().unwrap(), IntegerConst("%1010".into()));
+    assert_eq!(lexer.get_next_token().unwrap(), IntegerConst("&17".into()));
+    assert_eq!(lexer.get_next_token().unwrap(), EOF);
+  }
+
+  #[test]
+  fn integer_values_follow_the_radix_prefix() {
+    assert_eq!(integer_value("42"), Some(42));
+    assert_eq!(integer_value("$FF"), Some(255));
+    assert_eq!(integer_value("$7fffffff"), Some(i32::MAX));
+    assert_eq!(integer_value("%1010"), Some(10));
+    assert_eq!(integer_value("-&17"), Some(-15));
+    assert_eq!(integer_value("2147483648"), None);
+  }
+
+  #[test]
+  fn reject_hex_without_digits() {
+    let error = Lexer::new("$G").get_next_token().unwrap_err();
+    assert_eq!(error.message, "Invalid integer constant $");
+  }
+
+  #[test]
+  fn reject_integers_out_of_range() {
+    let error = Lexer::new("99999999999").get_next_token().unwrap_err();
+    assert_eq!(error.message, "Integer constant 99999999999 is out of range");
+    let error = Lexer::new("$FFFFFFFFFF").get_next_token().unwrap_err();
+    assert_eq!(error.message, "Integer constant $FFFFFFFFFF is out of range");
+    assert_eq!(
+      Lexer::new("2147483647").get_next_token().unwrap(),
+      IntegerConst("2147483647".into())
+    );
+  }
+
+  #[test]
+  fn lex_underscores_in_identifiers() {
+    let mut lexer = Lexer::new("_a an_int trailing_ __");
+
+    for name in &["_a", "an_int", "trailing_", "__"] {
+      assert_eq!(lexer.get_next_token().unwrap(), Id(name.to_string()));
+    }
+    assert_eq!(lexer.get_next_token().unwrap(), EOF);
+  }
+
+  #[test]
+  fn lex_empty_input() {
+    let mut lexer = Lexer::new("");
+    assert_eq!(lexer.get_next_token().unwrap(), EOF);
+    assert_eq!(Span::default(), lexer.token_span());
+  }
+
+  #[test]
+  fn lex_unicode_strings_and_comments() {
+    let mut lexer = Lexer::new("{ ünïcödé } 'héllo → wörld' x");
+
+    assert_eq!(
+      lexer.get_next_token().unwrap(),
+      StringConst("héllo → wörld".into())
+    );
+    assert_eq!(
+      Position {
+        line: 1,
+        column: 13
+      },
+      lexer.token_position()
+    );
+    assert_eq!(lexer.get_next_token().unwrap(), Id("x".into()));
+    assert_eq!(
+      Position {
+        line: 1,
+        column: 29
+      },
+      lexer.token_position()
+    );
+    assert_eq!(lexer.get_next_token().unwrap(), EOF);
+  }
+
+  #[test]
+  fn lex_token_spans() {
+    let text = "é := 'ü'";
+    let mut lexer = Lexer::with_dialect(text, Dialect::Delphi);
+
+    let mut spans = vec![];
+    while lexer.get_next_token().unwrap() != EOF {
+      spans.push(lexer.token_span());
+    }
+    let slices: Vec<&str> = spans
+      .iter()
+      .map(|span| &text[span.start..span.end])
+      .collect();
+    assert_eq!(vec!["é", ":=", "'ü'"], slices);
+    assert_eq!((2, 4), (spans[1].char_start, spans[1].char_end));
+    assert_eq!((3, 5), (spans[1].start, spans[1].end));
+  }
+
+  #[test]
+  fn reject_unicode_identifier_in_iso_pascal() {
+    let mut lexer = Lexer::new("größe");
+    assert_eq!(lexer.get_next_token().unwrap(), Id("gr".into()));
+    let error = lexer.get_next_token().unwrap_err();
+    assert!(error
+      .message
+      .starts_with("Identifiers may only contain ASCII letters"));
+    assert_eq!(Position { line: 1, column: 3 }, error.position);
+  }
+
+  #[test]
+  fn lex_unicode_identifiers_in_delphi() {
+    let mut lexer = Lexer::with_dialect("größe := 1", Dialect::Delphi);
+    assert_eq!(lexer.get_next_token().unwrap(), Id("größe".into()));
+    assert_eq!(lexer.get_next_token().unwrap(), Assign);
+  }
+
+  #[test]
+  fn lex_token_positions() {
+    let mut lexer = Lexer::new("BEGIN\n  x := 1\nEND.");
+
+    lexer.get_next_token().unwrap();
+    assert_eq!(Position { line: 1, column: 1 }, lexer.token_position());
+    lexer.get_next_token().unwrap();
+    assert_eq!(Position { line: 2, column: 3 }, lexer.token_position());
+    lexer.get_next_token().unwrap();
+    assert_eq!(Position { line: 2, column: 5 }, lexer.token_position());
+    lexer.get_next_token().unwrap();
+    lexer.get_next_token().unwrap();
+    assert_eq!(Position { line: 3, column: 1 }, lexer.token_position());
+  }
+
+  #[test]
+  fn iterate_spanned_tokens() {
+    let tokens: Vec<SpannedToken> = Lexer::new("x :=\n 1").map(Result::unwrap).collect();
+
+    assert_eq!(
+      tokens,
+      vec![
+        SpannedToken {
+          token: Id("x".into()),
+          position: Position { line: 1, column: 1 },
+          span: Span {
+            start: 0,
+            end: 1,
+            char_start: 0,
+            char_end: 1
+          },
+        },
+        SpannedToken {
+          token: Assign,
+          position: Position { line: 1, column: 3 },
+          span: Span {
+            start: 2,
+            end: 4,
+            char_start: 2,
+            char_end: 4
+          },
+        },
+        SpannedToken {
+          token: IntegerConst("1".into()),
+          position: Position { line: 2, column: 2 },
+          span: Span {
+            start: 6,
+            end: 7,
+            char_start: 6,
+            char_end: 7
+          },
+        },
+        SpannedToken {
+          token: EOF,
+          position: Position { line: 2, column: 3 },
+          span: Span {
+            start: 7,
+            end: 7,
+            char_start: 7,
+            char_end: 7
+          },
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn iteration_stops_at_the_first_error() {
+    let mut lexer = Lexer::new("a ? b");
+
+    assert_eq!(lexer.next().unwrap().unwrap().token, Id("a".into()));
+    assert_eq!(
+      lexer.next().unwrap().unwrap_err().message,
+      "Unknown token found: ?"
+    );
+    assert!(lexer.next().is_none());
+  }
+
+  #[test]
+  fn keep_whitespace_and_comments_as_trivia() {
+    let text = "x := 1; { one }\n  // two\n(* three *) y";
+    let mut lexer = Lexer::new(text);
+    lexer.set_keep_trivia(true);
+    let tokens: Vec<Token> = lexer.map(|token| token.unwrap().token).collect();
+
+    assert_eq!(
+      tokens,
+      vec![
+        Id("x".into()),
+        Whitespace(" ".into()),
+        Assign,
+        Whitespace(" ".into()),
+        IntegerConst("1".into()),
+        Semi,
+        Whitespace(" ".into()),
+        Comment("{ one }".into()),
+        Whitespace("\n  ".into()),
+        Comment("// two".into()),
+        Whitespace("\n".into()),
+        Comment("(* three *)".into()),
+        Whitespace(" ".into()),
+        Id("y".into()),
+        EOF,
+      ]
+    );
+  }
+
+  #[test]
+  fn trivia_spans_cover_the_whole_text() {
+    let text = "BEGIN { größe } x := 'ü' END.";
+    let mut lexer = Lexer::new(text);
+    lexer.set_keep_trivia(true);
+    let rebuilt: String = lexer
+      .map(|token| {
+        let span = token.unwrap().span;
+        text[span.start..span.end].to_string()
+      })
+      .collect();
+
+    assert_eq!(rebuilt, text);
+  }
 }
@@ -4,54 +4,162 @@ use std::fmt;
 pub enum Token {
   Program,
   Procedure,
+  Function,
+  Label,
+  Goto,
+  Forward,
+  Try,
+  Except,
+  Finally,
+  Raise,
   Begin,
   End,
   Var,
+  Type,
+  For,
+  To,
+  Downto,
+  Do,
+  Case,
+  Else,
+  Otherwise,
+  If,
+  Then,
+  Set,
+  In,
+  While,
+  Record,
+  Nil,
   Integer,
   Real,
+  Boolean,
+  Char,
+  Array,
+  Of,
+  True,
+  False,
   IntegerConst(String),
   RealConst(String),
+  StringConst(String),
   Id(String),
   Colon,
   Comma,
   Semi,
   Period,
+  Range,
   Plus,
   Minus,
   Multiply,
   IntegerDivision,
   RealDivision,
+  Equal,
+  NotEqual,
+  LessThan,
+  GreaterThan,
+  LessEqual,
+  GreaterEqual,
   LParen,
   RParen,
+  LBracket,
+  RBracket,
+  Caret,
   Assign,
+  // Whitespace and comments, kept only when the lexer is asked to
+  Whitespace(String),
+  Comment(String),
   EOF,
 }
 
+impl Token {
+  /// Returns true for the operators that compare two values.
+  pub fn is_relational(&self) -> bool {
+    matches!(
+      self,
+      Token::Equal
+        | Token::NotEqual
+        | Token::LessThan
+        | Token::GreaterThan
+        | Token::LessEqual
+        | Token::GreaterEqual
+        | Token::In
+    )
+  }
+  /// Returns true for the whitespace and comment tokens.
+  pub fn is_trivia(&self) -> bool {
+    matches!(self, Token::Whitespace(_) | Token::Comment(_))
+  }
+}
+
 impl<'a> fmt::Display for Token {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if let Token::StringConst(value) = self {
+      return write!(f, "'{}'", value.replace("'", "''"));
+    }
     let output = match self {
       Token::Program => "PROGRAM",
       Token::Procedure => "PROCEDURE",
+      Token::Function => "FUNCTION",
+      Token::Label => "LABEL",
+      Token::Goto => "GOTO",
+      Token::Forward => "FORWARD",
+      Token::Try => "TRY",
+      Token::Except => "EXCEPT",
+      Token::Finally => "FINALLY",
+      Token::Raise => "RAISE",
       Token::Begin => "BEGIN",
       Token::End => "END",
       Token::Var => "VAR",
+      Token::Type => "TYPE",
+      Token::For => "FOR",
+      Token::To => "TO",
+      Token::Downto => "DOWNTO",
+      Token::Do => "DO",
+      Token::Case => "CASE",
+      Token::Else => "ELSE",
+      Token::Otherwise => "OTHERWISE",
+      Token::If => "IF",
+      Token::Then => "THEN",
+      Token::Set => "SET",
+      Token::In => "IN",
+      Token::While => "WHILE",
+      Token::Record => "RECORD",
+      Token::Nil => "NIL",
       Token::Integer => "INTEGER",
       Token::Real => "REAL",
+      Token::Boolean => "BOOLEAN",
+      Token::Char => "CHAR",
+      Token::Array => "ARRAY",
+      Token::Of => "OF",
+      Token::True => "TRUE",
+      Token::False => "FALSE",
       Token::IntegerConst(value) => value,
       Token::RealConst(value) => value,
+      Token::StringConst(value) => value,
       Token::Id(name) => name,
       Token::Colon => ":",
       Token::Comma => ",",
       Token::Semi => ";",
       Token::Period => ".",
+      Token::Range => "..",
       Token::Plus => "+",
       Token::Minus => "-",
       Token::Multiply => "*",
       Token::IntegerDivision => "DIV",
       Token::RealDivision => "/",
+      Token::Equal => "=",
+      Token::NotEqual => "<>",
+      Token::LessThan => "<",
+      Token::GreaterThan => ">",
+      Token::LessEqual => "<=",
+      Token::GreaterEqual => ">=",
       Token::LParen => "(",
       Token::RParen => ")",
+      Token::LBracket => "[",
+      Token::RBracket => "]",
+      Token::Caret => "^",
       Token::Assign => ":=",
+      Token::Whitespace(text) => text,
+      Token::Comment(text) => text,
       Token::EOF => "EOF",
     };
     write!(f, "{}", output)
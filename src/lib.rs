@@ -1,14 +1,12 @@
 #[macro_use]
 extern crate mopa;
 
-#[macro_use]
-extern crate lazy_static;
-
 extern crate regex;
 
 mod ast;
+pub mod formatter;
 pub mod interpreter;
-mod lexer;
-mod parser;
-mod symbols;
+pub mod lexer;
+pub mod parser;
+pub mod symbols;
 pub mod utils;
@@ -1,53 +1,341 @@
-#[macro_use]
-extern crate mopa;
+extern crate basic_interpreter;
 
-#[macro_use]
-extern crate lazy_static;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::panic;
+use std::process;
 
-extern crate regex;
+use basic_interpreter::formatter::{self, Style};
+use basic_interpreter::interpreter::{is_complete, AstFormat, Interpreter, Repl};
+use basic_interpreter::lexer::Lexer;
+use basic_interpreter::parser;
+use basic_interpreter::utils::number::Number::Int;
 
-mod ast;
-mod interpreter;
-mod lexer;
-mod parser;
-mod symbols;
-mod utils;
+const USAGE: &str = "\
+Usage: basic-interpreter [<command>] [<options>] <file>...
+       basic-interpreter repl
 
-use std::env;
-use std::error;
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::BufReader;
+Commands:
+  run      Check and run each program, the default
+  check    Parse and check each program without running it
+  tokens   Print the tokens of each file with their positions
+  ast      Print the syntax tree of each program
+  symbols  Check each program and print its scopes and their symbols
+  fmt      Print each program laid out in a consistent style
+  repl     Enter declarations, statements and expressions one at a time,
+           with :help listing the commands of the session
+
+Options:
+  --check-leaks               run: list the blocks allocated by New that
+                              the program never disposed
+  --format=json|sexpr|dot     ast: the notation of the tree (json); only
+                              json gives each node's source span
+  --format=text|json|sexpr|dot
+                              symbols: the notation of the scopes (text)
+  --keywords=upper|lower, --indent=<width>,
+  --begin=same-line|next-line, --align-colons
+                              fmt: the layout, as for pasfmt
+  --dump-ast=json|sexpr       the same as ast --format=json|sexpr
+  --symbols[=text|json]       the same as symbols --format=text|json
+  --dot=ast|scopes            the same as ast or symbols --format=dot
+  -h, --help                  Print this help
+
+A file named - is read from standard input. Graphs in the dot notation
+are drawn by Graphviz, with `dot -Tpng`.
+
+Exit codes:
+  0  Every file succeeded
+  1  A program failed with a runtime error
+  2  The command line was wrong, or a file couldn't be read
+  3  A file has a syntax error
+  4  A program has a semantic error, such as an undeclared variable
+A program that calls Halt(code) exits with that code, which must be
+from 0 to 255; any other code is a runtime error, exiting with 1.";
+
+const COMMANDS: [&str; 6] = ["run", "check", "tokens", "ast", "symbols", "fmt"];
+
+/// The notations a syntax tree or a tree of scopes can be printed in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Notation {
+  Text,
+  Json,
+  SExpr,
+  Dot,
+}
+
+/// What is done with each file.
+enum Command {
+  Run { check_leaks: bool },
+  Check,
+  Tokens,
+  Ast(Notation),
+  Symbols(Notation),
+  Fmt(Style),
+}
+
+/// Why a file failed, which decides the exit code.
+enum Failure {
+  Unreadable(String),
+  Syntax(String),
+  Semantic(String),
+  Runtime(String),
+  Halt(i32),
+}
 
-use utils::number::Number::Nil;
+impl Failure {
+  fn exit_code(&self) -> i32 {
+    match self {
+      Failure::Runtime(_) => 1,
+      Failure::Unreadable(_) => 2,
+      Failure::Syntax(_) => 3,
+      Failure::Semantic(_) => 4,
+      Failure::Halt(code) => *code,
+    }
+  }
+}
 
-use interpreter::Interpreter;
+/// Reads a notation given with --format for the ast or symbols command.
+fn parse_notation(command: &str, format: &str) -> Result<Notation, String> {
+  match format {
+    "text" if command == "symbols" => Ok(Notation::Text),
+    "json" => Ok(Notation::Json),
+    "sexpr" => Ok(Notation::SExpr),
+    "dot" => Ok(Notation::Dot),
+    format => Err(format!("Unknown format for {}: {}", command, format)),
+  }
+}
 
-fn read_from_file(filename: &str) -> std::io::Result<String> {
-  let file = File::open(filename)?;
-  let mut buf_reader = BufReader::new(file);
-  let mut contents = String::new();
-  buf_reader.read_to_string(&mut contents)?;
-  Ok(contents)
+/// Reads the options that printed a syntax tree or scopes before there
+/// were commands, returning the command each stands for and its format.
+fn alias(arg: &str) -> Option<(&'static str, &str)> {
+  if let Some(format) = arg.strip_prefix("--dump-ast=") {
+    Some(("ast", format))
+  } else if arg == "--symbols" {
+    Some(("symbols", "text"))
+  } else if let Some(format) = arg.strip_prefix("--symbols=") {
+    Some(("symbols", format))
+  } else {
+    match arg {
+      "--dot=ast" => Some(("ast", "dot")),
+      "--dot=scopes" => Some(("symbols", "dot")),
+      _ => None,
+    }
+  }
+}
+
+/// Reads the command, its options and the files it applies to from the
+/// arguments. A first argument that isn't a command is taken as a file
+/// to run, unless an option such as --dump-ast names another command.
+fn parse_args(args: &[String]) -> Result<(Command, Vec<String>), String> {
+  let (mut command, args) = match args.first() {
+    Some(command) if COMMANDS.contains(&command.as_str()) => (command.as_str(), &args[1..]),
+    _ => ("run", args),
+  };
+  let mut check_leaks = false;
+  let mut notation = None;
+  let mut style = Style::default();
+  let mut filenames = vec![];
+  for arg in args {
+    match command {
+      _ if arg == "-" || !arg.starts_with('-') => filenames.push(arg.clone()),
+      "run" if arg == "--check-leaks" => check_leaks = true,
+      "run" if alias(arg).is_some() => {
+        let (aliased, format) = alias(arg).unwrap();
+        command = aliased;
+        notation = Some(parse_notation(command, format)?);
+      }
+      "ast" | "symbols" if arg.starts_with("--format=") => {
+        notation = Some(parse_notation(command, &arg["--format=".len()..])?);
+      }
+      "fmt" => formatter::set_option(&mut style, arg)?,
+      _ => return Err(format!("Unknown option for {}: {}", command, arg)),
+    }
+  }
+  if filenames.is_empty() {
+    return Err("No files given".to_string());
+  }
+  let command = match command {
+    "run" => Command::Run { check_leaks },
+    "check" => Command::Check,
+    "tokens" => Command::Tokens,
+    "ast" => Command::Ast(notation.unwrap_or(Notation::Json)),
+    "symbols" => Command::Symbols(notation.unwrap_or(Notation::Text)),
+    _ => Command::Fmt(style),
+  };
+  Ok((command, filenames))
+}
+
+fn read_source(filename: &str) -> Result<String, Failure> {
+  let source = if filename == "-" {
+    let mut source = String::new();
+    io::stdin().read_to_string(&mut source).map(|_| source)
+  } else {
+    fs::read_to_string(filename)
+  };
+  source.map_err(|err| Failure::Unreadable(err.to_string()))
+}
+
+/// Runs something that parses a program, turning the panic the parser
+/// raises on a syntax error into a failure.
+fn parse<T, F: FnOnce() -> T + panic::UnwindSafe>(parse: F) -> Result<T, Failure> {
+  let hook = panic::take_hook();
+  panic::set_hook(Box::new(|_| {}));
+  let result = parser::recover(parse);
+  panic::set_hook(hook);
+  result.map_err(Failure::Syntax)
+}
+
+/// Prints each token of the source with the line and column it starts
+/// at, stopping at the first text that can't be read as a token.
+fn print_tokens(source: &str) -> Result<(), Failure> {
+  for token in Lexer::new(source) {
+    let token = token.map_err(|err| Failure::Syntax(err.to_string()))?;
+    println!(
+      "{:>4}:{:<4} {:?}",
+      token.position.line, token.position.column, token.token
+    );
+  }
+  Ok(())
+}
+
+fn process_file(command: &Command, source: &str) -> Result<(), Failure> {
+  if let Command::Tokens = command {
+    return print_tokens(source);
+  }
+  if let Command::Fmt(style) = command {
+    print!("{}", parse(|| formatter::format(source, style))?);
+    return Ok(());
+  }
+  let mut interpreter = parse(|| Interpreter::new(source))?;
+  match command {
+    Command::Run { check_leaks } => {
+      interpreter.check().map_err(Failure::Semantic)?;
+      interpreter.set_leak_check(*check_leaks);
+      let result = interpreter.interpret();
+      if let Some(report) = interpreter.leak_report() {
+        println!("{}", report);
+      }
+      match result {
+        Ok(Int(code)) if code != 0 => return Err(Failure::Halt(code)),
+        Ok(_) => {}
+        Err(msg) => return Err(Failure::Runtime(msg)),
+      }
+    }
+    Command::Check => {
+      interpreter.check().map_err(Failure::Semantic)?;
+    }
+    Command::Ast(Notation::Dot) => print!("{}", interpreter.ast_graph()),
+    Command::Ast(Notation::SExpr) => println!("{}", interpreter.dump_ast(AstFormat::SExpr)),
+    Command::Ast(_) => println!("{}", interpreter.dump_ast(AstFormat::Json)),
+    Command::Symbols(notation) => {
+      let symbols = match notation {
+        Notation::Text => interpreter.check().map(|scope| scope.to_string()),
+        Notation::Json => interpreter
+          .dump_symbols(AstFormat::Json)
+          .map(|json| json + "\n"),
+        Notation::SExpr => interpreter
+          .dump_symbols(AstFormat::SExpr)
+          .map(|sexpr| sexpr + "\n"),
+        Notation::Dot => interpreter.scope_graph(),
+      };
+      print!("{}", symbols.map_err(Failure::Semantic)?);
+    }
+    Command::Tokens | Command::Fmt(_) => unreachable!(),
+  }
+  Ok(())
+}
+
+/// Reads declarations, statements and expressions from stdin, printing
+/// what each gives, until :quit or the end of the input. An entry is read
+/// on as many lines as it takes to finish it.
+fn repl() {
+  let mut repl = Repl::new();
+  let mut entry = String::new();
+  let prompt = |entry: &str| {
+    print!(
+      "{}",
+      if entry.is_empty() {
+        "pascal> "
+      } else {
+        "   ...> "
+      }
+    );
+    io::stdout().flush().unwrap();
+  };
+  prompt(&entry);
+  for line in io::stdin().lock().lines() {
+    entry += &line.unwrap_or_default();
+    entry.push('\n');
+    if !is_complete(&entry) {
+      prompt(&entry);
+      continue;
+    }
+    match entry.trim() {
+      ":quit" | ":q" => return,
+      _ => match repl.eval(&entry) {
+        Ok(output) if output.is_empty() => (),
+        Ok(output) => println!("{}", output),
+        Err(msg) => println!("!!!Error!!!: {}", msg),
+      },
+    }
+    entry.clear();
+    prompt(&entry);
+  }
+  println!();
 }
 
 ///
-///  Read and Interpret a Pascal file.
+///  Read and Interpret Pascal files.
 ///
 /// Use:
-///   cargo run <filename>
+///   cargo run -- [<command>] [<options>] <file>...
 ///
-fn main() -> Result<(), Box<error::Error>> {
-  let args: Vec<String> = env::args().collect();
-  let filename = &args[1];
-  let source = read_from_file(&filename)?;
-
-  println!("Processing file: {}", filename);
-  let mut interpreter = Interpreter::new(source.as_str());
-  match interpreter.interpret() {
-    Ok(Nil) => println!("Success!"),
-    Ok(value) => println!("Program terminated with value: {:?}", value),
-    Err(msg) => println!("!!!Error!!!: {}", msg),
+/// The commands run programs, check them without running them, or
+/// print their tokens, syntax trees, scopes or formatted source. See
+/// `--help` for the commands, their options and the exit codes.
+///
+/// Each file is processed in turn, even after one fails, with errors
+/// written to stderr after the name of the file. The exit code is that
+/// of the first file to fail.
+///
+fn main() {
+  let args: Vec<String> = env::args().skip(1).collect();
+  if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+    println!("{}", USAGE);
+    return;
   }
-  Ok(())
+  if args.first().map(String::as_str) == Some("repl") && args.len() == 1 {
+    repl();
+    return;
+  }
+  let (command, filenames) = match parse_args(&args) {
+    Ok(parsed) => parsed,
+    Err(msg) => {
+      eprintln!(
+        "{}\n{}\nRun with --help for the commands and their options.",
+        msg,
+        USAGE.lines().next().unwrap()
+      );
+      process::exit(2);
+    }
+  };
+  let mut exit_code = 0;
+  for filename in &filenames {
+    let result = read_source(filename).and_then(|source| process_file(&command, &source));
+    if let Err(failure) = result {
+      match &failure {
+        Failure::Unreadable(msg)
+        | Failure::Syntax(msg)
+        | Failure::Semantic(msg)
+        | Failure::Runtime(msg) => eprintln!("{}: {}", filename, msg),
+        Failure::Halt(_) => {}
+      }
+      if exit_code == 0 {
+        exit_code = failure.exit_code();
+      }
+    }
+  }
+  io::stdout().flush().unwrap();
+  process::exit(exit_code);
 }
@@ -2,295 +2,1201 @@ use lexer::token::Token;
 use lexer::token::Token::*;
 
 use ast::node::*;
-use lexer::Lexer;
+use interpreter::Dialect;
+use lexer::{integer_value, Lexer, Position, Span};
+use std::mem;
+use std::panic;
+use std::sync::Arc;
+
+type ParseResult<T> = Result<T, String>;
+
+// The handlers of a TRY .. EXCEPT block, and its ELSE branch
+type ExceptionBlock = (Vec<ExceptionHandler>, Option<Box<Node>>);
+
+/// Runs something that parses, returning the message of the panic the
+/// parser raises on a syntax error as an error. The panic is reported
+/// as usual, unless the panic hook has been replaced.
+pub fn recover<T, F: FnOnce() -> T + panic::UnwindSafe>(parse: F) -> Result<T, String> {
+  panic::catch_unwind(parse).map_err(|payload| {
+    payload
+      .downcast_ref::<String>()
+      .cloned()
+      .or_else(|| payload.downcast_ref::<&str>().map(|msg| msg.to_string()))
+      .unwrap_or_default()
+  })
+}
+
+/// The comments around a statement, a declaration, or the BEGIN or END
+/// of a block, kept so a formatter can write them back in the same places.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Comments {
+  // The comments on the lines before it
+  pub leading: Vec<String>,
+  // The comments following it on the line it ends
+  pub trailing: Vec<String>,
+}
 
 #[derive(Clone)]
 pub struct Parser {
   lexer: Lexer,
-  current_token: Option<Token>,
+  current_token: Token,
+  // The comments of each statement, declaration, BEGIN and closing
+  // END, in the order they were parsed, when the source is read with
+  // its comments
+  comments: Vec<Comments>,
+  // The comments read since the last of those started, each with
+  // whether it starts its own line
+  pending: Vec<(String, bool)>,
+  // The comments within expressions, each with the end of the token
+  // it follows
+  inline_comments: Vec<(usize, String)>,
+  // The number of expressions enclosing the current token
+  expression_depth: usize,
+  // The statement or declaration ended by the tokens consumed last
+  finished: Option<usize>,
+  // Where the current token and the token consumed before it are
+  current_span: Span,
+  previous_span: Span,
 }
 
 impl Parser {
   pub fn new(text: &str) -> Self {
-    let mut lexer = Lexer::new(&text);
-    let current_token = lexer.get_next_token();
-
+    Parser::with_dialect(text, Dialect::Iso)
+  }
+  pub fn with_dialect(text: &str, dialect: Dialect) -> Self {
+    Parser::read(Lexer::with_dialect(text, dialect))
+  }
+  /// Creates a parser that also keeps the comments in the source, which
+  /// `comments` returns once the program has been parsed.
+  pub fn with_comments(text: &str, dialect: Dialect) -> Self {
+    let mut lexer = Lexer::with_dialect(text, dialect);
+    lexer.set_keep_trivia(true);
+    Parser::read(lexer)
+  }
+  fn read(lexer: Lexer) -> Self {
     Parser {
       lexer,
-      current_token,
+      current_token: EOF,
+      comments: vec![],
+      pending: vec![],
+      inline_comments: vec![],
+      expression_depth: 0,
+      finished: None,
+      current_span: Span::default(),
+      previous_span: Span::default(),
+    }
+  }
+  /// Reads the first token, before anything is parsed.
+  fn start(&mut self) -> ParseResult<()> {
+    self.current_token = self.next_token()?;
+    Ok(())
+  }
+  /// Returns the comments of each statement, declaration and closing
+  /// END, in the order they appear in the source.
+  pub fn comments(&self) -> &[Comments] {
+    &self.comments
+  }
+  /// Returns the comments written within expressions, each with the
+  /// offset in the source of the end of the token it follows.
+  pub fn inline_comments(&self) -> &[(usize, String)] {
+    &self.inline_comments
+  }
+  /// Reads the next token, failing on text that can't be read as one.
+  /// Comments are put aside for the next statement or declaration.
+  fn next_token(&mut self) -> ParseResult<Token> {
+    let mut own_line = false;
+    loop {
+      match self.lexer.get_next_token().map_err(|err| err.to_string())? {
+        Whitespace(text) => own_line |= text.contains('\n'),
+        Comment(text) => self.pending.push((text, own_line)),
+        token => {
+          self.previous_span = mem::replace(&mut self.current_span, self.lexer.token_span());
+          return Ok(token);
+        }
+      }
+    }
+  }
+  /// Gives the statement or declaration just finished the comments
+  /// following it on the same line.
+  fn attach_trailing(&mut self) {
+    if let Some(index) = self.finished {
+      let count = self
+        .pending
+        .iter()
+        .take_while(|(_, own_line)| !own_line)
+        .count();
+      let trailing = self.pending.drain(..count).map(|(text, _)| text);
+      self.comments[index].trailing.extend(trailing);
+    }
+  }
+  /// Marks the start of a statement, a declaration, a BEGIN or a
+  /// closing END, giving it the comments read since the last one.
+  fn anchor(&mut self) -> usize {
+    self.attach_trailing();
+    let leading = mem::take(&mut self.pending);
+    self.comments.push(Comments {
+      leading: leading.into_iter().map(|(text, _)| text).collect(),
+      trailing: vec![],
+    });
+    self.comments.len() - 1
+  }
+  /// Keeps the comments read before a token within an expression with
+  /// the token they follow, rather than the statement.
+  fn anchor_inline(&mut self) {
+    let after = self.previous_span.end;
+    let comments = self.pending.drain(..).map(|(text, _)| (after, text));
+    self.inline_comments.extend(comments);
+  }
+  /// Returns the text from the start of a node, the span of its first
+  /// token, to the end of the last token consumed. A node no token has
+  /// been consumed for, such as an empty statement, is given no text.
+  fn span_from(&self, start: Span) -> Span {
+    if self.previous_span.end < start.start {
+      return Span {
+        end: start.start,
+        char_end: start.char_start,
+        ..start
+      };
+    }
+    Span {
+      end: self.previous_span.end,
+      char_end: self.previous_span.char_end,
+      ..start
     }
   }
   fn get_current_token(&self) -> Token {
-    self.current_token.clone().unwrap()
+    self.current_token.clone()
   }
   ///
   /// Verifies the token type matches the current token type.
   /// If valid the next token is saved.
   ///
-  fn consume(&mut self, token_type: &Token) {
+  fn consume(&mut self, token_type: &Token) -> ParseResult<()> {
     let current_token = self.get_current_token();
 
     if current_token == *token_type {
-      self.current_token = self.lexer.get_next_token();
+      if self.expression_depth > 0 {
+        self.anchor_inline();
+      }
+      // A semicolon may separate a statement from its trailing comment
+      if current_token != Semi {
+        self.attach_trailing();
+        self.finished = None;
+      }
+      self.current_token = self.next_token()?;
+      Ok(())
     } else {
-      panic!(
+      Err(format!(
         "Unexpected token error: expected {}, received {}",
         token_type, current_token
-      );
+      ))
     }
   }
-  fn program(&mut self) -> Box<Node> {
+  fn program(&mut self) -> ParseResult<Box<Node>> {
     // program : Program variable Semi block Period
-    self.consume(&Program);
-    let VarNode { identifier } = *self.variable();
-    self.consume(&Semi);
-    let block = self.block();
-    let node = ProgramNode::new(identifier, block);
-    self.consume(&Period);
-    Box::new(node)
-  }
-  fn block(&mut self) -> Box<Node> {
+    let anchor = self.anchor();
+    let start = self.current_span;
+    self.consume(&Program)?;
+    let identifier = self.variable()?.identifier;
+    self.finished = Some(anchor);
+    self.consume(&Semi)?;
+    let block = self.block()?;
+    // The comments around the period ending the program
+    let end = self.anchor();
+    self.consume(&Period)?;
+    self.finished = Some(end);
+    Ok(Box::new(ProgramNode::new(
+      identifier,
+      block,
+      self.span_from(start),
+    )))
+  }
+  fn block(&mut self) -> ParseResult<Box<Node>> {
     // block : declarations compound_statement
-    let declarations = self.declarations();
-    let compound_statement = self.compound_statement();
-    let node = BlockNode::new(declarations, compound_statement);
-    Box::new(node)
+    let start = self.current_span;
+    let declarations = self.declarations()?;
+    let compound_statement = self.compound_statement()?;
+    let span = self.span_from(start);
+    Ok(Box::new(BlockNode::new(
+      declarations,
+      compound_statement,
+      span,
+    )))
   }
-  fn compound_statement(&mut self) -> Box<Node> {
+  fn compound_statement(&mut self) -> ParseResult<Box<Node>> {
     // compound_statement : Begin statement_list End
-    self.consume(&Begin);
-    let nodes = self.statement_list();
-    self.consume(&End);
+    let start = self.current_span;
+    // BEGIN keeps the comments following it on its line
+    let begin = self.anchor();
+    self.consume(&Begin)?;
+    self.finished = Some(begin);
+    let nodes = self.statement_list()?;
+    self.anchor();
+    self.consume(&End)?;
 
-    Box::new(CompoundNode::new(nodes))
+    Ok(Box::new(CompoundNode::new(nodes, self.span_from(start))))
   }
-  fn declarations(&mut self) -> Vec<Box<Node>> {
-    // declarations : Var (variable_declaration Semi)+ (procedure_declaration)*
-    //              | (procedure_declaration)*
-    //              | empty
+  fn declarations(&mut self) -> ParseResult<Vec<Box<Node>>> {
+    // declarations : (Label label (Comma label)* Semi)?
+    //                (Type (type_definition Semi)+)?
+    //                (Var (variable_declaration Semi)+)?
+    //                (procedure_declaration | function_declaration)*
     let mut declarations: Vec<Box<Node>> = vec![];
+    if self.get_current_token() == Label {
+      self.consume(&Label)?;
+      self.anchor();
+      declarations.push(self.label_declaration()?);
+      while self.get_current_token() == Comma {
+        self.consume(&Comma)?;
+        self.anchor();
+        declarations.push(self.label_declaration()?);
+      }
+      self.finished = Some(self.comments.len() - 1);
+      self.consume(&Semi)?;
+    }
+    if self.get_current_token() == Type {
+      self.consume(&Type)?;
+      while let Id(_) = self.get_current_token() {
+        let anchor = self.anchor();
+        declarations.push(self.type_definition()?);
+        self.finished = Some(anchor);
+        self.consume(&Semi)?;
+      }
+    }
     if self.get_current_token() == Var {
-      self.consume(&Var);
+      self.consume(&Var)?;
       let mut current_token = self.get_current_token();
       while let Id(_) = current_token {
-        declarations.extend(self.variable_declaration());
+        declarations.extend(self.variable_declaration()?);
+        self.finished = Some(self.comments.len() - 1);
         current_token = self.get_current_token();
-        self.consume(&current_token);
+        self.consume(&current_token)?;
         current_token = self.get_current_token();
       }
     }
-    while self.get_current_token() == Procedure {
-      declarations.push(self.procedure_declaration());
+    while self.get_current_token() == Procedure || self.get_current_token() == Function {
+      declarations.push(self.procedure_declaration()?);
+    }
+    Ok(declarations)
+  }
+  fn label_declaration(&mut self) -> ParseResult<Box<Node>> {
+    let start = self.current_span;
+    let label = self.label()?;
+    Ok(Box::new(LabelDeclarationNode::new(
+      label,
+      self.span_from(start),
+    )))
+  }
+  fn type_definition(&mut self) -> ParseResult<Box<Node>> {
+    // type_definition : Id Equal (enumerated_type | type_spec)
+    let start = self.current_span;
+    let type_name = self.get_current_token();
+    self.consume(&type_name)?;
+    self.consume(&Equal)?;
+    let type_node = if self.get_current_token() == LParen {
+      self.enumerated_type()?
+    } else {
+      self.type_spec()?
+    };
+    let span = self.span_from(start);
+    Ok(Box::new(TypeDeclarationNode::new(
+      type_name, type_node, span,
+    )))
+  }
+  fn enumerated_type(&mut self) -> ParseResult<TypeNode> {
+    // enumerated_type : LParen Id (Comma Id)* RParen
+    let start = self.current_span;
+    self.consume(&LParen)?;
+    let mut identifiers = vec![self.variable()?.identifier];
+    while self.get_current_token() == Comma {
+      self.consume(&Comma)?;
+      identifiers.push(self.variable()?.identifier);
     }
-    declarations
+    self.consume(&RParen)?;
+    Ok(TypeNode::new(
+      TypeKind::Enumeration(identifiers),
+      self.span_from(start),
+    ))
   }
-  fn procedure_declaration(&mut self) -> Box<Node> {
+  fn procedure_declaration(&mut self) -> ParseResult<Box<Node>> {
     // procedure_declaration :
-    //    (Procedure Id (LParen formal_parameter_list RParen)? Semi Block Semi)*
+    //    Procedure Id (LParen formal_parameter_list RParen)? Semi (block | Forward) Semi
+    // function_declaration :
+    //    Function Id (LParen formal_parameter_list RParen)? Colon type_spec Semi
+    //    (block | Forward) Semi
     let mut params: Vec<Box<Node>> = vec![];
 
-    self.consume(&Procedure);
+    let anchor = self.anchor();
+    let start = self.current_span;
+    let kind = self.get_current_token();
+    self.consume(&kind)?;
     let proc_name = self.get_current_token();
-    self.consume(&proc_name);
+    self.consume(&proc_name)?;
     // formal parameters are optional
-    if self.get_current_token() != Semi {
-      self.consume(&LParen);
-      params = self.formal_parameter_list();
-      self.consume(&RParen);
+    if self.get_current_token() == LParen {
+      self.consume(&LParen)?;
+      params = self.formal_parameter_list()?;
+      self.consume(&RParen)?;
     }
-    self.consume(&Semi);
-    let block = self.block();
-    self.consume(&Semi);
-    Box::new(ProcedureNode::new(proc_name, params, block))
+    let return_type = if kind == Function {
+      self.consume(&Colon)?;
+      Some(self.type_spec()?)
+    } else {
+      None
+    };
+    self.finished = Some(anchor);
+    self.consume(&Semi)?;
+    let block = if self.get_current_token() == Forward {
+      self.consume(&Forward)?;
+      None
+    } else {
+      Some(Arc::new(self.block()?))
+    };
+    // The comments around the semicolon ending the declaration
+    self.finished = Some(self.anchor());
+    self.consume(&Semi)?;
+    let span = self.span_from(start);
+    Ok(Box::new(ProcedureNode::new(
+      proc_name,
+      params,
+      return_type,
+      block,
+      span,
+    )))
   }
-  fn formal_parameter_list(&mut self) -> Vec<Box<Node>> {
+  fn formal_parameter_list(&mut self) -> ParseResult<Vec<Box<Node>>> {
     // formal_parameter_list : formal_parameters
     //                       | formal_parameter Semi formal_parameter_list
-    let mut parameters = self.formal_parameters();
+    let mut parameters = self.formal_parameters()?;
 
     while self.get_current_token() == Semi {
-      self.consume(&Semi);
-      parameters.extend(self.formal_parameters());
+      self.consume(&Semi)?;
+      parameters.extend(self.formal_parameters()?);
     }
-    parameters
+    Ok(parameters)
   }
-  fn formal_parameters(&mut self) -> Vec<Box<Node>> {
+  fn formal_parameters(&mut self) -> ParseResult<Vec<Box<Node>>> {
     // formal_parameters : Id (Comma Id)* Colon type_spec
-    let mut parameter_nodes: Vec<VarNode> = Vec::new();
-    let mut identifier = self.get_current_token();
-    self.consume(&identifier);
-
-    parameter_nodes.push(VarNode::new(identifier));
+    let start = self.current_span;
+    let mut parameter_nodes: Vec<VarNode> = vec![*self.variable()?];
     while self.get_current_token() == Comma {
-      self.consume(&Comma);
-      identifier = self.get_current_token();
-      self.consume(&identifier);
-      parameter_nodes.push(VarNode::new(identifier));
+      self.consume(&Comma)?;
+      parameter_nodes.push(*self.variable()?);
     }
 
-    self.consume(&Colon);
+    self.consume(&Colon)?;
 
-    let type_node = self.type_spec();
+    let type_node = self.type_spec()?;
+    let span = self.span_from(start);
     let mut parameter_list: Vec<Box<Node>> = vec![];
     for node in parameter_nodes {
-      let parameter_node = ParameterNode::new(node, type_node.clone());
+      let parameter_node = ParameterNode::new(node, type_node.clone(), span);
       parameter_list.push(Box::new(parameter_node));
     }
-    parameter_list
+    Ok(parameter_list)
   }
-  fn variable_declaration(&mut self) -> Vec<Box<Node>> {
+  fn variable_declaration(&mut self) -> ParseResult<Vec<Box<Node>>> {
     // variable_declaration : Id (Comma Id)* Colon type_spec
-    let mut var_nodes: Vec<VarNode> = Vec::new();
-    let mut identifier = self.get_current_token();
-    self.consume(&identifier);
-
-    var_nodes.push(VarNode::new(identifier));
+    // Each variable declared is given its own comments, which the
+    // first holds
+    self.anchor();
+    let start = self.current_span;
+    let mut var_nodes: Vec<VarNode> = vec![*self.variable()?];
     while self.get_current_token() == Comma {
-      self.consume(&Comma);
-      identifier = self.get_current_token();
-      self.consume(&identifier);
-      var_nodes.push(VarNode::new(identifier));
+      self.consume(&Comma)?;
+      var_nodes.push(*self.variable()?);
     }
 
-    self.consume(&Colon);
+    self.consume(&Colon)?;
 
-    let type_node = self.type_spec();
+    let type_node = self.type_spec()?;
+    let span = self.span_from(start);
     let mut var_declarations: Vec<Box<Node>> = vec![];
+    for _ in 1..var_nodes.len() {
+      self.anchor();
+    }
     for node in var_nodes {
-      let declaration = DeclarationNode::new(node, type_node.clone());
+      let declaration = DeclarationNode::new(node, type_node.clone(), span);
       var_declarations.push(Box::new(declaration));
     }
-    var_declarations
+    Ok(var_declarations)
   }
-  fn type_spec(&mut self) -> TypeNode {
+  fn type_spec(&mut self) -> ParseResult<TypeNode> {
     // type_spec : Integer
-    //             Real
+    //           | Real
+    //           | Boolean
+    //           | Char
+    //           | Id
+    //           | array_type
+    //           | set_type
+    //           | pointer_type
+    //           | record_type
+    let start = self.current_span;
     let current_token = self.get_current_token();
     match current_token {
+      Integer | Real | Boolean | Char | Id(_) => {
+        self.consume(&current_token)?;
+        Ok(TypeNode::new(TypeKind::Named(current_token), start))
+      }
+      Array => self.array_type(),
+      Set => self.set_type(),
+      Caret => self.pointer_type(),
+      Record => self.record_type(),
+      token => Err(format!("Unknown token type found {}", token)),
+    }
+  }
+  fn pointer_type(&mut self) -> ParseResult<TypeNode> {
+    // pointer_type : Caret (Id | Integer | Real | Boolean | Char)
+    let start = self.current_span;
+    self.consume(&Caret)?;
+    let current_token = self.get_current_token();
+    match current_token {
+      Id(_) | Integer | Real | Boolean | Char => {
+        self.consume(&current_token)?;
+        Ok(TypeNode::new(
+          TypeKind::Pointer(current_token),
+          self.span_from(start),
+        ))
+      }
+      token => Err(format!("Invalid pointer type: ^{}", token)),
+    }
+  }
+  fn record_type(&mut self) -> ParseResult<TypeNode> {
+    // record_type : Record (field_declaration (Semi field_declaration)*)? Semi? End
+    // field_declaration : Id (Comma Id)* Colon type_spec
+    let start = self.current_span;
+    self.consume(&Record)?;
+    let mut fields: Vec<(Token, TypeNode, Span)> = vec![];
+    while let Id(_) = self.get_current_token() {
+      let field_start = self.current_span;
+      let mut names = vec![self.variable()?.identifier];
+      while self.get_current_token() == Comma {
+        self.consume(&Comma)?;
+        names.push(self.variable()?.identifier);
+      }
+      self.consume(&Colon)?;
+      let type_node = self.type_spec()?;
+      let span = self.span_from(field_start);
+      for name in names {
+        fields.push((name, type_node.clone(), span));
+      }
+      if self.get_current_token() != Semi {
+        break;
+      }
+      self.consume(&Semi)?;
+    }
+    self.consume(&End)?;
+    Ok(TypeNode::new(
+      TypeKind::Record(fields),
+      self.span_from(start),
+    ))
+  }
+  fn set_type(&mut self) -> ParseResult<TypeNode> {
+    // set_type : Set Of (Integer | Real | index_type)
+    let start = self.current_span;
+    self.consume(&Set)?;
+    self.consume(&Of)?;
+    // INTEGER and REAL are parsed so the semantic pass can reject them
+    let current_token = self.get_current_token();
+    let base_type = match current_token {
       Integer | Real => {
-        self.consume(&current_token);
-        TypeNode::new(current_token)
+        let span = self.current_span;
+        self.consume(&current_token)?;
+        TypeNode::new(TypeKind::Named(current_token), span)
+      }
+      _ => self.index_type()?,
+    };
+    Ok(TypeNode::new(
+      TypeKind::Set(Box::new(base_type)),
+      self.span_from(start),
+    ))
+  }
+  fn array_type(&mut self) -> ParseResult<TypeNode> {
+    // array_type : Array LBracket index_type RBracket Of type_spec
+    let start = self.current_span;
+    self.consume(&Array)?;
+    self.consume(&LBracket)?;
+    let index_type = self.index_type()?;
+    self.consume(&RBracket)?;
+    self.consume(&Of)?;
+    let element_type = self.type_spec()?;
+    let kind = TypeKind::Array(Box::new(index_type), Box::new(element_type));
+    Ok(TypeNode::new(kind, self.span_from(start)))
+  }
+  fn index_type(&mut self) -> ParseResult<TypeNode> {
+    // index_type : Char
+    //            | Boolean
+    //            | Id
+    //            | constant Range constant
+    let start = self.current_span;
+    let current_token = self.get_current_token();
+    let kind = match current_token {
+      Char | Boolean => {
+        self.consume(&current_token)?;
+        TypeKind::Named(current_token)
+      }
+      Id(_) => {
+        self.consume(&current_token)?;
+        if self.get_current_token() == Range {
+          self.consume(&Range)?;
+          TypeKind::Subrange(current_token, self.constant()?)
+        } else {
+          TypeKind::Named(current_token)
+        }
       }
-      token => panic!("Unknown token type found {}", token),
+      _ => {
+        let low = self.constant()?;
+        self.consume(&Range)?;
+        let high = self.constant()?;
+        TypeKind::Subrange(low, high)
+      }
+    };
+    Ok(TypeNode::new(kind, self.span_from(start)))
+  }
+  fn constant(&mut self) -> ParseResult<Token> {
+    // constant : Minus? IntegerConst
+    //          | StringConst
+    //          | True
+    //          | False
+    //          | Id
+    let current_token = self.get_current_token();
+    match current_token {
+      Minus => {
+        self.consume(&Minus)?;
+        match self.get_current_token() {
+          IntegerConst(value) => {
+            self.consume(&IntegerConst(value.clone()))?;
+            Ok(IntegerConst(format!("-{}", value)))
+          }
+          token => Err(format!("Invalid constant: -{}", token)),
+        }
+      }
+      IntegerConst(_) | StringConst(_) | True | False | Id(_) => {
+        self.consume(&current_token)?;
+        Ok(current_token)
+      }
+      token => Err(format!("Invalid constant: {}", token)),
     }
   }
-  fn statement_list(&mut self) -> Vec<Box<Node>> {
+  fn statement_list(&mut self) -> ParseResult<Vec<Box<Node>>> {
     // "statement_list : statement
     //                 | statement Semi statement_list"
-    let node = self.statement();
+    let node = self.statement()?;
     let mut results = vec![node];
 
     while self.get_current_token() == Semi {
-      self.consume(&Semi);
-      results.append(&mut vec![self.statement()]);
+      self.consume(&Semi)?;
+      results.append(&mut vec![self.statement()?]);
 
       if let Id(_) = self.get_current_token() {
-        panic!(
+        return Err(format!(
           "Invalid token in statement list: {}",
           self.get_current_token()
-        )
+        ));
       }
     }
-    results
+    Ok(results)
   }
-  fn statement(&mut self) -> Box<Node> {
-    // statement : compound_statement
+  fn statement(&mut self) -> ParseResult<Box<Node>> {
+    // statement : label Colon statement
+    //           | compound_statement
+    //           | goto_statement
+    //           | if_statement
+    //           | case_statement
+    //           | for_statement
+    //           | while_statement
+    //           | try_statement
+    //           | raise_statement
+    //           | procedure_call
     //           | assign_statement
     //           | empty
-    match self.get_current_token() {
-      Begin => self.compound_statement(),
-      Id(_) => self.assignment_statement(),
+    let anchor = self.anchor();
+    let start = self.current_span;
+    let node = match self.get_current_token() {
+      IntegerConst(_) => {
+        let label = self.label()?;
+        self.consume(&Colon)?;
+        let statement = self.statement()?;
+        let span = self.span_from(start);
+        Box::new(LabelledStatementNode::new(label, statement, span))
+      }
+      Begin => self.compound_statement()?,
+      Goto => {
+        // goto_statement : Goto label
+        self.consume(&Goto)?;
+        let label = self.label()?;
+        Box::new(GotoNode::new(label, self.span_from(start)))
+      }
+      If => self.if_statement()?,
+      Case => self.case_statement()?,
+      For => self.for_statement()?,
+      While => self.while_statement()?,
+      Try => self.try_statement()?,
+      Raise => self.raise_statement()?,
+      Id(_) => {
+        let position = self.lexer.token_position();
+        let variable = self.variable()?;
+        match self.get_current_token() {
+          Assign | LBracket | Caret | Period => self.assignment_statement(variable)?,
+          _ => self.procedure_call(*variable, position)?,
+        }
+      }
       _ => self.empty(),
+    };
+    self.finished = Some(anchor);
+    Ok(node)
+  }
+  fn label(&mut self) -> ParseResult<Token> {
+    // label : IntegerConst
+    // Labels are compared by value, so leading zeros are dropped
+    let token = self.get_current_token();
+    match token {
+      IntegerConst(ref digits) => {
+        self.consume(&token)?;
+        let digits = digits.trim_start_matches('0');
+        Ok(IntegerConst(
+          if digits.is_empty() { "0" } else { digits }.to_string(),
+        ))
+      }
+      _ => Err(format!(
+        "Unexpected token error: expected a label, received {}",
+        token
+      )),
+    }
+  }
+  fn if_statement(&mut self) -> ParseResult<Box<Node>> {
+    // if_statement : If expr Then statement (Else statement)?
+    let start = self.current_span;
+    self.consume(&If)?;
+    let condition = self.expr()?;
+    self.consume(&Then)?;
+    let then_branch = self.statement()?;
+    let mut else_branch: Option<Box<Node>> = None;
+    if self.get_current_token() == Else {
+      self.consume(&Else)?;
+      else_branch = Some(self.statement()?);
+    }
+    let span = self.span_from(start);
+    Ok(Box::new(IfNode::new(
+      condition,
+      then_branch,
+      else_branch,
+      span,
+    )))
+  }
+  fn case_statement(&mut self) -> ParseResult<Box<Node>> {
+    // case_statement : Case expr Of case_element (Semi case_element)* Semi?
+    //                  ((Else | Otherwise) statement_list)? End
+    let start = self.current_span;
+    self.consume(&Case)?;
+    let selector = self.expr()?;
+    self.consume(&Of)?;
+    let mut branches = vec![self.case_element()?];
+    while self.get_current_token() == Semi {
+      self.consume(&Semi)?;
+      match self.get_current_token() {
+        End | Else | Otherwise => break,
+        _ => branches.push(self.case_element()?),
+      }
+    }
+    let mut else_branch: Option<Box<Node>> = None;
+    let current_token = self.get_current_token();
+    if current_token == Else || current_token == Otherwise {
+      self.consume(&current_token)?;
+      else_branch = Some(self.statements()?);
+    }
+    self.anchor();
+    self.consume(&End)?;
+    let span = self.span_from(start);
+    Ok(Box::new(CaseNode::new(
+      selector,
+      branches,
+      else_branch,
+      span,
+    )))
+  }
+  fn case_element(&mut self) -> ParseResult<CaseBranch> {
+    // case_element : case_label (Comma case_label)* Colon statement
+    // case_label : constant (Range constant)?
+    let start = self.current_span;
+    let mut labels = vec![];
+    loop {
+      let low = self.constant()?;
+      let high = if self.get_current_token() == Range {
+        self.consume(&Range)?;
+        self.constant()?
+      } else {
+        low.clone()
+      };
+      labels.push((low, high));
+      if self.get_current_token() != Comma {
+        break;
+      }
+      self.consume(&Comma)?;
+    }
+    self.consume(&Colon)?;
+    let statement = self.statement()?;
+    Ok(CaseBranch::new(labels, statement, self.span_from(start)))
+  }
+  fn while_statement(&mut self) -> ParseResult<Box<Node>> {
+    // while_statement : While expr Do statement
+    let start = self.current_span;
+    self.consume(&While)?;
+    let condition = self.expr()?;
+    self.consume(&Do)?;
+    let body = self.statement()?;
+    Ok(Box::new(WhileNode::new(
+      condition,
+      body,
+      self.span_from(start),
+    )))
+  }
+  fn for_statement(&mut self) -> ParseResult<Box<Node>> {
+    // for_statement : For variable Assign expr (To | Downto) expr Do statement
+    let start = self.current_span;
+    self.consume(&For)?;
+    let identifier = *self.variable()?;
+    self.consume(&Assign)?;
+    let from = self.expr()?;
+    let direction = self.get_current_token();
+    match direction {
+      To | Downto => self.consume(&direction)?,
+      token => return Err(format!("Expected TO or DOWNTO, received {}", token)),
+    }
+    let end = self.expr()?;
+    self.consume(&Do)?;
+    let body = self.statement()?;
+    let span = self.span_from(start);
+    Ok(Box::new(ForNode::new(
+      identifier, from, end, direction, body, span,
+    )))
+  }
+  fn try_statement(&mut self) -> ParseResult<Box<Node>> {
+    // try_statement : Try statement_list
+    //                 (Except exception_block | Finally statement_list) End
+    let start = self.current_span;
+    self.consume(&Try)?;
+    let body = self.statements()?;
+    if self.get_current_token() == Finally {
+      self.consume(&Finally)?;
+      let finally = self.statements()?;
+      self.anchor();
+      self.consume(&End)?;
+      return Ok(Box::new(TryFinallyNode::new(
+        body,
+        finally,
+        self.span_from(start),
+      )));
+    }
+    self.consume(&Except)?;
+    let (handlers, else_branch) = self.exception_block()?;
+    self.anchor();
+    self.consume(&End)?;
+    let span = self.span_from(start);
+    Ok(Box::new(TryExceptNode::new(
+      body,
+      handlers,
+      else_branch,
+      span,
+    )))
+  }
+  /// Parses a statement list that is not enclosed in BEGIN and END,
+  /// such as the body of a TRY, as a compound statement.
+  fn statements(&mut self) -> ParseResult<Box<Node>> {
+    let start = self.current_span;
+    let statements = self.statement_list()?;
+    Ok(Box::new(CompoundNode::new(
+      statements,
+      self.span_from(start),
+    )))
+  }
+  fn exception_block(&mut self) -> ParseResult<ExceptionBlock> {
+    // exception_block : exception_handler (Semi exception_handler)* Semi?
+    //                   (Else statement_list)?
+    //                 | statement_list
+    if !self.at_exception_handler() {
+      return Ok((vec![], Some(self.statements()?)));
+    }
+    let mut handlers = vec![self.exception_handler()?];
+    while self.get_current_token() == Semi {
+      self.consume(&Semi)?;
+      if !self.at_exception_handler() {
+        break;
+      }
+      handlers.push(self.exception_handler()?);
+    }
+    let mut else_branch: Option<Box<Node>> = None;
+    if self.get_current_token() == Else {
+      self.consume(&Else)?;
+      else_branch = Some(self.statements()?);
+    }
+    Ok((handlers, else_branch))
+  }
+  /// Returns true if the current token starts an ON clause. ON is not a
+  /// reserved word, so it can still name a variable elsewhere.
+  fn at_exception_handler(&self) -> bool {
+    match self.get_current_token() {
+      Id(name) => name.eq_ignore_ascii_case("ON"),
+      _ => false,
+    }
+  }
+  fn exception_handler(&mut self) -> ParseResult<ExceptionHandler> {
+    // exception_handler : On (Id Colon)? Id Do statement
+    let start = self.current_span;
+    let on = self.get_current_token();
+    self.consume(&on)?;
+    let mut variable = None;
+    let mut class = self.variable()?.identifier;
+    if self.get_current_token() == Colon {
+      self.consume(&Colon)?;
+      variable = Some(class);
+      class = self.variable()?.identifier;
+    }
+    self.consume(&Do)?;
+    let statement = self.statement()?;
+    Ok(ExceptionHandler::new(
+      variable,
+      class,
+      statement,
+      self.span_from(start),
+    ))
+  }
+  fn raise_statement(&mut self) -> ParseResult<Box<Node>> {
+    // raise_statement : Raise (Id Period Id LParen expr RParen | variable)?
+    let start = self.current_span;
+    self.consume(&Raise)?;
+    if let Id(_) = self.get_current_token() {
+      let variable = self.variable()?;
+      if self.get_current_token() != Period {
+        return Ok(Box::new(RaiseNode::new(
+          Some(variable),
+          self.span_from(start),
+        )));
+      }
+      // Exceptions are only created by their class's Create constructor
+      self.consume(&Period)?;
+      let constructor = self.variable()?.identifier;
+      if !constructor.to_string().eq_ignore_ascii_case("CREATE") {
+        return Err(format!(
+          "Unexpected token error: expected Create, received {}",
+          constructor
+        ));
+      }
+      self.consume(&LParen)?;
+      let message = self.expr()?;
+      self.consume(&RParen)?;
+      let exception =
+        ExceptionNode::new(variable.identifier, message, self.span_from(variable.span));
+      return Ok(Box::new(RaiseNode::new(
+        Some(Box::new(exception)),
+        self.span_from(start),
+      )));
     }
+    Ok(Box::new(RaiseNode::new(None, self.span_from(start))))
   }
-  fn assignment_statement(&mut self) -> Box<Node> {
-    // assignment_statement : variable Assign expr
-    let left = self.variable();
+  fn procedure_call(&mut self, variable: VarNode, position: Position) -> ParseResult<Box<Node>> {
+    // procedure_call : Id (LParen actual_parameters RParen)?
+    let mut args: Vec<Box<Node>> = vec![];
+    if self.get_current_token() == LParen {
+      // The arguments keep their comments as an expression does
+      self.expression_depth += 1;
+      self.consume(&LParen)?;
+      args = self.actual_parameters()?;
+      self.consume(&RParen)?;
+      self.expression_depth -= 1;
+    }
+    Ok(Box::new(ProcedureCallNode::new(
+      variable.identifier,
+      args,
+      position,
+      self.span_from(variable.span),
+    )))
+  }
+  fn assignment_statement(&mut self, variable: Box<VarNode>) -> ParseResult<Box<Node>> {
+    // assignment_statement : variable_access Assign expr
+    let start = variable.span;
+    let left = self.variable_access(variable)?;
     let current_token = self.get_current_token();
-    self.consume(&Assign);
-    let right = self.expr();
-    let node = AssignNode::new(left, right, current_token);
-    Box::new(node)
+    self.consume(&Assign)?;
+    let right = self.expr()?;
+    let node = AssignNode::new(left, right, current_token, self.span_from(start));
+    Ok(Box::new(node))
   }
-  fn variable(&mut self) -> Box<VarNode> {
+  fn variable(&mut self) -> ParseResult<Box<VarNode>> {
     // variable : Id
     let current_token = self.get_current_token();
     if let Id(_) = current_token {
-      self.consume(&current_token);
-      let node = VarNode::new(current_token);
-      Box::new(node)
+      let span = self.current_span;
+      self.consume(&current_token)?;
+      let node = VarNode::new(current_token, span);
+      Ok(Box::new(node))
     } else {
-      panic!("Invalid variable: {}", current_token);
+      Err(format!("Invalid variable: {}", current_token))
     }
   }
+  fn variable_access(&mut self, variable: Box<VarNode>) -> ParseResult<Box<Node>> {
+    // variable_access : variable (LBracket expr RBracket
+    //                             | Caret
+    //                             | Period Id)*
+    let start = variable.span;
+    let mut node: Box<Node> = variable;
+    loop {
+      match self.get_current_token() {
+        LBracket => {
+          self.consume(&LBracket)?;
+          let index = self.expr()?;
+          self.consume(&RBracket)?;
+          node = Box::new(IndexNode::new(node, index, self.span_from(start)));
+        }
+        Caret => {
+          self.consume(&Caret)?;
+          node = Box::new(DerefNode::new(node, self.span_from(start)));
+        }
+        Period => {
+          self.consume(&Period)?;
+          let field = self.variable()?.identifier;
+          node = Box::new(FieldNode::new(node, field, self.span_from(start)));
+        }
+        _ => return Ok(node),
+      }
+    }
+  }
+  fn function_call(&mut self, variable: VarNode) -> ParseResult<Box<Node>> {
+    // function_call : Id LParen actual_parameters RParen
+    self.consume(&LParen)?;
+    let args = self.actual_parameters()?;
+    self.consume(&RParen)?;
+    let span = self.span_from(variable.span);
+    Ok(Box::new(FunctionCallNode::new(
+      variable.identifier,
+      args,
+      span,
+    )))
+  }
+  fn actual_parameters(&mut self) -> ParseResult<Vec<Box<Node>>> {
+    // actual_parameters : (expr (Comma expr)*)?
+    let mut args: Vec<Box<Node>> = vec![];
+    if self.get_current_token() != RParen {
+      args.push(self.expr()?);
+      while self.get_current_token() == Comma {
+        self.consume(&Comma)?;
+        args.push(self.expr()?);
+      }
+    }
+    Ok(args)
+  }
   fn empty(&self) -> Box<Node> {
-    Box::new(NoOpNode {})
+    Box::new(NoOpNode {
+      span: self.span_from(self.current_span),
+    })
   }
-  fn factor(&mut self) -> Box<Node> {
+  fn factor(&mut self) -> ParseResult<Box<Node>> {
     // factor : Plus factor
     //        | Minus factor
-    //        | Integer
+    //        | IntegerConst
+    //        | RealConst
+    //        | StringConst
+    //        | True
+    //        | False
     //        | LParen expr RParen
-    //        | variable
+    //        | set_constructor
+    //        | Nil
+    //        | function_call
+    //        | variable_access
+    //        | Integer | Real | Boolean | Char
     let mut current_token = self.get_current_token();
+    let start = self.current_span;
 
-    match current_token {
+    Ok(match current_token {
       Plus | Minus => {
-        self.consume(&current_token);
-        let node = UnaryOpNode::new(current_token, self.factor());
+        self.consume(&current_token)?;
+        let expr = self.factor()?;
+        let node = UnaryOpNode::new(current_token, expr, self.span_from(start));
         Box::new(node)
       }
       IntegerConst(value) => {
         current_token = self.get_current_token();
-        self.consume(&current_token);
-        Box::new(IntegerNumNode::new(value.parse::<i32>().unwrap()))
+        self.consume(&current_token)?;
+        let number = integer_value(&value).unwrap();
+        Box::new(IntegerNumNode::new(number, value, start))
       }
       RealConst(value) => {
         current_token = self.get_current_token();
-        self.consume(&current_token);
-        Box::new(RealNumNode::new(value.parse::<f32>().unwrap()))
+        self.consume(&current_token)?;
+        let number = value.parse::<f32>().unwrap();
+        Box::new(RealNumNode::new(number, value, start))
+      }
+      StringConst(value) => {
+        current_token = self.get_current_token();
+        self.consume(&current_token)?;
+        let mut chars = value.chars();
+        match (chars.next(), chars.next()) {
+          (Some(value), None) => Box::new(CharNode::new(value, start)),
+          _ => Box::new(StringNode::new(value.clone(), start)),
+        }
+      }
+      True | False => {
+        self.consume(&current_token)?;
+        Box::new(BooleanNode::new(current_token == True, start))
       }
       LParen => {
-        self.consume(&current_token);
-        let node = self.expr();
-        self.consume(&RParen);
+        self.consume(&current_token)?;
+        let node = self.expr()?;
+        self.consume(&RParen)?;
         node
       }
-      _ => self.variable(),
+      LBracket => self.set_constructor()?,
+      Nil => {
+        self.consume(&Nil)?;
+        Box::new(NilNode::new(start))
+      }
+      Id(_) => {
+        let variable = self.variable()?;
+        if self.get_current_token() == LParen {
+          self.function_call(*variable)?
+        } else {
+          self.variable_access(variable)?
+        }
+      }
+      // Type names are only meaningful as arguments to Low and High
+      Integer | Real | Boolean | Char => {
+        self.consume(&current_token)?;
+        Box::new(TypeNode::new(TypeKind::Named(current_token), start))
+      }
+      _ => self.variable()?,
+    })
+  }
+  fn set_constructor(&mut self) -> ParseResult<Box<Node>> {
+    // set_constructor : LBracket (member (Comma member)*)? RBracket
+    // member : expr (Range expr)?
+    let start = self.current_span;
+    self.consume(&LBracket)?;
+    let mut members: Vec<SetMember> = vec![];
+    if self.get_current_token() != RBracket {
+      loop {
+        let low = self.expr()?;
+        let mut high: Option<Box<Node>> = None;
+        if self.get_current_token() == Range {
+          self.consume(&Range)?;
+          high = Some(self.expr()?);
+        }
+        members.push((low, high));
+        if self.get_current_token() != Comma {
+          break;
+        }
+        self.consume(&Comma)?;
+      }
     }
+    self.consume(&RBracket)?;
+    Ok(Box::new(SetNode::new(members, self.span_from(start))))
   }
-  fn term(&mut self) -> Box<Node> {
+  fn term(&mut self) -> ParseResult<Box<Node>> {
     // factor ((Multiply | Divide) factor)*
-    let mut node = self.factor();
+    let start = self.current_span;
+    let mut node = self.factor()?;
     let mut current_token = self.get_current_token();
 
-    while current_token == Multiply || current_token == IntegerDivision
+    while current_token == Multiply
+      || current_token == IntegerDivision
       || current_token == RealDivision
     {
-      self.consume(&current_token);
-      node = Box::new(BinOpNode::new(node, self.factor(), current_token));
+      self.consume(&current_token)?;
+      let right = self.factor()?;
+      node = Box::new(BinOpNode::new(
+        node,
+        right,
+        current_token,
+        self.span_from(start),
+      ));
       current_token = self.get_current_token();
     }
+    Ok(node)
+  }
+  fn expr(&mut self) -> ParseResult<Box<Node>> {
+    self.expression_depth += 1;
+    let node = self.relation();
+    self.expression_depth -= 1;
     node
   }
-  fn expr(&mut self) -> Box<Node> {
+  fn relation(&mut self) -> ParseResult<Box<Node>> {
+    // simple_expr ((Equal | NotEqual | LessThan | GreaterThan
+    //               | LessEqual | GreaterEqual | In) simple_expr)?
+    let start = self.current_span;
+    let node = self.simple_expr()?;
+
+    let current_token = self.get_current_token();
+    if current_token.is_relational() {
+      self.consume(&current_token)?;
+      let right = self.simple_expr()?;
+      let span = self.span_from(start);
+      return Ok(Box::new(BinOpNode::new(node, right, current_token, span)));
+    }
+    Ok(node)
+  }
+  fn simple_expr(&mut self) -> ParseResult<Box<Node>> {
     // term ((Plus | Minus) term))*
-    let mut node = self.term();
+    let start = self.current_span;
+    let mut node = self.term()?;
 
     let mut current_token = self.get_current_token();
     while current_token == Plus || current_token == Minus {
-      self.consume(&current_token);
-      node = Box::new(BinOpNode::new(node, self.term(), current_token));
+      self.consume(&current_token)?;
+      let right = self.term()?;
+      node = Box::new(BinOpNode::new(
+        node,
+        right,
+        current_token,
+        self.span_from(start),
+      ));
       current_token = self.get_current_token();
     }
-    node
+    Ok(node)
   }
-  pub fn parse(&mut self) -> Box<Node> {
-    let node = self.program();
+  /// Checks that nothing follows what has been parsed.
+  fn end_of_file(&mut self) -> ParseResult<()> {
     let current_token = self.get_current_token();
     if current_token != EOF {
-      panic!("Unexpected token found at end of file: {}", current_token);
+      return Err(format!(
+        "Unexpected token found at end of file: {}",
+        current_token
+      ));
     }
-    node
+    // The comments at the end of the text
+    self.anchor();
+    Ok(())
+  }
+  /// Parses a program, panicking on a syntax error.
+  pub fn parse(&mut self) -> Box<Node> {
+    self.parse_program().unwrap_or_else(|err| panic!("{}", err))
+  }
+  /// Parses a program, returning a syntax error as an error.
+  pub fn parse_program(&mut self) -> ParseResult<Box<Node>> {
+    self.start()?;
+    let node = self.program()?;
+    self.end_of_file()?;
+    Ok(node)
+  }
+  /// Parses declarations on their own, outside a program, as they are
+  /// entered in an interactive session.
+  pub fn parse_declarations(&mut self) -> ParseResult<Vec<Box<Node>>> {
+    self.start()?;
+    let declarations = self.declarations()?;
+    self.end_of_file()?;
+    Ok(declarations)
+  }
+  /// Parses statements separated by semicolons, outside a program, as
+  /// a compound statement without BEGIN and END.
+  pub fn parse_statements(&mut self) -> ParseResult<Box<Node>> {
+    self.start()?;
+    let statements = self.statements()?;
+    self.end_of_file()?;
+    Ok(statements)
+  }
+  /// Parses an expression on its own, outside a program.
+  pub fn parse_expression(&mut self) -> ParseResult<Box<Node>> {
+    self.start()?;
+    let expression = self.expr()?;
+    self.end_of_file()?;
+    Ok(expression)
   }
 }
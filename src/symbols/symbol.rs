@@ -1,6 +1,6 @@
 use lexer::token::Token;
-use std::collections::HashMap;
 use std::fmt;
+use utils::names::{same_name, NameMap};
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct BuiltIn(Token);
@@ -8,26 +8,158 @@ pub struct BuiltIn(Token);
 impl BuiltIn {
   pub fn new(name: Token) -> Self {
     match name {
-      Token::Integer | Token::Real => BuiltIn(name),
+      Token::Integer | Token::Real | Token::Boolean | Token::Char => BuiltIn(name),
       _ => panic!("Invalid symbol value found {}", name),
     }
   }
 }
 
+#[derive(Clone, Debug)]
+pub enum Type {
+  Simple(BuiltIn),
+  // The base type followed by the lower and upper ordinal bounds
+  Subrange(Box<Type>, i32, i32),
+  // The index type followed by the element type
+  Array(Box<Type>, Box<Type>),
+  // The names of the values, in order
+  Enumeration(Vec<String>),
+  // The base type of the members, if known; the empty set [] has none
+  Set(Option<Box<Type>>),
+  // The name of the type pointed to, which may be declared after the
+  // pointer type; NIL points to no type in particular
+  Pointer(Option<String>),
+  // The name and type of each field
+  Record(Vec<(String, Type)>),
+  Str,
+}
+
+use self::Type::*;
+
+impl Type {
+  pub fn is_ordinal(&self) -> bool {
+    self.bounds().is_some()
+  }
+  /// Returns the type a subrange was taken from.
+  pub fn base(&self) -> Type {
+    match self {
+      Subrange(base, _, _) => base.base(),
+      other => other.clone(),
+    }
+  }
+  /// Returns the number of values a variable of the type holds: one
+  /// for each element of an array and each field of a record.
+  pub fn size(&self) -> i64 {
+    match self {
+      Array(index, element) => {
+        let (low, high) = index.bounds().unwrap_or((0, 0));
+        (high as i64 - low as i64 + 1).saturating_mul(element.size())
+      }
+      Record(fields) => fields
+        .iter()
+        .map(|(_, kind)| kind.size())
+        .fold(0, i64::saturating_add),
+      _ => 1,
+    }
+  }
+  /// Returns the lowest and highest ordinal values of an ordinal type.
+  pub fn bounds(&self) -> Option<(i32, i32)> {
+    match self {
+      Simple(BuiltIn(Token::Integer)) => Some((i32::MIN, i32::MAX)),
+      Simple(BuiltIn(Token::Char)) => Some((0, 255)),
+      Simple(BuiltIn(Token::Boolean)) => Some((0, 1)),
+      Subrange(_, low, high) => Some((*low, *high)),
+      Enumeration(names) => Some((0, names.len() as i32 - 1)),
+      _ => None,
+    }
+  }
+}
+
+/// Types are the same whatever the case their names are spelt in.
+impl PartialEq for Type {
+  fn eq(&self, other: &Type) -> bool {
+    let same_names = |left: &Vec<String>, right: &Vec<String>| {
+      left.len() == right.len() && left.iter().zip(right).all(|(l, r)| same_name(l, r))
+    };
+    match (self, other) {
+      (Simple(left), Simple(right)) => left == right,
+      (Subrange(left, left_low, left_high), Subrange(right, right_low, right_high)) => {
+        left == right && left_low == right_low && left_high == right_high
+      }
+      (Array(left_index, left), Array(right_index, right)) => {
+        left_index == right_index && left == right
+      }
+      (Enumeration(left), Enumeration(right)) => same_names(left, right),
+      (Set(left), Set(right)) => left == right,
+      (Pointer(Some(left)), Pointer(Some(right))) => same_name(left, right),
+      (Pointer(None), Pointer(None)) | (Str, Str) => true,
+      (Record(left), Record(right)) => {
+        left.len() == right.len()
+          && left
+            .iter()
+            .zip(right)
+            .all(|((left, left_type), (right, right_type))| {
+              same_name(left, right) && left_type == right_type
+            })
+      }
+      _ => false,
+    }
+  }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Symbol {
   BuiltInSymbol(BuiltIn),
-  VarSymbol(String, BuiltIn),
-  ProcedureSymbol(String, Vec<(String, BuiltIn)>),
+  VarSymbol(String, Type),
+  ConstSymbol(String, Type),
+  TypeSymbol(String, Type),
+  ProcedureSymbol(String, Vec<(String, Type)>),
+  // The name and parameters of a function, followed by its result type
+  FunctionSymbol(String, Vec<(String, Type)>, Type),
+  BuiltInFunctionSymbol(String),
+  BuiltInProcedureSymbol(String),
 }
 
 use self::Symbol::*;
 
+/// The standard functions required by ISO 7185, along with
+/// the ordinal helpers found in Turbo Pascal.
+pub const BUILTIN_FUNCTIONS: [&str; 17] = [
+  "ABS", "SQR", "SIN", "COS", "EXP", "LN", "SQRT", "ARCTAN", "TRUNC", "ROUND", "ODD", "ORD", "CHR",
+  "SUCC", "PRED", "LOW", "HIGH",
+];
+
+/// The standard procedures, including the Turbo Pascal procedures
+/// that leave a loop, routine or program early.
+pub const BUILTIN_PROCEDURES: [&str; 10] = [
+  "INC", "DEC", "WRITE", "WRITELN", "NEW", "DISPOSE", "BREAK", "CONTINUE", "EXIT", "HALT",
+];
+
+impl Symbol {
+  pub fn name(&self) -> String {
+    match self {
+      BuiltInSymbol(builtin) => builtin.to_string(),
+      VarSymbol(name, _)
+      | ConstSymbol(name, _)
+      | TypeSymbol(name, _)
+      | ProcedureSymbol(name, _)
+      | FunctionSymbol(name, _, _)
+      | BuiltInFunctionSymbol(name)
+      | BuiltInProcedureSymbol(name) => name.to_string(),
+    }
+  }
+}
+
 #[derive(Clone)]
 pub struct SymbolTable {
   scope_name: String,
   scope_level: u32,
-  symbols: HashMap<String, Symbol>,
+  symbols: NameMap<Symbol>,
+  // The names of the symbols, in the order they were declared
+  names: Vec<String>,
+  enclosing_scope: Option<Box<SymbolTable>>,
+  // The scopes nested in this one that have been left, in the order
+  // they were entered
+  nested_scopes: Vec<SymbolTable>,
 }
 
 impl Default for SymbolTable {
@@ -35,54 +167,112 @@ impl Default for SymbolTable {
     SymbolTable {
       scope_name: "".into(),
       scope_level: 0,
-      symbols: HashMap::new(),
+      symbols: NameMap::new(),
+      names: vec![],
+      enclosing_scope: None,
+      nested_scopes: vec![],
     }
   }
 }
 
 impl SymbolTable {
   pub fn new(scope_name: &str, scope_level: u32) -> Self {
-    let scope_name = scope_name.to_string();
-    let symbols = HashMap::new();
     let mut symbol_table = SymbolTable {
-      scope_name,
+      scope_name: scope_name.to_string(),
       scope_level,
-      symbols,
+      ..Default::default()
     };
     symbol_table.initialise_builtins();
     symbol_table
   }
+  /// Creates an empty scope nested one level inside the given scope.
+  pub fn nested(scope_name: &str, enclosing_scope: SymbolTable) -> Self {
+    SymbolTable {
+      scope_name: scope_name.to_string(),
+      scope_level: enclosing_scope.scope_level + 1,
+      enclosing_scope: Some(Box::new(enclosing_scope)),
+      ..Default::default()
+    }
+  }
+  /// Consumes a nested scope, returning the scope enclosing it, which
+  /// keeps the scope left among its nested scopes.
+  pub fn into_enclosing_scope(mut self) -> Option<SymbolTable> {
+    let mut enclosing_scope = *self.enclosing_scope.take()?;
+    enclosing_scope.nested_scopes.push(self);
+    Some(enclosing_scope)
+  }
+  pub fn scope_name(&self) -> &str {
+    &self.scope_name
+  }
+  pub fn scope_level(&self) -> u32 {
+    self.scope_level
+  }
+  /// Returns the symbols declared in this scope, in the order they
+  /// were declared.
+  pub fn symbols(&self) -> Vec<&Symbol> {
+    self.names.iter().map(|name| &self.symbols[name]).collect()
+  }
+  /// Returns the scopes that were nested in this one and have been left.
+  pub fn nested_scopes(&self) -> &[SymbolTable] {
+    &self.nested_scopes
+  }
+  fn add(&mut self, name: String, symbol: Symbol) {
+    if self.symbols.insert(name.clone(), symbol).is_none() {
+      self.names.push(name);
+    }
+  }
   // Inserts a builtin type into the Symbol Table.
   pub fn set(&mut self, builtin: BuiltIn) {
-    self
-      .symbols
-      .insert(builtin.0.to_string(), BuiltInSymbol(builtin));
+    self.add(builtin.0.to_string(), BuiltInSymbol(builtin));
   }
   // Returns the builtin type for the given token reference.
   pub fn get(&self, name: &Token) -> Symbol {
     self.lookup(&name.to_string()).unwrap()
   }
-  // Inserts a user-defined type into the Symbol Table.
+  // Inserts a user-defined symbol into the Symbol Table.
   pub fn insert(&mut self, symbol: Symbol) {
-    if let Symbol::VarSymbol(key, _kind) = symbol.clone() {
-      self.symbols.insert(key, symbol);
-    } else {
-      panic!(format!("Error, Invalid Symbol! {}", symbol));
+    match symbol {
+      VarSymbol(_, _)
+      | ConstSymbol(_, _)
+      | TypeSymbol(_, _)
+      | ProcedureSymbol(_, _)
+      | FunctionSymbol(_, _, _) => {
+        self.add(symbol.name(), symbol);
+      }
+      _ => panic!("Error, Invalid Symbol! {}", symbol),
     }
   }
   // Returns the matching symbol in the symbol table corresponding
-  // to the given key.
+  // to the given key, searching the enclosing scopes in turn.
   pub fn lookup(&self, key: &str) -> Option<Symbol> {
     match self.symbols.get(key) {
-      None => None,
+      None => match self.enclosing_scope {
+        Some(ref scope) => scope.lookup(key),
+        None => None,
+      },
       Some(symbol) => Some(symbol.clone()),
     }
   }
+  // Returns the matching symbol in the current scope only.
+  pub fn lookup_local(&self, key: &str) -> Option<Symbol> {
+    self.symbols.get(key).cloned()
+  }
   fn initialise_builtins(&mut self) {
     let int_type = BuiltIn::new(Token::Integer);
     let real_type = BuiltIn::new(Token::Real);
+    let boolean_type = BuiltIn::new(Token::Boolean);
+    let char_type = BuiltIn::new(Token::Char);
     self.set(int_type);
     self.set(real_type);
+    self.set(boolean_type);
+    self.set(char_type);
+
+    for name in BUILTIN_FUNCTIONS.iter() {
+      self.add(name.to_string(), BuiltInFunctionSymbol(name.to_string()));
+    }
+    for name in BUILTIN_PROCEDURES.iter() {
+      self.add(name.to_string(), BuiltInProcedureSymbol(name.to_string()));
+    }
   }
 }
 
@@ -92,6 +282,38 @@ impl fmt::Display for BuiltIn {
   }
 }
 
+impl fmt::Display for Type {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Simple(builtin) => write!(f, "{}", builtin),
+      Subrange(base, low, high) if **base == Simple(BuiltIn(Token::Char)) => write!(
+        f,
+        "{}..{}",
+        Token::StringConst((*low as u8 as char).to_string()),
+        Token::StringConst((*high as u8 as char).to_string())
+      ),
+      Subrange(base, low, high) => match **base {
+        Enumeration(ref names) => write!(f, "{}..{}", names[*low as usize], names[*high as usize]),
+        _ => write!(f, "{}..{}", low, high),
+      },
+      Array(index, element) => write!(f, "ARRAY[{}] OF {}", index, element),
+      Enumeration(names) => write!(f, "({})", names.join(", ")),
+      Set(Some(base)) => write!(f, "SET OF {}", base),
+      Set(None) => write!(f, "SET OF []"),
+      Pointer(Some(name)) => write!(f, "^{}", name),
+      Pointer(None) => write!(f, "NIL"),
+      Record(fields) => {
+        let fields: Vec<String> = fields
+          .iter()
+          .map(|(name, kind)| format!("{}: {}", name, kind))
+          .collect();
+        write!(f, "RECORD {} END", fields.join("; "))
+      }
+      Str => write!(f, "STRING"),
+    }
+  }
+}
+
 impl fmt::Display for Symbol {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     write!(
@@ -100,31 +322,56 @@ impl fmt::Display for Symbol {
       match self {
         BuiltInSymbol(symbol) => symbol.to_string(),
         VarSymbol(key, symbol) => format!("{}: {}", key, symbol),
+        ConstSymbol(key, symbol) => format!("{} (constant): {}", key, symbol),
+        TypeSymbol(key, symbol) => format!("{} = {}", key, symbol),
         ProcedureSymbol(procedure_name, params) => {
-          let mut output: String = String::new();
-          for param in params {
-            let (name, kind) = param;
-            output += &format!("{}: {}", name, kind);
-          }
+          let output = params
+            .iter()
+            .map(|(name, kind)| format!("{}: {}", name, kind))
+            .collect::<Vec<_>>()
+            .join(", ");
           format!("{} {{ {} }}", procedure_name, output)
         }
+        FunctionSymbol(function_name, params, result) => {
+          let output = params
+            .iter()
+            .map(|(name, kind)| format!("{}: {}", name, kind))
+            .collect::<Vec<_>>()
+            .join(", ");
+          format!("{} {{ {} }}: {}", function_name, output, result)
+        }
+        BuiltInFunctionSymbol(function_name) => function_name.to_string(),
+        BuiltInProcedureSymbol(procedure_name) => procedure_name.to_string(),
       }
     )
   }
 }
 
-impl fmt::Display for SymbolTable {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    println!("Symbol Table Info:");
-    println!("Scope: {}, Level: {}", &self.scope_name, &self.scope_level);
-
-    for (key, val) in &self.symbols {
-      writeln!(f, "{{ {} => {} }}", key, val).unwrap();
+impl SymbolTable {
+  /// Writes the scope and, indented under it, the scopes nested in it.
+  fn write_tree(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+    let margin = "  ".repeat(indent);
+    writeln!(
+      f,
+      "{}Scope: {}, Level: {}",
+      margin, &self.scope_name, &self.scope_level
+    )?;
+    for val in self.symbols() {
+      writeln!(f, "{}{{ {} => {} }}", margin, val.name(), val)?;
+    }
+    for scope in &self.nested_scopes {
+      scope.write_tree(f, indent + 1)?;
     }
     Ok(())
   }
 }
 
+impl fmt::Display for SymbolTable {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    self.write_tree(f, 0)
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -150,9 +397,9 @@ mod tests {
 
   #[test]
   fn create_integer_variable() {
-    let a = VarSymbol("a".into(), BuiltIn::new(Token::Integer));
+    let a = VarSymbol("a".into(), Simple(BuiltIn::new(Token::Integer)));
 
-    if let VarSymbol(key, builtin) = a.clone() {
+    if let VarSymbol(key, Simple(builtin)) = a.clone() {
       assert_eq!("a", key);
       assert_eq!(Token::Integer, builtin.0);
       assert_eq!("a: INTEGER", a.to_string())
@@ -161,9 +408,9 @@ mod tests {
 
   #[test]
   fn create_real_variable() {
-    let a = VarSymbol("a".into(), BuiltIn::new(Token::Real));
+    let a = VarSymbol("a".into(), Simple(BuiltIn::new(Token::Real)));
 
-    if let VarSymbol(key, builtin) = a.clone() {
+    if let VarSymbol(key, Simple(builtin)) = a.clone() {
       assert_eq!("a", key);
       assert_eq!(Token::Real, builtin.0);
       assert_eq!("a: REAL", a.to_string())
@@ -172,10 +419,10 @@ mod tests {
 
   #[test]
   fn define_and_lookup_integer() {
-    let mut symbol_table = SymbolTable::new("Global".into(), 1);
+    let mut symbol_table = SymbolTable::new("Global", 1);
 
     if let BuiltInSymbol(builtin) = symbol_table.lookup("INTEGER").unwrap() {
-      let int_variable = VarSymbol("a".into(), builtin);
+      let int_variable = VarSymbol("a".into(), Simple(builtin));
       symbol_table.insert(int_variable);
       let symbol_lookup = symbol_table.lookup("a").unwrap();
       assert_eq!("a: INTEGER", symbol_lookup.to_string());
@@ -184,21 +431,110 @@ mod tests {
 
   #[test]
   fn define_and_lookup_real() {
-    let mut symbol_table = SymbolTable::new("Global".into(), 1);
+    let mut symbol_table = SymbolTable::new("Global", 1);
 
     if let BuiltInSymbol(builtin) = symbol_table.lookup("REAL").unwrap() {
-      let real_variable = VarSymbol("x".into(), builtin);
+      let real_variable = VarSymbol("x".into(), Simple(builtin));
       symbol_table.insert(real_variable);
       let symbol_lookup = symbol_table.lookup("x").unwrap();
       assert_eq!("x: REAL", symbol_lookup.to_string());
     }
   }
 
+  #[test]
+  fn display_array_types() {
+    let char_type = Simple(BuiltIn::new(Token::Char));
+    let index = Subrange(Box::new(char_type.clone()), 97, 122);
+    let letters = Array(
+      Box::new(index),
+      Box::new(Simple(BuiltIn::new(Token::Integer))),
+    );
+    assert_eq!("ARRAY['a'..'z'] OF INTEGER", letters.to_string());
+  }
+
+  #[test]
+  fn display_enumerated_types() {
+    let color = Enumeration(vec!["Red".into(), "Green".into(), "Blue".into()]);
+    assert_eq!("(Red, Green, Blue)", color.to_string());
+    assert_eq!(Some((0, 2)), color.bounds());
+    let warm = Subrange(Box::new(color), 0, 1);
+    assert_eq!("Red..Green", warm.to_string());
+  }
+
+  #[test]
+  fn lookup_in_enclosing_scope() {
+    let mut global_scope = SymbolTable::new("Global", 1);
+    global_scope.insert(VarSymbol("a".into(), Simple(BuiltIn::new(Token::Integer))));
+    let mut procedure_scope = SymbolTable::nested("P1", global_scope);
+    procedure_scope.insert(VarSymbol("k".into(), Simple(BuiltIn::new(Token::Real))));
+
+    assert_eq!(
+      "a: INTEGER",
+      procedure_scope.lookup("a").unwrap().to_string()
+    );
+    assert_eq!(None, procedure_scope.lookup_local("a"));
+    assert_eq!(
+      "k: REAL",
+      procedure_scope.lookup_local("k").unwrap().to_string()
+    );
+
+    let global_scope = procedure_scope.into_enclosing_scope().unwrap();
+    assert_eq!(None, global_scope.lookup("k"));
+  }
+
+  #[test]
+  fn keep_scopes_left_in_the_enclosing_scope() {
+    let mut global_scope = SymbolTable::new("Global", 1);
+    global_scope.insert(VarSymbol("b".into(), Simple(BuiltIn::new(Token::Real))));
+    global_scope.insert(VarSymbol("a".into(), Simple(BuiltIn::new(Token::Integer))));
+    let global_scope = SymbolTable::nested("P1", global_scope)
+      .into_enclosing_scope()
+      .unwrap();
+    let global_scope = SymbolTable::nested("P2", global_scope)
+      .into_enclosing_scope()
+      .unwrap();
+
+    let names: Vec<String> = global_scope.symbols().iter().map(|s| s.name()).collect();
+    assert_eq!(&names[names.len() - 2..], &["b", "a"]);
+    let nested: Vec<&str> = global_scope
+      .nested_scopes()
+      .iter()
+      .map(SymbolTable::scope_name)
+      .collect();
+    assert_eq!(nested, ["P1", "P2"]);
+    assert_eq!(global_scope.nested_scopes()[0].scope_level(), 2);
+  }
+
+  #[test]
+  fn lookup_ignores_case() {
+    let mut symbol_table = SymbolTable::new("Global", 1);
+    symbol_table.insert(VarSymbol(
+      "Total".into(),
+      Simple(BuiltIn::new(Token::Integer)),
+    ));
+
+    assert_eq!(
+      "Total: INTEGER",
+      symbol_table.lookup("TOTAL").unwrap().to_string()
+    );
+    assert_eq!(
+      "Total: INTEGER",
+      symbol_table.lookup_local("total").unwrap().to_string()
+    );
+    assert!(symbol_table.lookup("integer").is_some());
+  }
+
+  #[test]
+  fn lookup_builtin_function() {
+    let symbol_table = SymbolTable::new("Global", 1);
+    let symbol_lookup = symbol_table.lookup("SQRT");
+    assert_eq!(Some(BuiltInFunctionSymbol("SQRT".into())), symbol_lookup);
+  }
+
   #[test]
   fn lookup_unknown_variable() {
-    let symbol_table = SymbolTable::new("Global".into(), 1);
+    let symbol_table = SymbolTable::new("Global", 1);
     let symbol_lookup = symbol_table.lookup("x");
     assert_eq!(None, symbol_lookup);
   }
-
 }
@@ -0,0 +1,119 @@
+use std::fmt;
+use utils::number::Number;
+
+/// The built-in exception classes, each with the class it inherits
+/// from, following the hierarchy of Free Pascal's SysUtils unit.
+const CLASSES: [(&str, &str); 14] = [
+  ("Exception", ""),
+  ("EExternal", "Exception"),
+  ("EIntError", "EExternal"),
+  ("EDivByZero", "EIntError"),
+  ("ERangeError", "EIntError"),
+  ("EIntOverflow", "EIntError"),
+  ("EMathError", "EExternal"),
+  ("EInvalidOp", "EMathError"),
+  ("EZeroDivide", "EMathError"),
+  ("EOverflow", "EMathError"),
+  ("EAccessViolation", "EExternal"),
+  ("EStackOverflow", "EExternal"),
+  ("EInvalidPointer", "Exception"),
+  ("EInOutError", "Exception"),
+];
+
+/// Returns the usual spelling of a built-in exception class, if the
+/// name is one, ignoring case.
+pub fn class_name(name: &str) -> Option<&'static str> {
+  CLASSES
+    .iter()
+    .map(|&(class, _)| class)
+    .find(|class| class.eq_ignore_ascii_case(name))
+}
+
+/// An exception, raised by RAISE or by a runtime error.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exception {
+  pub class: String,
+  pub message: String,
+}
+
+impl Exception {
+  pub fn new(class: &str, message: &str) -> Self {
+    Exception {
+      class: class.to_string(),
+      message: message.to_string(),
+    }
+  }
+  /// Returns true if the exception is of the given class, or of a class
+  /// inheriting from it.
+  pub fn is_a(&self, class: &str) -> bool {
+    let mut current = self.class.as_str();
+    while !current.is_empty() {
+      if current.eq_ignore_ascii_case(class) {
+        return true;
+      }
+      current = CLASSES
+        .iter()
+        .find(|(name, _)| *name == current)
+        .map_or("", |&(_, parent)| parent);
+    }
+    false
+  }
+  /// Returns the exception as the value of a variable, a record whose
+  /// ClassName and Message fields can be read by a handler.
+  pub fn to_value(&self) -> Number {
+    Number::Record(vec![
+      ("ClassName".to_string(), Number::Str(self.class.clone())),
+      ("Message".to_string(), Number::Str(self.message.clone())),
+    ])
+  }
+  /// Returns the exception held by a variable.
+  pub fn from_value(value: &Number) -> Option<Exception> {
+    match value {
+      Number::Record(fields) => match fields.as_slice() {
+        [(_, Number::Str(class)), (_, Number::Str(message))] => {
+          Some(Exception::new(class, message))
+        }
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for Exception {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}: {}", self.class, self.message)
+  }
+}
+
+/// An error found while running a program, which raises an exception
+/// of the given class that a handler may catch.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeError {
+  pub class: &'static str,
+  pub message: String,
+}
+
+impl RuntimeError {
+  pub fn new(class: &'static str, message: String) -> Self {
+    RuntimeError { class, message }
+  }
+  /// Returns the exception the error raises.
+  pub fn exception(&self) -> Exception {
+    Exception::new(self.class, &self.message)
+  }
+}
+
+/// Errors in the program itself, such as an operand of the wrong type,
+/// raise a plain Exception.
+impl From<String> for RuntimeError {
+  fn from(message: String) -> Self {
+    RuntimeError::new("Exception", message)
+  }
+}
+
+impl fmt::Display for RuntimeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Runtime error: {}", self.message)
+  }
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+use utils::exception::RuntimeError;
+use utils::number::{Number, RuntimeResult};
+
+/// A dynamic variable, along with where and as what it was created.
+struct Block {
+  value: Option<Number>,
+  type_name: String,
+  line: usize,
+  column: usize,
+}
+
+/// A block that was still allocated when the program finished.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Leak {
+  pub address: usize,
+  pub type_name: String,
+  /// The location of the call to New that allocated the block
+  pub line: usize,
+  pub column: usize,
+}
+
+/// Every block a program allocated with New but never disposed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LeakReport {
+  pub leaks: Vec<Leak>,
+}
+
+impl LeakReport {
+  pub fn is_empty(&self) -> bool {
+    self.leaks.is_empty()
+  }
+}
+
+fn invalid_pointer(address: usize) -> RuntimeError {
+  RuntimeError::new("EInvalidPointer", format!("invalid pointer {}", address))
+}
+
+/// The dynamic variables created by New. Addresses are never reused,
+/// so a pointer to a disposed block can always be recognised.
+#[derive(Default)]
+pub struct Heap {
+  blocks: Vec<Block>,
+}
+
+impl Heap {
+  pub fn new() -> Self {
+    Heap { blocks: vec![] }
+  }
+  /// Allocates a new block holding the given value, returning its address.
+  /// The type name and source location are kept for the leak report.
+  pub fn allocate(&mut self, value: Number, type_name: &str, line: usize, column: usize) -> usize {
+    self.blocks.push(Block {
+      value: Some(value),
+      type_name: type_name.to_string(),
+      line,
+      column,
+    });
+    self.blocks.len() - 1
+  }
+  /// Returns the value held in the block at the given address.
+  pub fn get(&self, address: usize) -> RuntimeResult {
+    match self.blocks.get(address) {
+      Some(Block {
+        value: Some(value), ..
+      }) => Ok(value.clone()),
+      Some(_) => Err(RuntimeError::new(
+        "EInvalidPointer",
+        format!("block {} was used after being disposed", address),
+      )),
+      None => Err(invalid_pointer(address)),
+    }
+  }
+  /// Replaces the value held in the block at the given address.
+  pub fn store(&mut self, address: usize, value: Number) -> Result<(), RuntimeError> {
+    self.get(address)?;
+    self.blocks[address].value = Some(value);
+    Ok(())
+  }
+  /// Releases the block at the given address.
+  pub fn dispose(&mut self, address: usize) -> Result<(), RuntimeError> {
+    match self.blocks.get(address) {
+      Some(Block { value: Some(_), .. }) => {
+        self.blocks[address].value = None;
+        Ok(())
+      }
+      Some(_) => Err(RuntimeError::new(
+        "EInvalidPointer",
+        format!("block {} was disposed twice", address),
+      )),
+      None => Err(invalid_pointer(address)),
+    }
+  }
+  /// Lists the blocks that have not been disposed.
+  pub fn leaks(&self) -> LeakReport {
+    let leaks = self
+      .blocks
+      .iter()
+      .enumerate()
+      .filter(|(_, block)| block.value.is_some())
+      .map(|(address, block)| Leak {
+        address,
+        type_name: block.type_name.clone(),
+        line: block.line,
+        column: block.column,
+      })
+      .collect();
+    LeakReport { leaks }
+  }
+  /// Discards every block, ready for the next program run.
+  pub fn clear(&mut self) {
+    self.blocks.clear();
+  }
+}
+
+impl fmt::Display for Leak {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "block {} of type {} allocated by New at line {}, column {}",
+      self.address, self.type_name, self.line, self.column
+    )
+  }
+}
+
+impl fmt::Display for LeakReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.is_empty() {
+      return write!(f, "No memory leaks found.");
+    }
+    write!(f, "{} block(s) were never disposed:", self.leaks.len())?;
+    for leak in &self.leaks {
+      write!(f, "\n  {}", leak)?;
+    }
+    Ok(())
+  }
+}
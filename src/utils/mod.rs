@@ -1 +1,5 @@
+pub mod exception;
+pub mod heap;
+pub mod names;
 pub mod number;
+pub mod set;
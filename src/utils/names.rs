@@ -0,0 +1,56 @@
+use std::collections::hash_map::{HashMap, Values};
+use std::ops::Index;
+
+/// Returns the key an identifier is stored under. Pascal identifiers
+/// are not case sensitive, so every spelling of a name has one key.
+pub fn key(name: &str) -> String {
+  name.to_uppercase()
+}
+
+/// Returns true if two identifiers are spellings of the same name.
+pub fn same_name(left: &str, right: &str) -> bool {
+  key(left) == key(right)
+}
+
+/// A map from identifiers to values that ignores the case of the
+/// identifiers, while the values keep whatever spelling they hold.
+#[derive(Clone, Debug)]
+pub struct NameMap<V> {
+  entries: HashMap<String, V>,
+}
+
+impl<V> NameMap<V> {
+  pub fn new() -> Self {
+    NameMap {
+      entries: HashMap::new(),
+    }
+  }
+  pub fn get(&self, name: &str) -> Option<&V> {
+    self.entries.get(&key(name))
+  }
+  pub fn contains_key(&self, name: &str) -> bool {
+    self.entries.contains_key(&key(name))
+  }
+  pub fn insert(&mut self, name: String, value: V) -> Option<V> {
+    self.entries.insert(key(&name), value)
+  }
+  pub fn remove(&mut self, name: &str) -> Option<V> {
+    self.entries.remove(&key(name))
+  }
+  pub fn values(&self) -> Values<'_, String, V> {
+    self.entries.values()
+  }
+}
+
+impl<V> Default for NameMap<V> {
+  fn default() -> Self {
+    NameMap::new()
+  }
+}
+
+impl<V> Index<&str> for NameMap<V> {
+  type Output = V;
+  fn index(&self, name: &str) -> &V {
+    &self.entries[&key(name)]
+  }
+}
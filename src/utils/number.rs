@@ -1,6 +1,10 @@
 use regex::Regex;
+use std::cmp::Ordering;
 use std::ops;
 use std::str::FromStr;
+use std::sync::Arc;
+use utils::exception::RuntimeError;
+use utils::set::OrdinalSet;
 
 #[derive(Debug)]
 pub struct ParseNumberError {
@@ -14,15 +18,30 @@ enum NumberErrorKind {
   InvalidNumber,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Number {
   Nil,
   Int(i32),
   Real(f32),
+  Bool(bool),
+  Char(char),
+  Str(String),
+  // The ordinal value, followed by the names of every value in the type
+  Enum(i32, Arc<Vec<String>>),
+  // The lower bound of the index, followed by the elements
+  Array(Box<Number>, Vec<Number>),
+  Set(OrdinalSet),
+  // The address of a block on the heap, or None for NIL
+  Pointer(Option<usize>),
+  // The name and value of each field
+  Record(Vec<(String, Number)>),
 }
 
 pub type NumberResult = Result<Number, String>;
 
+/// The result of an operation that may fail with a runtime error.
+pub type RuntimeResult = Result<Number, RuntimeError>;
+
 use self::Number::*;
 
 fn convert(text: &str) -> Number {
@@ -56,6 +75,31 @@ impl ToString for Number {
       Number::Nil => "Nil".into(),
       Number::Int(value) => format!("Int({})", value),
       Number::Real(value) => format!("Real({})", value),
+      Number::Bool(value) => format!("Bool({})", value),
+      Number::Char(value) => format!("Char({})", value),
+      Number::Str(value) => format!("Str({})", value),
+      Number::Enum(ordinal, names) => format!("Enum({})", names[*ordinal as usize]),
+      Number::Array(low, elements) => {
+        let elements: Vec<String> = elements.iter().map(|element| element.to_string()).collect();
+        format!("Array({}: [{}])", low.to_string(), elements.join(", "))
+      }
+      Number::Set(set) => {
+        let ordinals: Vec<String> = set
+          .ordinals()
+          .iter()
+          .map(|ordinal| ordinal.to_string())
+          .collect();
+        format!("Set([{}])", ordinals.join(", "))
+      }
+      Number::Pointer(Some(address)) => format!("Pointer({})", address),
+      Number::Pointer(None) => "Pointer(NIL)".into(),
+      Number::Record(fields) => {
+        let fields: Vec<String> = fields
+          .iter()
+          .map(|(name, value)| format!("{}: {}", name, value.to_string()))
+          .collect();
+        format!("Record({})", fields.join(", "))
+      }
     }
   }
 }
@@ -68,6 +112,7 @@ impl ops::Neg for Number {
       Nil => Nil,
       Int(value) => Int(-value),
       Real(value) => Real(-value),
+      other => panic!("Invalid negation, {}", other.to_string()),
     }
   }
 }
@@ -80,13 +125,26 @@ impl ops::Add for Number {
       Int(left) => match rhs {
         Nil => self,
         Int(right) => Int(left + right),
-        Real(right) => panic!("Invalid addition, {} and {}", left, right),
+        right => panic!("Invalid addition, {} and {}", left, right.to_string()),
       },
       Real(left) => match rhs {
         Nil => self,
-        Int(right) => panic!("Invalid addition, {} and {}", left, right),
         Real(right) => Real(left + right),
+        right => panic!("Invalid addition, {} and {}", left, right.to_string()),
+      },
+      Set(left) => match rhs {
+        Set(right) => Set(left.union(&right)),
+        right => panic!(
+          "Invalid addition, {} and {}",
+          Set(left).to_string(),
+          right.to_string()
+        ),
       },
+      left => panic!(
+        "Invalid addition, {} and {}",
+        left.to_string(),
+        rhs.to_string()
+      ),
     }
   }
 }
@@ -99,13 +157,26 @@ impl ops::Mul for Number {
       Int(left) => match rhs {
         Nil => self,
         Int(right) => Int(left * right),
-        Real(right) => panic!("Invalid multiplication, {} and {}", left, right),
+        right => panic!("Invalid multiplication, {} and {}", left, right.to_string()),
       },
       Real(left) => match rhs {
         Nil => self,
-        Int(right) => panic!("Invalid multiplication, {} and {}", left, right),
         Real(right) => Real(left * right),
+        right => panic!("Invalid multiplication, {} and {}", left, right.to_string()),
+      },
+      Set(left) => match rhs {
+        Set(right) => Set(left.intersection(&right)),
+        right => panic!(
+          "Invalid multiplication, {} and {}",
+          Set(left).to_string(),
+          right.to_string()
+        ),
       },
+      left => panic!(
+        "Invalid multiplication, {} and {}",
+        left.to_string(),
+        rhs.to_string()
+      ),
     }
   }
 }
@@ -118,13 +189,26 @@ impl ops::Sub for Number {
       Int(left) => match rhs {
         Nil => self,
         Int(right) => Int(left - right),
-        Real(right) => panic!("Invalid subtraction, {} and {}", left, right),
+        right => panic!("Invalid subtraction, {} and {}", left, right.to_string()),
       },
       Real(left) => match rhs {
         Nil => self,
-        Int(right) => panic!("Invalid subtraction, {} and {}", left, right),
         Real(right) => Real(left - right),
+        right => panic!("Invalid subtraction, {} and {}", left, right.to_string()),
       },
+      Set(left) => match rhs {
+        Set(right) => Set(left.difference(&right)),
+        right => panic!(
+          "Invalid subtraction, {} and {}",
+          Set(left).to_string(),
+          right.to_string()
+        ),
+      },
+      left => panic!(
+        "Invalid subtraction, {} and {}",
+        left.to_string(),
+        rhs.to_string()
+      ),
     }
   }
 }
@@ -137,13 +221,18 @@ impl ops::Div for Number {
       Int(left) => match rhs {
         Nil => self,
         Int(right) => Int(left / right),
-        Real(right) => panic!("Invalid division, {} and {}", left, right),
+        right => panic!("Invalid division, {} and {}", left, right.to_string()),
       },
       Real(left) => match rhs {
         Nil => self,
-        Int(right) => panic!("Invalid division, {} and {}", left, right),
         Real(right) => Real(left / right),
+        right => panic!("Invalid division, {} and {}", left, right.to_string()),
       },
+      left => panic!(
+        "Invalid division, {} and {}",
+        left.to_string(),
+        rhs.to_string()
+      ),
     }
   }
 }
@@ -159,3 +248,279 @@ impl From<f32> for Number {
     Number::Real(num)
   }
 }
+
+impl From<bool> for Number {
+  fn from(value: bool) -> Self {
+    Number::Bool(value)
+  }
+}
+
+/// The standard functions required by ISO 7185. Integer arguments are
+/// promoted to real wherever the function is only defined for reals.
+impl Number {
+  pub fn as_real(&self) -> Result<f32, String> {
+    match self {
+      Int(value) => Ok(*value as f32),
+      Real(value) => Ok(*value),
+      other => Err(format!("Expected a number, found {}", other.to_string())),
+    }
+  }
+  fn checked_real(value: f32, function: &str) -> RuntimeResult {
+    if value.is_finite() {
+      Ok(Real(value))
+    } else {
+      Err(RuntimeError::new(
+        "EOverflow",
+        format!("floating point overflow in {}", function),
+      ))
+    }
+  }
+  fn checked_int(value: f32, function: &str) -> RuntimeResult {
+    // i32::MAX as f32 rounds up to 2^31, which doesn't fit, so the
+    // upper bound is exclusive
+    if (-2147483648.0..2147483648.0).contains(&value) {
+      Ok(Int(value as i32))
+    } else {
+      Err(RuntimeError::new(
+        "ERangeError",
+        format!("{}({}) is out of the INTEGER range", function, value),
+      ))
+    }
+  }
+  /// Applies an integer operation, failing on overflow rather than
+  /// wrapping or stopping the interpreter. Anything else is left to
+  /// the operator.
+  fn checked(
+    self,
+    rhs: Number,
+    operation: fn(i32, i32) -> Option<i32>,
+    operator: fn(Number, Number) -> Number,
+  ) -> RuntimeResult {
+    match (&self, &rhs) {
+      (Int(left), Int(right)) => operation(*left, *right).map(Int).ok_or_else(|| {
+        RuntimeError::new("EIntOverflow", "integer overflow".to_string())
+      }),
+      _ => Ok(operator(self, rhs)),
+    }
+  }
+  pub fn plus(self, rhs: Number) -> RuntimeResult {
+    self.checked(rhs, i32::checked_add, ops::Add::add)
+  }
+  pub fn minus(self, rhs: Number) -> RuntimeResult {
+    self.checked(rhs, i32::checked_sub, ops::Sub::sub)
+  }
+  pub fn times(self, rhs: Number) -> RuntimeResult {
+    self.checked(rhs, i32::checked_mul, ops::Mul::mul)
+  }
+  pub fn negate(self) -> RuntimeResult {
+    match self {
+      Int(value) => value.checked_neg().map(Int).ok_or_else(|| {
+        RuntimeError::new("EIntOverflow", "integer overflow".to_string())
+      }),
+      other => Ok(-other),
+    }
+  }
+  /// Divides by another number, failing on division by zero rather
+  /// than giving an infinite real or stopping the interpreter.
+  pub fn divide(self, rhs: Number) -> RuntimeResult {
+    match (&self, &rhs) {
+      (Int(_), Int(0)) => Err(RuntimeError::new(
+        "EDivByZero",
+        "division by zero".to_string(),
+      )),
+      (Int(left), Int(right)) => left.checked_div(*right).map(Int).ok_or_else(|| {
+        RuntimeError::new(
+          "EIntOverflow",
+          format!("integer overflow in {} div {}", left, right),
+        )
+      }),
+      (Real(_), Real(right)) if *right == 0.0 => Err(RuntimeError::new(
+        "EZeroDivide",
+        "floating point division by zero".to_string(),
+      )),
+      _ => Ok(self / rhs),
+    }
+  }
+  pub fn abs(self) -> RuntimeResult {
+    match self {
+      Int(value) => value.checked_abs().map(Int).ok_or_else(|| {
+        RuntimeError::new("EIntOverflow", format!("integer overflow in abs({})", value))
+      }),
+      Real(value) => Ok(Real(value.abs())),
+      other => Err(format!("Invalid argument to abs: {}", other.to_string()).into()),
+    }
+  }
+  pub fn sqr(self) -> RuntimeResult {
+    match self {
+      Int(value) => value.checked_mul(value).map(Int).ok_or_else(|| {
+        RuntimeError::new("EIntOverflow", format!("integer overflow in sqr({})", value))
+      }),
+      Real(value) => Number::checked_real(value * value, "sqr"),
+      other => Err(format!("Invalid argument to sqr: {}", other.to_string()).into()),
+    }
+  }
+  pub fn sqrt(self) -> RuntimeResult {
+    let value = self.as_real()?;
+    if value < 0.0 {
+      return Err(RuntimeError::new(
+        "EInvalidOp",
+        format!("sqrt of negative number {}", value),
+      ));
+    }
+    Ok(Real(value.sqrt()))
+  }
+  pub fn sin(self) -> RuntimeResult {
+    Ok(Real(self.as_real()?.sin()))
+  }
+  pub fn cos(self) -> RuntimeResult {
+    Ok(Real(self.as_real()?.cos()))
+  }
+  pub fn arctan(self) -> RuntimeResult {
+    Ok(Real(self.as_real()?.atan()))
+  }
+  pub fn exp(self) -> RuntimeResult {
+    Number::checked_real(self.as_real()?.exp(), "exp")
+  }
+  pub fn ln(self) -> RuntimeResult {
+    let value = self.as_real()?;
+    if value <= 0.0 {
+      return Err(RuntimeError::new(
+        "EInvalidOp",
+        format!("ln of non-positive number {}", value),
+      ));
+    }
+    Ok(Real(value.ln()))
+  }
+  pub fn trunc(self) -> RuntimeResult {
+    match self {
+      Int(value) => Ok(Int(value)),
+      other => Number::checked_int(other.as_real()?.trunc(), "trunc"),
+    }
+  }
+  pub fn round(self) -> RuntimeResult {
+    match self {
+      Int(value) => Ok(Int(value)),
+      // f32::round rounds halfway cases away from zero, as Pascal requires.
+      other => Number::checked_int(other.as_real()?.round(), "round"),
+    }
+  }
+  pub fn odd(self) -> RuntimeResult {
+    match self {
+      Int(value) => Ok(Bool(value % 2 != 0)),
+      other => Err(format!("Invalid argument to odd: {}", other.to_string()).into()),
+    }
+  }
+}
+
+/// Ordinal support shared by INTEGER, CHAR and BOOLEAN values.
+impl Number {
+  /// Returns the ordinal number of the value within its type.
+  pub fn ordinal(&self) -> Result<i32, String> {
+    match self {
+      Int(value) => Ok(*value),
+      Char(value) => Ok(*value as i32),
+      Bool(value) => Ok(*value as i32),
+      Enum(value, _) => Ok(*value),
+      other => Err(format!(
+        "Expected an ordinal value, found {}",
+        other.to_string()
+      )),
+    }
+  }
+  /// Returns the value of the same type as `self` with the given ordinal
+  /// number, or a range error if the type has no such value.
+  pub fn with_ordinal(&self, ordinal: i64) -> RuntimeResult {
+    let out_of_range = || {
+      RuntimeError::new(
+        "ERangeError",
+        format!("range check error, ordinal {}", ordinal),
+      )
+    };
+    match self {
+      Int(_) if (i32::MIN as i64..=i32::MAX as i64).contains(&ordinal) => {
+        Ok(Int(ordinal as i32))
+      }
+      Char(_) => Number::chr(ordinal).map_err(|_| out_of_range()),
+      Bool(_) if ordinal == 0 || ordinal == 1 => Ok(Bool(ordinal == 1)),
+      Enum(_, names) if ordinal >= 0 && ordinal < names.len() as i64 => {
+        Ok(Enum(ordinal as i32, names.clone()))
+      }
+      Int(_) | Bool(_) | Enum(_, _) => Err(out_of_range()),
+      other => Err(
+        format!(
+          "Expected an ordinal value, found {}",
+          other.to_string()
+        )
+        .into(),
+      ),
+    }
+  }
+  /// Returns the lowest and highest values of the value's type.
+  pub fn bounds(&self) -> Result<(Number, Number), String> {
+    match self {
+      Int(_) => Ok((Int(i32::MIN), Int(i32::MAX))),
+      Char(_) => Ok((Char(0 as char), Char(255 as char))),
+      Bool(_) => Ok((Bool(false), Bool(true))),
+      other => Err(format!(
+        "Expected an ordinal value, found {}",
+        other.to_string()
+      )),
+    }
+  }
+  pub fn chr(ordinal: i64) -> RuntimeResult {
+    if (0..=255).contains(&ordinal) {
+      Ok(Char(ordinal as u8 as char))
+    } else {
+      Err(RuntimeError::new(
+        "ERangeError",
+        format!("chr({}) is out of the CHAR range", ordinal),
+      ))
+    }
+  }
+  pub fn ord(self) -> RuntimeResult {
+    Ok(Int(self.ordinal()?))
+  }
+  pub fn succ(self) -> RuntimeResult {
+    self.with_ordinal(self.ordinal()? as i64 + 1)
+  }
+  pub fn pred(self) -> RuntimeResult {
+    self.with_ordinal(self.ordinal()? as i64 - 1)
+  }
+}
+
+impl Number {
+  /// Compares two values of compatible types, promoting
+  /// integers when they are compared with reals.
+  pub fn compare(&self, other: &Number) -> Result<Ordering, String> {
+    let ordering = match (self, other) {
+      (Real(_), Int(_)) | (Int(_), Real(_)) | (Real(_), Real(_)) => {
+        self.as_real()?.partial_cmp(&other.as_real()?)
+      }
+      (Str(left), Str(right)) => Some(left.cmp(right)),
+      (Pointer(left), Pointer(right)) => Some(left.cmp(right)),
+      (Int(_), Int(_)) | (Char(_), Char(_)) | (Bool(_), Bool(_)) | (Enum(_, _), Enum(_, _)) => {
+        Some(self.ordinal()?.cmp(&other.ordinal()?))
+      }
+      _ => None,
+    };
+    ordering.ok_or_else(|| {
+      format!(
+        "Cannot compare {} and {}",
+        self.to_string(),
+        other.to_string()
+      )
+    })
+  }
+  /// Returns the text written by Write and WriteLn.
+  pub fn to_text(&self) -> Result<String, String> {
+    match self {
+      Int(value) => Ok(value.to_string()),
+      Real(value) => Ok(value.to_string()),
+      Bool(value) => Ok(value.to_string().to_uppercase()),
+      Char(value) => Ok(value.to_string()),
+      Str(value) => Ok(value.clone()),
+      Enum(ordinal, names) => Ok(names[*ordinal as usize].clone()),
+      other => Err(format!("Cannot write {}", other.to_string())),
+    }
+  }
+}
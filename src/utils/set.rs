@@ -0,0 +1,120 @@
+use std::collections::BTreeSet;
+
+/// The number of ordinal values a bitset can hold, enough for any
+/// set of CHAR or of a typical enumeration or subrange.
+const BITSET_SIZE: i32 = 256;
+
+/// The most ordinals a range in a set constructor may span, as those
+/// outside the bitset are stored one by one.
+pub const MAX_RANGE_SIZE: i64 = 65536;
+
+/// A set of ordinal values. Sets whose members all fall in 0..255 are
+/// stored as a bitset, anything else as an ordered set of ordinals.
+/// The small representation is always used when possible, so equal
+/// sets are always stored the same way.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OrdinalSet {
+  Small([u64; 4]),
+  Large(BTreeSet<i32>),
+}
+
+use self::OrdinalSet::*;
+
+impl OrdinalSet {
+  pub fn new() -> Self {
+    Small([0; 4])
+  }
+  /// Returns the ordinals in the set, in ascending order.
+  pub fn ordinals(&self) -> Vec<i32> {
+    match self {
+      Small(bits) => (0..BITSET_SIZE)
+        .filter(|&ordinal| bits[ordinal as usize / 64] & (1 << (ordinal % 64)) != 0)
+        .collect(),
+      Large(ordinals) => ordinals.iter().cloned().collect(),
+    }
+  }
+  pub fn contains(&self, ordinal: i32) -> bool {
+    match self {
+      Small(bits) => {
+        (0..BITSET_SIZE).contains(&ordinal)
+          && bits[ordinal as usize / 64] & (1 << (ordinal % 64)) != 0
+      }
+      Large(ordinals) => ordinals.contains(&ordinal),
+    }
+  }
+  pub fn insert(&mut self, ordinal: i32) {
+    self.insert_range(ordinal, ordinal);
+  }
+  /// Adds every ordinal from `low` to `high` inclusive.
+  pub fn insert_range(&mut self, low: i32, high: i32) {
+    if low > high {
+      return;
+    }
+    if let Small(ref mut bits) = self {
+      if low >= 0 && high < BITSET_SIZE {
+        for ordinal in low..=high {
+          bits[ordinal as usize / 64] |= 1 << (ordinal % 64);
+        }
+        return;
+      }
+    }
+    if let Small(_) = self {
+      *self = Large(self.ordinals().into_iter().collect());
+    }
+    if let Large(ref mut ordinals) = self {
+      ordinals.extend(low..=high);
+    }
+  }
+  pub fn union(&self, other: &OrdinalSet) -> OrdinalSet {
+    match (self, other) {
+      (Small(left), Small(right)) => Small(combine(left, right, |l, r| l | r)),
+      _ => OrdinalSet::from_ordinals(self.ordinals().into_iter().chain(other.ordinals())),
+    }
+  }
+  pub fn difference(&self, other: &OrdinalSet) -> OrdinalSet {
+    match (self, other) {
+      (Small(left), Small(right)) => Small(combine(left, right, |l, r| l & !r)),
+      _ => OrdinalSet::from_ordinals(
+        self
+          .ordinals()
+          .into_iter()
+          .filter(|&ordinal| !other.contains(ordinal)),
+      ),
+    }
+  }
+  pub fn intersection(&self, other: &OrdinalSet) -> OrdinalSet {
+    match (self, other) {
+      (Small(left), Small(right)) => Small(combine(left, right, |l, r| l & r)),
+      _ => OrdinalSet::from_ordinals(
+        self
+          .ordinals()
+          .into_iter()
+          .filter(|&ordinal| other.contains(ordinal)),
+      ),
+    }
+  }
+  pub fn is_subset(&self, other: &OrdinalSet) -> bool {
+    self.difference(other) == OrdinalSet::new()
+  }
+  fn from_ordinals<I: Iterator<Item = i32>>(ordinals: I) -> OrdinalSet {
+    let mut set = OrdinalSet::new();
+    for ordinal in ordinals {
+      set.insert(ordinal);
+    }
+    set
+  }
+}
+
+impl Default for OrdinalSet {
+  fn default() -> Self {
+    OrdinalSet::new()
+  }
+}
+
+fn combine(left: &[u64; 4], right: &[u64; 4], op: fn(u64, u64) -> u64) -> [u64; 4] {
+  let mut bits = [0; 4];
+  for (i, word) in bits.iter_mut().enumerate() {
+    *word = op(left[i], right[i]);
+  }
+  bits
+}
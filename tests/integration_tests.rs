@@ -2,7 +2,43 @@ extern crate basic_interpreter;
 
 #[cfg(test)]
 mod tests {
-  use basic_interpreter::{interpreter::*, utils::number::Number::Nil};
+  use basic_interpreter::formatter::{self, BeginPlacement, KeywordCase, Style};
+  use basic_interpreter::{interpreter::*, utils::heap::Leak, utils::number::Number::*};
+  use std::io::{self, Write};
+  use std::sync::{Arc, Mutex};
+
+  /// Collects the output of Write and WriteLn.
+  #[derive(Clone, Default)]
+  struct Output(Arc<Mutex<Vec<u8>>>);
+
+  impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.0.lock().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  impl Output {
+    fn text(&self) -> String {
+      String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+  }
+
+  /// Runs a program, returning its result and everything it wrote.
+  fn run(
+    text: &str,
+  ) -> (
+    Result<basic_interpreter::utils::number::Number, String>,
+    String,
+  ) {
+    let output = Output::default();
+    let mut interpreter = Interpreter::new(text);
+    interpreter.set_output(Box::new(output.clone()));
+    let result = interpreter.interpret();
+    (result, output.text())
+  }
 
   #[test]
   fn begin_then_end() {
@@ -121,8 +157,7 @@ mod tests {
   }
 
   #[test]
-  #[should_panic]
-  fn should_panic_identifiers_cannot_contain_underscores() {
+  fn identifiers_can_contain_underscores() {
     let mut interpreter = Interpreter::new(
       r#"
     PROGRAM NoUnderscore;
@@ -172,7 +207,7 @@ mod tests {
     let mut interpreter = Interpreter::new(
       r#"
     Program multiple;
-    Var a, b, c, result : INTEGER;
+    Var a, b : INTEGER; c, result : REAL;
     Begin
       Begin
         a := 10 * 4;
@@ -228,7 +263,8 @@ mod tests {
       r#"
     PROGRAM statement;
     VAR
-      a, b   : INTEGER;
+      a : INTEGER;
+      b : REAL;
     BEGIN
       a := 42;
       b := 100 / a + (10 + a) - -1;
@@ -334,4 +370,2570 @@ mod tests {
     assert_eq!(interpreter.interpret(), Ok(Nil));
   }
 
+  #[test]
+  fn evaluate_integer_math_builtins() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Math;
+    var a, b, c, d : integer;
+    begin
+      a := abs(-7);
+      b := Sqr(a - 2);
+      c := trunc(7.9);
+      d := round(-2.5)
+    end.
+    "#,
+    );
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(interpreter.lookup("a"), Some(Int(7)));
+    assert_eq!(interpreter.lookup("b"), Some(Int(25)));
+    assert_eq!(interpreter.lookup("c"), Some(Int(7)));
+    assert_eq!(interpreter.lookup("d"), Some(Int(-3)));
+  }
+
+  #[test]
+  fn trunc_and_round_check_the_integer_range() {
+    let (result, output) = run(
+      r#"
+    program Bounds;
+    begin
+      writeln(trunc(2147483520.0));
+      writeln(round(-2147483648.0))
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "2147483520\n-2147483648\n");
+
+    for call in &["trunc(2147483648.0)", "round(2147483648.0)", "trunc(-2147483904.0)"] {
+      let source = format!("program Bounds; begin writeln({}) end.", call);
+      let (result, output) = run(&source);
+      assert!(
+        result
+          .as_ref()
+          .unwrap_err()
+          .ends_with("is out of the INTEGER range"),
+        "{} gave {:?}",
+        call,
+        result
+      );
+      assert_eq!(output, "");
+    }
+  }
+
+  #[test]
+  fn evaluate_real_math_builtins() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Math;
+    var x, y, z : real;
+    begin
+      x := sqrt(16);
+      y := abs(-1.5) + sin(0.0) + ln(exp(0.0));
+      z := ARCTAN(0) + cos(0)
+    end.
+    "#,
+    );
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(interpreter.lookup("x"), Some(Real(4.0)));
+    assert_eq!(interpreter.lookup("y"), Some(Real(1.5)));
+    assert_eq!(interpreter.lookup("z"), Some(Real(1.0)));
+  }
+
+  #[test]
+  fn integers_are_promoted_in_real_arithmetic() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Mixed;
+    var i : integer; r, s, t : real;
+    begin
+      i := 3;
+      r := i * 0.5 + 1;
+      s := i / 2;
+      t := sqrt(16) + 1
+    end.
+    "#,
+    );
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(interpreter.lookup("r"), Some(Real(2.5)));
+    assert_eq!(interpreter.lookup("s"), Some(Real(1.5)));
+    assert_eq!(interpreter.lookup("t"), Some(Real(5.0)));
+  }
+
+  #[test]
+  fn evaluate_odd_builtin() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Parity;
+    var a, b : boolean;
+    begin
+      a := odd(3);
+      b := odd(10 div 5)
+    end.
+    "#,
+    );
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(interpreter.lookup("a"), Some(Bool(true)));
+    assert_eq!(interpreter.lookup("b"), Some(Bool(false)));
+  }
+
+  #[test]
+  fn math_domain_errors_are_runtime_errors() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Domain;
+    var x : real;
+    begin
+      x := sqrt(-1)
+    end.
+    "#,
+    );
+    assert!(interpreter.interpret().is_err());
+
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Domain;
+    var x : real;
+    begin
+      x := ln(0)
+    end.
+    "#,
+    );
+    assert!(interpreter.interpret().is_err());
+  }
+
+  #[test]
+  fn builtin_arguments_are_type_checked() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Types;
+    var a : boolean;
+    begin
+      a := odd(2.5)
+    end.
+    "#,
+    );
+    assert!(interpreter.interpret().is_err());
+
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Types;
+    var a : real;
+    begin
+      a := sqrt(odd(1))
+    end.
+    "#,
+    );
+    assert!(interpreter.interpret().is_err());
+
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Types;
+    var a : real;
+    begin
+      a := sqrt(1, 2)
+    end.
+    "#,
+    );
+    assert!(interpreter.interpret().is_err());
+  }
+
+  #[test]
+  fn evaluate_ord_chr_succ_pred() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Ordinals;
+    var a, b : integer;
+        c, d : char;
+        e : boolean;
+    begin
+      a := ord('A');
+      b := Pred(ord(true));
+      c := chr(a + 1);
+      d := succ(c);
+      e := pred(true)
+    end.
+    "#,
+    );
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(interpreter.lookup("a"), Some(Int(65)));
+    assert_eq!(interpreter.lookup("b"), Some(Int(0)));
+    assert_eq!(interpreter.lookup("c"), Some(Char('B')));
+    assert_eq!(interpreter.lookup("d"), Some(Char('C')));
+    assert_eq!(interpreter.lookup("e"), Some(Bool(false)));
+  }
+
+  #[test]
+  fn evaluate_inc_and_dec() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Counters;
+    var a, b : integer;
+        c : char;
+    begin
+      a := 1;
+      b := 10;
+      c := 'a';
+      Inc(a);
+      inc(a, 5);
+      DEC(b, a);
+      Inc(c, 2)
+    end.
+    "#,
+    );
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(interpreter.lookup("a"), Some(Int(7)));
+    assert_eq!(interpreter.lookup("b"), Some(Int(3)));
+    assert_eq!(interpreter.lookup("c"), Some(Char('c')));
+  }
+
+  #[test]
+  fn evaluate_low_and_high() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Bounds;
+    var a, b, c : integer;
+        d : char;
+        e : boolean;
+        letters : array ['a'..'z'] of integer;
+        numbers : array [-2..5] of real;
+    begin
+      a := high(integer);
+      b := low(numbers);
+      c := High(numbers);
+      d := high(letters);
+      e := low(boolean)
+    end.
+    "#,
+    );
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(interpreter.lookup("a"), Some(Int(2147483647)));
+    assert_eq!(interpreter.lookup("b"), Some(Int(-2)));
+    assert_eq!(interpreter.lookup("c"), Some(Int(5)));
+    assert_eq!(interpreter.lookup("d"), Some(Char('z')));
+    assert_eq!(interpreter.lookup("e"), Some(Bool(false)));
+  }
+
+  #[test]
+  fn evaluate_array_elements() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Arrays;
+    var counts : array ['a'..'c'] of integer;
+        grid : array [1..2] of array [1..2] of integer;
+        total : integer;
+    begin
+      counts['a'] := 1;
+      counts['b'] := 2;
+      inc(counts['b']);
+      grid[2][1] := counts['b'] * 10;
+      total := counts['a'] + grid[2][1]
+    end.
+    "#,
+    );
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(interpreter.lookup("total"), Some(Int(31)));
+  }
+
+  #[test]
+  fn ordinal_range_errors_are_runtime_errors() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Range;
+    var a : boolean;
+    begin
+      a := succ(true)
+    end.
+    "#,
+    );
+    assert!(interpreter.interpret().is_err());
+
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Range;
+    var c : char;
+    begin
+      c := chr(256)
+    end.
+    "#,
+    );
+    assert!(interpreter.interpret().is_err());
+
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Range;
+    var a : array [1..3] of integer;
+    begin
+      a[4] := 1
+    end.
+    "#,
+    );
+    assert!(interpreter.interpret().is_err());
+  }
+
+  #[test]
+  fn ordinal_arguments_are_type_checked() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Types;
+    var a : integer;
+    begin
+      a := ord(1.5)
+    end.
+    "#,
+    );
+    assert!(interpreter.interpret().is_err());
+
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Types;
+    var x : real;
+    begin
+      inc(x)
+    end.
+    "#,
+    );
+    assert!(interpreter.interpret().is_err());
+
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Types;
+    var a : integer;
+    begin
+      inc(a + 1)
+    end.
+    "#,
+    );
+    assert!(interpreter.interpret().is_err());
+  }
+
+  #[test]
+  fn assignments_are_type_checked() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Types;
+    var x : integer; r : real; a : array[1..3] of integer;
+    begin
+      x := 3;
+      r := x;
+      a[1] := x;
+      x := a[1] + 1
+    end.
+    "#,
+    );
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(interpreter.lookup("x"), Some(Int(4)));
+
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Types;
+    var x : integer;
+    begin
+      x := 'a'
+    end.
+    "#,
+    );
+    assert_eq!(
+      interpreter.interpret(),
+      Err("Cannot assign a value of type CHAR to a variable of type INTEGER.".into())
+    );
+
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Types;
+    var x : integer;
+    begin
+      x := 1.5
+    end.
+    "#,
+    );
+    assert_eq!(
+      interpreter.interpret(),
+      Err("Cannot assign a value of type REAL to a variable of type INTEGER.".into())
+    );
+  }
+
+  #[test]
+  fn oversized_arrays_are_rejected() {
+    let (result, _) = run("program Big; var a : array[1..2000000000] of integer; begin end.");
+    assert_eq!(
+      result,
+      Err("Array type ARRAY[1..2000000000] OF INTEGER has more than 1048576 elements.".into())
+    );
+    // Elements of nested arrays and records count towards the whole
+    let (result, _) = run(
+      r#"
+    program Big;
+    type Row = record cells : array[1..2000] of integer; total : integer end;
+    var grid : array[1..1000] of Row;
+    begin end.
+    "#,
+    );
+    assert!(result.unwrap_err().ends_with("has more than 1048576 elements."));
+
+    let (result, _) = run("program Big; var a : array[1..1048576] of char; begin a[5] := 'x' end.");
+    assert_eq!(result, Ok(Nil));
+  }
+
+  #[test]
+  fn compare_enumerated_values() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Colors;
+    type Color = (Red, Green, Blue);
+    var c : Color; before, same, after : boolean;
+    begin
+      c := Green;
+      before := Red < c;
+      same := c = Green;
+      after := c >= Blue
+    end.
+    "#,
+    );
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(interpreter.lookup("before"), Some(Bool(true)));
+    assert_eq!(interpreter.lookup("same"), Some(Bool(true)));
+    assert_eq!(interpreter.lookup("after"), Some(Bool(false)));
+  }
+
+  #[test]
+  fn enumerated_types_are_ordinal() {
+    let (result, output) = run(
+      r#"
+    program Colors;
+    type Color = (Red, Green, Blue);
+    var c : Color; count : array [Color] of integer; n : integer;
+    begin
+      n := 0;
+      for c := Red to Blue do
+      begin
+        n := n + 1;
+        count[c] := n * ord(c)
+      end;
+      for c := Blue downto Green do
+        writeln(c, ' ', count[c]);
+      c := succ(Red);
+      writeln(low(Color), '..', high(count), ' ', c)
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "Blue 6\nGreen 2\nRed..Blue Green\n");
+  }
+
+  #[test]
+  fn enumerated_values_have_range_errors() {
+    let (result, _) = run(
+      r#"
+    program Colors;
+    type Color = (Red, Green, Blue);
+    var c : Color;
+    begin
+      c := succ(Blue)
+    end.
+    "#,
+    );
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn enumerated_types_are_type_checked() {
+    let programs = [
+      // Enumerated values are constants
+      "program Colors; type Color = (Red, Green); begin Red := Green end.",
+      // Values of different enumerated types cannot be compared
+      "program Colors; type Color = (Red, Green); Fruit = (Apple, Pear);
+       var b : boolean; begin b := Red < Apple end.",
+      // Nor can they be used as integers
+      "program Colors; type Color = (Red, Green); var i : integer;
+       begin for i := Red to Green do end.",
+      // Each name can only be declared once in a scope
+      "program Colors; type Color = (Red, Green); Light = (Red, Amber); begin end.",
+    ];
+    for program in programs.iter() {
+      let (result, _) = run(program);
+      assert!(result.is_err(), "{}", program);
+    }
+    // Nor are type names variables
+    let (result, _) = run("program Types; type T = integer; begin T := 5 end.");
+    assert_eq!(result, Err("Cannot assign to type T.".into()));
+  }
+
+  #[test]
+  fn evaluate_case_statements() {
+    let (result, output) = run(
+      r#"
+    program Cases;
+    type Color = (Red, Green, Blue);
+    var i : integer; c : Color; ch : char;
+    begin
+      for i := 0 to 10 do
+        case i of
+          1, 3: write('a');
+          5..9: write('b');
+          -1, 10: write('c')
+        else
+          write('-');
+          write('.')
+        end;
+      writeln;
+      for c := Red to Blue do
+        case c of
+          Red: write('r');
+          Green..Blue: write('g');
+        end;
+      ch := 'x';
+      case ch of
+        'a'..'m': writeln(' first');
+        'n'..'z': writeln(' second')
+        otherwise writeln(' neither')
+      end
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "-.a-.a-.bbbbbc\nrgg second\n");
+  }
+
+  #[test]
+  fn unmatched_case_label_depends_on_dialect() {
+    let program = r#"
+    program Cases;
+    var i : integer;
+    begin
+      i := 4;
+      case i of
+        1: i := 10;
+        2, 3: i := 20
+      end
+    end.
+    "#;
+    let mut interpreter = Interpreter::new(program);
+    assert!(interpreter.interpret().is_err());
+
+    let mut interpreter = Interpreter::new(program);
+    interpreter.set_dialect(Dialect::Turbo);
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(interpreter.lookup("i"), Some(Int(4)));
+  }
+
+  #[test]
+  fn case_labels_are_checked() {
+    let programs = [
+      // Duplicate labels
+      "program Cases; var i : integer; begin case i of 1: ; 2, 1: end end.",
+      // Overlapping ranges
+      "program Cases; var i : integer; begin case i of 1..5: ; 5..9: end end.",
+      // Empty ranges
+      "program Cases; var i : integer; begin case i of 5..1: end end.",
+      // Labels that don't match the selector type
+      "program Cases; var i : integer; begin case i of 'a': end end.",
+      // Non-ordinal selectors
+      "program Cases; var x : real; begin case x of 1: end end.",
+    ];
+    for program in programs.iter() {
+      let (result, _) = run(program);
+      assert!(result.is_err(), "{}", program);
+    }
+  }
+
+  #[test]
+  fn evaluate_set_membership() {
+    let (result, output) = run(
+      r#"
+    program Sets;
+    var ch : char; letters, vowels : set of char;
+    begin
+      letters := ['a'..'z', 'A'..'Z'];
+      vowels := ['a', 'e', 'i', 'o', 'u'];
+      for ch := 'a' to 'h' do
+        if ch in vowels then
+          write('v')
+        else if ch in letters - vowels then
+          write('c');
+      if '1' in letters then writeln(' digit') else writeln(' no digits')
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "vcccvccc no digits\n");
+  }
+
+  #[test]
+  fn evaluate_set_algebra() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Sets;
+    type Color = (Red, Green, Blue);
+    var warm, cool, all : set of Color;
+        a, b, c, d, e, f, g : boolean;
+    begin
+      warm := [Red];
+      cool := [Green..Blue];
+      all := warm + cool;
+      a := all = [Red, Green, Blue];
+      b := warm * cool = [];
+      c := all - cool <> warm;
+      d := warm <= all;
+      e := cool >= all;
+      f := Blue in all * cool;
+      g := 300 in [-5, 300, 1000] * [1..400]
+    end.
+    "#,
+    );
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(interpreter.lookup("a"), Some(Bool(true)));
+    assert_eq!(interpreter.lookup("b"), Some(Bool(true)));
+    assert_eq!(interpreter.lookup("c"), Some(Bool(false)));
+    assert_eq!(interpreter.lookup("d"), Some(Bool(true)));
+    assert_eq!(interpreter.lookup("e"), Some(Bool(false)));
+    assert_eq!(interpreter.lookup("f"), Some(Bool(true)));
+    assert_eq!(interpreter.lookup("g"), Some(Bool(true)));
+  }
+
+  #[test]
+  fn large_set_ranges_are_built_at_once() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Sets;
+    var a, b : boolean;
+    begin
+      a := 20000 in [300..20300];
+      b := 20301 in [-65000..-1, 300..20300]
+    end.
+    "#,
+    );
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(interpreter.lookup("a"), Some(Bool(true)));
+    assert_eq!(interpreter.lookup("b"), Some(Bool(false)));
+
+    let (result, _) = run("program Sets; var a : boolean; begin a := 1 in [1..2000000000] end.");
+    assert_eq!(
+      result,
+      Err("Runtime error: set range 1..2000000000 is too large".into())
+    );
+  }
+
+  #[test]
+  fn sets_are_type_checked() {
+    let programs = [
+      // Sets of large types are not supported
+      "program Sets; var s : set of integer; begin end.",
+      "program Sets; var s : set of real; begin end.",
+      // Members must share a type
+      "program Sets; var b : boolean; begin b := 'a' in [1, 'b'] end.",
+      // The member tested must match the set
+      "program Sets; var b : boolean; begin b := 1 in ['a'..'z'] end.",
+      // Sets are not ordered
+      "program Sets; var b : boolean; begin b := [1] < [1, 2] end.",
+      // Conditions must be BOOLEAN
+      "program Sets; begin if 1 then end.",
+    ];
+    for program in programs.iter() {
+      let (result, _) = run(program);
+      assert!(result.is_err(), "{}", program);
+    }
+  }
+
+  #[test]
+  fn evaluate_linked_list() {
+    let (result, output) = run(
+      r#"
+    program List;
+    type
+      PNode = ^TNode;
+      TNode = record
+        value : integer;
+        next : PNode
+      end;
+    var head, node : PNode; i, total : integer;
+    begin
+      head := nil;
+      for i := 1 to 4 do
+      begin
+        new(node);
+        node^.value := i * i;
+        node^.next := head;
+        head := node
+      end;
+      total := 0;
+      node := head;
+      while node <> nil do
+      begin
+        write(node^.value, ' ');
+        total := total + node^.value;
+        head := node;
+        node := node^.next;
+        dispose(head)
+      end;
+      writeln(total)
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "16 9 4 1 30\n");
+  }
+
+  #[test]
+  fn evaluate_records_and_pointers_to_simple_types() {
+    let mut interpreter = Interpreter::new(
+      r#"
+    program Records;
+    type Point = record x, y : integer; end;
+    var p, q : Point; r : ^real; same : boolean;
+    begin
+      p.x := 3;
+      p.y := 4;
+      q := p;
+      q.y := 5;
+      new(r);
+      r^ := 4.5;
+      same := p.y = q.y
+    end.
+    "#,
+    );
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(interpreter.lookup("same"), Some(Bool(false)));
+    assert_eq!(
+      interpreter.lookup("q"),
+      Some(Record(vec![("x".into(), Int(3)), ("y".into(), Int(5))]))
+    );
+  }
+
+  #[test]
+  fn heap_errors_are_runtime_errors() {
+    let programs = [
+      // NIL dereference
+      "program Heap; var p : ^integer; begin p := nil; p^ := 1 end.",
+      // Use after dispose
+      "program Heap; var p : ^integer; begin new(p); dispose(p); p^ := 1 end.",
+      // Double dispose
+      "program Heap; var p, q : ^integer; begin new(p); q := p; dispose(p); dispose(q) end.",
+    ];
+    for program in programs.iter() {
+      let (result, _) = run(program);
+      assert!(
+        result.as_ref().unwrap_err().starts_with("Runtime error"),
+        "{}: {:?}",
+        program,
+        result
+      );
+    }
+  }
+
+  #[test]
+  fn pointers_are_type_checked() {
+    let programs = [
+      "program Heap; var i : integer; begin new(i) end.",
+      "program Heap; var p : ^integer; c : char; begin new(p); c := p^.x end.",
+      "program Heap; var p : ^integer; q : ^char; b : boolean; begin b := p = q end.",
+      "program Heap; var i : integer; begin while i do end.",
+      "program Heap; type R = record a : integer; a : char end; begin end.",
+    ];
+    for program in programs.iter() {
+      let (result, _) = run(program);
+      assert!(result.is_err(), "{}", program);
+    }
+  }
+
+  #[test]
+  fn report_heap_leaks() {
+    let program = r#"
+    program Leaks;
+    type PNode = ^TNode;
+         TNode = record next : PNode end;
+    var head : PNode; p : ^integer;
+    begin
+      new(head);
+      new(head^.next);
+      new(p);
+      dispose(head)
+    end.
+    "#;
+    let mut interpreter = Interpreter::new(program);
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(interpreter.leak_report(), None);
+
+    let mut interpreter = Interpreter::new(program);
+    interpreter.set_leak_check(true);
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    let report = interpreter.leak_report().unwrap();
+    assert_eq!(
+      report.leaks,
+      vec![
+        Leak {
+          address: 1,
+          type_name: "TNode".into(),
+          line: 8,
+          column: 7,
+        },
+        Leak {
+          address: 2,
+          type_name: "INTEGER".into(),
+          line: 9,
+          column: 7,
+        },
+      ]
+    );
+    assert_eq!(
+      report.to_string(),
+      "2 block(s) were never disposed:\n  \
+       block 1 of type TNode allocated by New at line 8, column 7\n  \
+       block 2 of type INTEGER allocated by New at line 9, column 7"
+    );
+  }
+
+  #[test]
+  fn forward_declarations_allow_mutual_recursion() {
+    let (result, output) = run(
+      r#"
+    program Parity;
+    var calls : integer;
+
+      procedure Odd(n : integer); forward;
+
+      procedure Even(n : integer);
+      begin
+        calls := calls + 1;
+        if n = 0 then writeln('even') else Odd(n - 1)
+      end;
+
+      procedure Odd(n : integer);
+      begin
+        calls := calls + 1;
+        if n = 0 then writeln('odd') else Even(n - 1)
+      end;
+
+    begin
+      calls := 0;
+      Even(7);
+      Even(4);
+      writeln(calls)
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "odd\neven\n13\n");
+  }
+
+  #[test]
+  fn procedure_calls_have_their_own_variables() {
+    let (result, output) = run(
+      r#"
+    program Locals;
+    var i : integer; x : real;
+
+      procedure Countdown(i : integer);
+      var j : integer;
+      begin
+        j := i * 10;
+        if i > 0 then Countdown(i - 1);
+        write(j, ' ')
+      end;
+
+      procedure Scale(factor : real);
+      begin
+        x := factor * 2.5
+      end;
+
+    begin
+      i := 42;
+      Countdown(3);
+      Scale(2);
+      writeln(i)
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "0 10 20 30 42\n");
+  }
+
+  #[test]
+  fn forward_declarations_are_checked() {
+    let programs = [
+      // No body
+      "program F; procedure A; forward; begin A end.",
+      // A different parameter list
+      "program F; procedure A(x : integer); forward;
+       procedure A(x : real); begin end; begin end.",
+      "program F; procedure A(x : integer); forward;
+       procedure A; begin end; begin end.",
+      // Two bodies
+      "program F; procedure A; forward; procedure A; begin end;
+       procedure A; begin end; begin end.",
+      // The body must be declared in the same block
+      "program F; procedure A; forward;
+       procedure B; procedure A; begin end; begin end; begin end.",
+    ];
+    for program in programs.iter() {
+      let (result, _) = run(program);
+      assert!(result.is_err(), "{}", program);
+    }
+  }
+
+  #[test]
+  fn procedure_arguments_are_type_checked() {
+    let programs = [
+      "program P; procedure A(x : integer); begin end; begin A end.",
+      "program P; procedure A(x : integer); begin end; begin A(1, 2) end.",
+      "program P; procedure A(x : integer); begin end; begin A(1.5) end.",
+      "program P; procedure A(c : char); begin end; begin A(true) end.",
+    ];
+    for program in programs.iter() {
+      let (result, _) = run(program);
+      assert!(result.is_err(), "{}", program);
+    }
+  }
+
+  #[test]
+  fn evaluate_recursive_functions() {
+    let (result, output) = run(
+      r#"
+    program Recursion;
+    var count : integer;
+
+      function Factorial(n : integer) : integer;
+      begin
+        if n <= 1 then Factorial := 1 else Factorial := n * Factorial(n - 1)
+      end;
+
+      function Fibonacci(n : integer) : integer;
+      begin
+        if n < 2 then
+          Fibonacci := n
+        else
+          Fibonacci := Fibonacci(n - 1) + Fibonacci(n - 2)
+      end;
+
+      function Next : integer;
+      begin
+        count := count + 1;
+        Next := count
+      end;
+
+      function Half(n : integer) : real;
+      begin
+        if odd(n) then Half := n / 2 else Half := n div 2
+      end;
+
+      function Depth(n : integer) : integer;
+      begin
+        if n = 0 then Depth := 0 else Depth := 1 + Depth(n - 1)
+      end;
+
+    begin
+      count := 0;
+      writeln(Factorial(10), ' ', Fibonacci(15));
+      writeln(Next + Next, ' ', Half(6) + 0.5);
+      writeln(Depth(DEPTH))
+    end.
+    "#
+      .replace("DEPTH", &(DEFAULT_MAX_CALL_DEPTH - 1).to_string())
+      .as_str(),
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(
+      output,
+      format!("3628800 610\n3 3.5\n{}\n", DEFAULT_MAX_CALL_DEPTH - 1)
+    );
+  }
+
+  #[test]
+  fn deep_recursion_is_a_stack_overflow() {
+    let program = r#"
+    program Main;
+      procedure Down(n : integer);
+      begin
+        Down(n + 1)
+      end;
+
+      procedure Ping(n : integer); forward;
+
+      procedure Pong(n : integer);
+      begin
+        Ping(n)
+      end;
+
+      procedure Ping(n : integer);
+      begin
+        Pong(n)
+      end;
+
+    begin
+      MAIN
+    end.
+    "#;
+    let mut interpreter = Interpreter::new(&program.replace("MAIN", "Down(1)"));
+    interpreter.set_max_call_depth(100);
+    assert_eq!(
+      interpreter.interpret(),
+      Err("Runtime error: Stack overflow calling Down, call chain: Main -> Down x 100".into())
+    );
+
+    let mut interpreter = Interpreter::new(&program.replace("MAIN", "Ping(1)"));
+    interpreter.set_max_call_depth(100);
+    assert_eq!(
+      interpreter.interpret(),
+      Err(
+        "Runtime error: Stack overflow calling Ping, call chain: \
+         Main -> Ping -> Pong -> Ping -> (93 more) -> Ping -> Pong -> Ping -> Pong"
+          .into()
+      )
+    );
+
+    // Without a call limit, the stack itself is checked
+    let (result, _) = run(&program.replace("MAIN", "Down(1)"));
+    assert!(result
+      .unwrap_err()
+      .starts_with("Runtime error: Stack overflow"));
+  }
+
+  #[test]
+  fn functions_are_checked() {
+    let programs = [
+      // Assigning a result outside the function
+      "program F; var x : integer; function A : integer; begin A := 1 end;
+       begin A := 2 end.",
+      // A function called as a procedure, and a procedure used as a value
+      "program F; function A : integer; begin A := 1 end; begin A end.",
+      "program F; var x : integer; procedure A; begin end; begin x := A end.",
+      // Missing and mismatched arguments
+      "program F; var x : integer; function A(n : integer) : integer; begin A := n end;
+       begin x := A end.",
+      "program F; var b : boolean; function A(n : integer) : integer; begin A := n end;
+       begin b := A(true) = 1 end.",
+      // A forward declaration with a different result type
+      "program F; function A : integer; forward; function A : real; begin A := 1 end;
+       begin end.",
+    ];
+    for program in programs.iter() {
+      let (result, _) = run(program);
+      assert!(result.is_err(), "{}", program);
+    }
+    let (result, _) =
+      run("program F; var x : integer; function A : integer; begin end; begin x := A end.");
+    assert_eq!(
+      result,
+      Err("Runtime error: function A returned without a result".into())
+    );
+  }
+
+  #[test]
+  fn nested_procedures_see_enclosing_variables() {
+    let (result, output) = run(
+      r#"
+    program Static;
+
+      procedure Outer(n : integer);
+      var depth : integer;
+
+        procedure Show;
+        begin
+          write(depth, ' ')
+        end;
+
+        procedure Inner(k : integer);
+        var depth : integer;
+        begin
+          depth := -1;
+          if k > 0 then Inner(k - 1) else Show
+        end;
+
+      begin
+        depth := n;
+        Inner(2);
+        if n > 0 then Outer(n - 1);
+        Show
+      end;
+
+    begin
+      Outer(2);
+      writeln
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "2 1 0 0 1 2 \n");
+  }
+
+  #[test]
+  fn nested_routines_are_scoped() {
+    let (result, output) = run(
+      r#"
+    program Scopes;
+    var total : integer;
+
+      procedure A;
+        procedure Helper;
+        begin
+          write('A ')
+        end;
+      begin
+        Helper
+      end;
+
+      procedure B;
+      type Colour = (Red, Green);
+      var c : Colour;
+        procedure Helper;
+        begin
+          c := Green;
+          write('B ')
+        end;
+      begin
+        Helper;
+        writeln(ord(c))
+      end;
+
+      function Sum(n : integer) : integer;
+      var i : integer;
+        procedure Add(k : integer);
+        begin
+          total := total + k;
+          Sum := total
+        end;
+      begin
+        for i := 1 to n do Add(i)
+      end;
+
+    begin
+      total := 0;
+      A;
+      B;
+      writeln(Sum(4))
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "A B 1\n10\n");
+  }
+
+  #[test]
+  fn evaluate_break_continue_and_exit() {
+    let (result, output) = run(
+      r#"
+    program Flow;
+    var i, j : integer;
+
+      function FirstSquareAbove(n : integer) : integer;
+      var i : integer;
+      begin
+        i := 0;
+        while true do
+        begin
+          i := i + 1;
+          if i * i > n then Exit(i * i)
+        end
+      end;
+
+      procedure Count(limit : integer);
+      var i : integer;
+      begin
+        i := 0;
+        while i < 10 do
+        begin
+          i := i + 1;
+          if i > limit then Exit;
+          write(i)
+        end
+      end;
+
+    begin
+      for i := 1 to 10 do
+      begin
+        if odd(i) then Continue;
+        if i > 6 then Break;
+        for j := 1 to 10 do
+          if j > 2 then break else write(j);
+        write(' ', i, ' ')
+      end;
+      writeln(i);
+      writeln(FirstSquareAbove(50));
+      Count(3);
+      writeln;
+      Exit;
+      writeln('not reached')
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "12 2 12 4 12 6 8\n64\n123\n");
+  }
+
+  #[test]
+  fn halt_gives_an_exit_code() {
+    let (result, output) = run(
+      r#"
+    program Stop;
+    var i : integer;
+
+      function Check(n : integer) : integer;
+      begin
+        if n > 2 then Halt(n + 1);
+        Check := n
+      end;
+
+    begin
+      for i := 1 to 10 do
+        writeln(Check(i) * 10)
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Int(4)));
+    assert_eq!(output, "10\n20\n");
+
+    let (result, _) = run("program Stop; begin Halt; Halt(1) end.");
+    assert_eq!(result, Ok(Int(0)));
+
+    // An exit status has room for codes from 0 to 255 only
+    let (result, _) = run("program Stop; begin Halt(255) end.");
+    assert_eq!(result, Ok(Int(255)));
+    for code in &["256", "-1"] {
+      let (result, output) = run(&format!(
+        "program Stop; begin Halt({}); writeln('not reached') end.",
+        code
+      ));
+      assert_eq!(
+        result,
+        Err(format!("Runtime error: Halt code {} is outside 0..255", code))
+      );
+      assert_eq!(output, "");
+    }
+  }
+
+  #[test]
+  fn early_exits_are_checked() {
+    let programs = [
+      "program F; begin Break end.",
+      "program F; var i : integer; procedure P; begin Continue end;
+       begin for i := 1 to 2 do P end.",
+      "program F; procedure P; begin Exit(1) end; begin end.",
+      "program F; function A : integer; begin Exit(true) end; begin end.",
+      "program F; begin Halt('x') end.",
+      "program F; var i : integer; begin while true do Break(i) end.",
+    ];
+    for program in programs.iter() {
+      let (result, _) = run(program);
+      assert!(result.is_err(), "{}", program);
+    }
+  }
+
+  #[test]
+  fn evaluate_goto_statements() {
+    let (result, output) = run(
+      r#"
+    program Jumps;
+    label 10, 20, 99;
+    var i, total : integer;
+
+      procedure Countdown(n : integer);
+      label 1;
+      begin
+        001: write(n);
+        n := n - 1;
+        if n > 0 then goto 1;
+        writeln
+      end;
+
+    begin
+      i := 0;
+      total := 0;
+      10: i := i + 1;
+      total := total + i;
+      if i < 5 then goto 10;
+      while true do
+      begin
+        begin
+          if total > 10 then goto 20
+        end;
+        total := total + 100
+      end;
+      20: writeln(total);
+      for i := 1 to 10 do
+        if i = 3 then goto 99 else write(i);
+      99: writeln;
+      Countdown(3)
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "15\n12\n321\n");
+  }
+
+  #[test]
+  fn goto_statements_are_checked() {
+    let programs = [
+      // Undeclared, unset and unused labels
+      "program G; begin goto 1 end.",
+      "program G; label 1; begin 1: end.",
+      "program G; label 1; begin goto 1 end.",
+      "program G; label 1, 1; begin 1: goto 1 end.",
+      "program G; label 1; begin 1: ; 1: goto 1 end.",
+      // Jumps into structured statements
+      "program G; label 1; var b : boolean; begin goto 1; if b then 1: end.",
+      "program G; label 1; var b : boolean; begin goto 1; while b do begin 1: end end.",
+      "program G; label 1; begin goto 1; begin 1: end end.",
+      // Jumps out of a procedure
+      "program G; label 1; procedure P; begin goto 1 end; begin 1: P end.",
+    ];
+    for program in programs.iter() {
+      let (result, _) = run(program);
+      assert!(result.is_err(), "{}", program);
+    }
+  }
+
+  #[test]
+  fn evaluate_exception_handlers() {
+    let (result, output) = run(
+      r#"
+    program Exceptions;
+    var a, i : integer;
+      s : array[1..3] of integer;
+
+      function Quotient(x, y : integer) : integer;
+      begin
+        try
+          Quotient := x div y
+        except
+          on E: EDivByZero do
+          begin
+            writeln(E.ClassName, ': ', E.Message);
+            Quotient := 0
+          end
+        end
+      end;
+
+    begin
+      writeln(Quotient(10, 2), ' ', Quotient(1, 0));
+      try
+        i := 4;
+        s[i] := 1
+      except
+        on EDivByZero do writeln('wrong handler');
+        on E: EIntError do writeln(E.ClassName)
+      end;
+      try
+        try
+          raise Exception.Create('boom')
+        finally
+          writeln('finally')
+        end
+      except
+        on E: Exception do writeln(E.Message)
+      end;
+      for i := 1 to 3 do
+        try
+          if i = 2 then break;
+          writeln('loop ', i)
+        finally
+          writeln('cleanup ', i)
+        end;
+      try
+        a := 0;
+        a := 1 div a
+      except
+        on ERangeError do writeln('wrong handler')
+      else
+        writeln('else')
+      end;
+      try
+        try
+          raise EMathError.Create('first')
+        except
+          writeln('re-raising');
+          raise
+        end
+      except
+        on E: EMathError do writeln(E.Message)
+      end
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(
+      output,
+      "EDivByZero: division by zero\n5 0\nERangeError\nfinally\nboom\nloop 1\ncleanup 1\n\
+       cleanup 2\nelse\nre-raising\nfirst\n"
+    );
+  }
+
+  #[test]
+  fn integer_overflow_raises_an_exception() {
+    let (result, output) = run(
+      r#"
+    program Overflow;
+    var i : integer;
+    begin
+      i := 2147483647;
+      try
+        i := i * 2
+      except
+        on E: EIntOverflow do writeln(E.ClassName, ': ', E.Message)
+      end;
+      try
+        i := -2147483647 - 1;
+        i := -i
+      except
+        on E: EIntError do writeln(E.ClassName)
+      end;
+      i := 2147483647;
+      i := i + 1
+    end.
+    "#,
+    );
+    assert_eq!(result, Err("Runtime error: integer overflow".to_string()));
+    assert_eq!(output, "EIntOverflow: integer overflow\nEIntOverflow\n");
+  }
+
+  #[test]
+  fn a_failed_operand_stops_the_expression() {
+    let (result, output) = run(
+      r#"
+    program Operands;
+    var x : integer;
+
+      function F : integer;
+      begin
+        try
+          raise Exception.Create('inner')
+        except
+          on E: Exception do writeln('F handled ', E.Message)
+        end;
+        F := 1
+      end;
+
+    begin
+      try
+        x := (1 div 0) + F;
+        writeln('not reached')
+      except
+        on E: EDivByZero do writeln('caught ', E.ClassName)
+      end;
+      writeln('done')
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "caught EDivByZero\ndone\n");
+  }
+
+  #[test]
+  fn unhandled_exceptions_are_runtime_errors() {
+    let (result, output) = run(
+      r#"
+    program Unhandled;
+    var a : integer;
+    begin
+      try
+        a := 0;
+        a := 1 div a
+      except
+        on ERangeError do writeln('wrong handler')
+      end
+    end.
+    "#,
+    );
+    assert_eq!(result, Err("Runtime error: division by zero".to_string()));
+    assert_eq!(output, "");
+
+    let (result, output) = run(
+      r#"
+    program Unhandled;
+    begin
+      try
+        raise EInvalidOp.Create('bad operation')
+      finally
+        writeln('finally')
+      end
+    end.
+    "#,
+    );
+    assert_eq!(
+      result,
+      Err("Runtime error: unhandled exception EInvalidOp: bad operation".to_string())
+    );
+    assert_eq!(output, "finally\n");
+
+    // Halt ends the program without running FINALLY blocks
+    let (result, output) = run("program H; begin try halt(3) finally writeln('finally') end end.");
+    assert_eq!(result, Ok(Int(3)));
+    assert_eq!(output, "");
+  }
+
+  #[test]
+  fn exception_handlers_are_checked() {
+    let programs = [
+      // Unknown exception classes
+      "program X; begin try except on EUnknown do end end.",
+      "program X; begin raise EUnknown.Create('x') end.",
+      // Only exceptions can be raised, with a string message
+      "program X; var i : integer; begin raise i end.",
+      "program X; begin raise Exception.Create(1) end.",
+      // RAISE on its own only re-raises the exception being handled
+      "program X; begin raise end.",
+      // The exception variable is local to its handler
+      "program X; begin try except on E: Exception do end; writeln(E.Message) end.",
+    ];
+    for program in programs.iter() {
+      let (result, _) = run(program);
+      assert!(result.is_err(), "{}", program);
+    }
+  }
+
+  #[test]
+  fn identifiers_are_case_insensitive() {
+    let (result, output) = run(
+      r#"
+    PROGRAM Cases;
+    TYPE
+      Colour = (Red, Green, Blue);
+      PNode = ^Node;
+      Node = RECORD Value : INTEGER; Next : pnode END;
+    VAR
+      Number, i : Integer;
+      c : colour;
+      p : ^NODE;
+      q : PNode;
+
+      FUNCTION Twice(X : integer) : integer; FORWARD;
+
+      function twice(x : INTEGER) : INTEGER;
+      begin
+        TWICE := x * 2
+      end;
+
+    begin
+      number := 1;
+      NUMBER := Number + 1;
+      c := green;
+      case C of RED : write('r'); GREEN : write('g') end;
+      new(P);
+      p^.value := 5;
+      P^.NEXT := nil;
+      q := p;
+      if q = P then write(Q^.Value);
+      for I := 1 to 2 do write(i);
+      writeln(' ', number, ' ', Twice(NUMBER), ' ', ord(high(Colour)));
+      dispose(q)
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "g512 2 4 2\n");
+
+    // The original spelling is kept for diagnostics
+    let (result, _) = run("program P; var Total : integer; begin Total := totl end.");
+    assert_eq!(result, Err("Undeclared variable totl found.".to_string()));
+    let (result, _) = run("program P; var Total, TOTAL : integer; begin end.");
+    assert_eq!(
+      result,
+      Err("Found duplicate variable declaration for 'TOTAL'!".to_string())
+    );
+  }
+
+  #[test]
+  fn evaluate_comments_and_literals() {
+    let (result, output) = run(
+      r#"
+    program Literals; // a line comment
+    var
+      i : integer; (* a (* nested *) comment *)
+      x : real;
+      { a { nested } brace comment }
+      max_value : integer;
+    begin
+      max_value := $7FFFFFFF;
+      i := $ff + %101 + &17;
+      x := 1.5E-3 * 2e3;
+      writeln(i, ' ', max_value, ' ', x)
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "275 2147483647 3\n");
+  }
+
+  #[test]
+  #[should_panic(expected = "Unterminated comment")]
+  fn should_panic_unterminated_comment() {
+    let mut interpreter = Interpreter::new("program P; begin { end.");
+    interpreter.interpret().unwrap();
+  }
+
+  #[test]
+  fn evaluate_unicode_text() {
+    let (result, output) = run(
+      r#"
+    program Unicode; { ¿qué tal? }
+    var c : char;
+    begin
+      c := 'é';
+      writeln('héllo → wörld ', c)
+    end.
+    "#,
+    );
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "héllo → wörld é\n");
+
+    let output = Output::default();
+    let mut interpreter = Interpreter::with_dialect(
+      "program Delphi; var größe : integer; begin größe := 2; writeln(GRÖSSE) end.",
+      Dialect::Delphi,
+    );
+    interpreter.set_output(Box::new(output.clone()));
+    assert_eq!(interpreter.interpret(), Ok(Nil));
+    assert_eq!(output.text(), "2\n");
+  }
+
+  #[test]
+  fn print_program_as_canonical_source() {
+    let interpreter = Interpreter::new(
+      r#"
+    program   Print; var x, y : integer;
+    procedure Show(n : integer); begin writeln(n) end;
+    begin x:=1; if x>0 then begin y := (x+1)*2 end else y:=-(x-1); Show(y); end.
+    "#,
+    );
+    assert_eq!(
+      interpreter.to_source(),
+      r#"PROGRAM Print;
+
+VAR
+  x, y: INTEGER;
+
+PROCEDURE Show(n: INTEGER);
+BEGIN
+  writeln(n)
+END;
+
+BEGIN
+  x := 1;
+  IF x > 0 THEN BEGIN
+    y := (x + 1) * 2
+  END ELSE
+    y := -(x - 1);
+  Show(y);
+END.
+"#
+    );
+  }
+
+  #[test]
+  fn print_only_the_parentheses_precedence_requires() {
+    let interpreter = Interpreter::new(
+      r#"
+    program Parens;
+    var a, b, c : integer; d : boolean; r : real;
+    begin
+      a := ((a + b)) + c;
+      a := a - (b - c);
+      a := a - (b + c) * c;
+      a := (a * b) div (c * 2);
+      a := -(-a) + (-b);
+      d := (a < b) = (b <= c);
+      d := a + 1 in [1..(b + c)];
+      r := 1.5e-7 / (2.0 * r);
+      r := 1e20 + 3.0
+    end.
+    "#,
+    );
+    let source = interpreter.to_source();
+    let statements: Vec<&str> = source
+      .lines()
+      .filter(|line| line.contains(":="))
+      .map(str::trim)
+      .collect();
+    assert_eq!(
+      statements,
+      vec![
+        "a := a + b + c;",
+        "a := a - (b - c);",
+        "a := a - (b + c) * c;",
+        "a := a * b DIV (c * 2);",
+        "a := --a + -b;",
+        "d := (a < b) = (b <= c);",
+        "d := a + 1 IN [1..b + c];",
+        "r := 1.5e-7 / (2.0 * r);",
+        "r := 1e20 + 3.0",
+      ]
+    );
+  }
+
+  #[test]
+  fn printed_source_parses_to_the_same_program() {
+    let text = r#"
+    program RoundTrip;
+    label 1;
+    type
+      Color = (Red, Green, Blue);
+      PCell = ^Cell;
+      Cell = record value : integer; next : PCell end;
+    var
+      i, total : integer;
+      c : Color;
+      list : PCell;
+      counts : array [Red..Blue] of integer;
+      letters : set of char;
+    function Square(n : integer) : integer; forward;
+    procedure Count(n : integer);
+      procedure Add(k : integer); begin total := total + k end;
+    begin
+      for i := n downto 1 do Add(Square(i))
+    end;
+    function Square(n : integer) : integer; begin Square := n * n end;
+    begin
+      total := 0; Count(3);
+      letters := ['a'..'c', 'x'];
+      if 'b' in letters then
+        if total > 100 then writeln('big')
+        else writeln('small: ', total)
+      else writeln('none');
+      for c := Red to Blue do counts[c] := ord(c) * 2;
+      new(list); list^.value := counts[Green]; list^.next := nil;
+      case total of 0: ; 1..13: writeln('low'); 14: begin writeln('it''s 14') end
+      otherwise writeln('high') end;
+      i := 0;
+      1: i := i + 1; if i < 3 then goto 1;
+      try
+        try total := total div (i - 3) finally writeln('finally') end
+      except
+        on E: EDivByZero do writeln(E.ClassName, ': ', E.Message);
+        on EIntError do ;
+      else raise
+      end;
+      while i > 0 do begin i := i - 1; end;
+      dispose(list);
+    end.
+    "#;
+    let source = Interpreter::new(text).to_source();
+    assert_eq!(Interpreter::new(&source).to_source(), source);
+
+    let (result, output) = run(text);
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(run(&source), (result, output));
+  }
+
+  const COMMENTED: &str = r#"{ Sums the numbers to three }
+program Sum; { header }
+var
+  { counters }
+  i, total : integer; { loop state }
+procedure Show(n : integer); // show a number
+begin
+  writeln(n) { print it }
+end;
+begin
+  total := 0;
+  { add them up }
+  for i := 1 to 3 do begin total := total + i; (* running *) end;
+  if total > 3 then Show(total) // big
+  else Show(0);
+  // done
+end. { the end }
+"#;
+
+  #[test]
+  fn format_keeps_comments_in_place() {
+    assert_eq!(
+      formatter::format(COMMENTED, &Style::default()),
+      r#"{ Sums the numbers to three }
+PROGRAM Sum; { header }
+
+VAR
+  { counters }
+  i, total: INTEGER; { loop state }
+
+PROCEDURE Show(n: INTEGER); // show a number
+BEGIN
+  writeln(n) { print it }
+END;
+
+BEGIN
+  total := 0;
+  { add them up }
+  FOR i := 1 TO 3 DO BEGIN
+    total := total + i; (* running *)
+  END;
+  IF total > 3 THEN
+    Show(total) // big
+  ELSE
+    Show(0);
+  // done
+END. { the end }
+"#
+    );
+  }
+
+  #[test]
+  fn format_in_a_configured_style() {
+    let style = Style {
+      keyword_case: KeywordCase::Lower,
+      indent_width: 4,
+      begin_placement: BeginPlacement::NextLine,
+      align_colons: true,
+    };
+    assert_eq!(
+      formatter::format(COMMENTED, &style),
+      r#"{ Sums the numbers to three }
+program Sum; { header }
+
+var
+    { counters }
+    i, total: integer; { loop state }
+
+procedure Show(n: integer); // show a number
+begin
+    writeln(n) { print it }
+end;
+
+begin
+    total := 0;
+    { add them up }
+    for i := 1 to 3 do
+    begin
+        total := total + i; (* running *)
+    end;
+    if total > 3 then
+        Show(total) // big
+    else
+        Show(0);
+    // done
+end. { the end }
+"#
+    );
+  }
+
+  #[test]
+  fn formatting_is_stable_and_keeps_the_program() {
+    let text = r#"
+    program Stable; (* a program *)
+    label 1;
+    type Color = (Red, Green); { colors }
+      Cell = record value : integer; end;
+    var c : Color; { the color }
+      n : integer;
+    function Twice(k : integer) : integer; forward; { later }
+    function Twice(k : integer) : integer;
+      var r : integer;
+    begin r := k * 2; Twice := r { result } end;
+    begin
+      n := 0;
+      1: n := n + 1; if n < 2 then goto 1; { loop back }
+      case n of
+        1: ; { nothing }
+        2: begin writeln('two') end // two
+        otherwise
+          { anything else }
+          writeln(n)
+        { last }
+      end;
+      try raise Exception.Create('no') except on E: Exception do writeln(E.Message) { caught } end;
+      if n > 0 then begin writeln(Twice(n)) end { positive } else writeln('none');
+      ;
+      { empty }
+    end.
+    "#;
+    let styles = vec![
+      Style::default(),
+      Style {
+        keyword_case: KeywordCase::Lower,
+        indent_width: 3,
+        begin_placement: BeginPlacement::NextLine,
+        align_colons: true,
+      },
+    ];
+    for style in styles {
+      let formatted = formatter::format(text, &style);
+      assert_eq!(formatter::format(&formatted, &style), formatted);
+      for comment in &["(* a program *)", "{ colors }", "{ later }", "{ result }"] {
+        assert!(formatted.contains(comment), "{} is missing", comment);
+      }
+      assert_eq!(
+        Interpreter::new(&formatted).to_source(),
+        Interpreter::new(text).to_source()
+      );
+      assert_eq!(run(&formatted), run(text));
+    }
+  }
+
+  #[test]
+  fn format_keeps_comments_within_expressions() {
+    let text = r#"program Inline;
+var x: integer;
+begin
+  x := 1 + { note } 2;
+  x := (x { first } + 2) * 3; { after }
+  writeln(x { argument }, { second } abs({ inner } x))
+end.
+"#;
+    let formatted = formatter::format(text, &Style::default());
+    assert_eq!(
+      formatted,
+      r#"PROGRAM Inline;
+
+VAR
+  x: INTEGER;
+
+BEGIN
+  x := 1 + { note } 2;
+  x := (x { first } + 2) * 3; { after }
+  writeln(x { argument }, { second } abs({ inner } x))
+END.
+"#
+    );
+    assert_eq!(formatter::format(&formatted, &Style::default()), formatted);
+  }
+
+  #[test]
+  fn format_keeps_the_source_spelling_and_grouping() {
+    let text = r#"program Spelling;
+type Point = record x, y: integer; z: real end;
+var a, b, c: integer; r: real;
+procedure Show(x, y: integer; z: real); begin writeln(x, y, z) end;
+begin { start }
+  a := $FF; b := %1010 + &17; c := 007;
+  r := -1.5e3; Show(a, b, r)
+end.
+"#;
+    assert_eq!(
+      formatter::format(text, &Style::default()),
+      r#"PROGRAM Spelling;
+
+TYPE
+  Point = RECORD
+    x, y: INTEGER;
+    z: REAL;
+  END;
+
+VAR
+  a, b, c: INTEGER;
+  r: REAL;
+
+PROCEDURE Show(x, y: INTEGER; z: REAL);
+BEGIN
+  writeln(x, y, z)
+END;
+
+BEGIN { start }
+  a := $FF;
+  b := %1010 + &17;
+  c := 007;
+  r := -1.5e3;
+  Show(a, b, r)
+END.
+"#
+    );
+    let (result, output) = run(text);
+    assert_eq!(result, Ok(Nil));
+    assert_eq!(output, "25525-1500\n");
+  }
+
+  #[test]
+  fn pasfmt_check_reports_unformatted_files() {
+    use std::process::Command;
+    let path = std::env::temp_dir().join(format!("pasfmt_check_{}.pas", std::process::id()));
+    std::fs::write(&path, COMMENTED).unwrap();
+    let pasfmt = |args: &[&str]| {
+      Command::new(env!("CARGO_BIN_EXE_pasfmt"))
+        .args(args)
+        .arg(&path)
+        .status()
+        .unwrap()
+        .code()
+    };
+
+    assert_eq!(pasfmt(&["--check"]), Some(1));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), COMMENTED);
+    assert_eq!(pasfmt(&["--keywords=lower"]), Some(0));
+    assert_eq!(pasfmt(&["--check", "--keywords=lower"]), Some(0));
+    assert_eq!(pasfmt(&["--check"]), Some(1));
+    assert_eq!(pasfmt(&["--indent=x"]), Some(2));
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn dump_ast_as_an_s_expression() {
+    let interpreter = Interpreter::new(
+      r#"
+    program Dump;
+    type Size = (Small, Big);
+    var d: integer; s: Size;
+    begin
+      d := 3;
+      case d of 1, 2..4: s := Small; else ; end;
+      if d in [5..9] then writeln('it''s "big"')
+    end.
+    "#,
+    );
+    assert_eq!(
+      interpreter.dump_ast(AstFormat::SExpr),
+      "(Program Dump (Block (\
+       (TypeDeclaration Size (EnumeratedType (Small Big))) \
+       (Declaration (Var d) (NamedType INTEGER)) \
+       (Declaration (Var s) (NamedType Size))) \
+       (Compound (\
+       (Assign (Var d) (IntegerNum 3)) \
+       (Case (Var d) ((CaseBranch ((CaseLabel 1 1) (CaseLabel 2 4)) \
+       (Assign (Var s) (Var Small)))) (Compound ((NoOp) (NoOp)))) \
+       (If (BinOp IN (Var d) (Set ((SetMember (IntegerNum 5) (IntegerNum 9))))) \
+       (ProcedureCall writeln ((String \"it's \\\"big\\\"\"))) nil)))))"
+    );
+  }
+
+  #[test]
+  fn dump_ast_as_json_with_spans() {
+    let source = "program P; begin x := -(y) end.";
+    let interpreter = Interpreter::new(source);
+    assert_eq!(
+      interpreter.dump_ast(AstFormat::Json),
+      r#"{"kind":"Program","span":{"start":0,"end":31},"identifier":"P","#.to_string()
+        + r#""block":{"kind":"Block","span":{"start":11,"end":30},"declarations":[],"#
+        + r#""compound_statement":{"kind":"Compound","span":{"start":11,"end":30},"#
+        + r#""children":[{"kind":"Assign","span":{"start":17,"end":26},"#
+        + r#""identifier":{"kind":"Var","span":{"start":17,"end":18},"identifier":"x"},"#
+        + r#""expr":{"kind":"UnaryOp","span":{"start":22,"end":26},"operator":"-","#
+        + r#""expr":{"kind":"Var","span":{"start":24,"end":25},"identifier":"y"}}}]}}}"#
+    );
+    assert_eq!(&source[11..30], "begin x := -(y) end");
+    assert_eq!(&source[22..26], "-(y)");
+  }
+
+  #[test]
+  fn dump_type_specifications_with_spans() {
+    let source = "program P; type R = record a, b: integer end; var s: array [1..3] of R; begin end.";
+    let json = Interpreter::new(source).dump_ast(AstFormat::Json);
+    assert!(json.contains(
+      &(r#""type_node":{"kind":"RecordType","span":{"start":20,"end":44},"fields":["#.to_string()
+        + r#"{"kind":"RecordField","span":{"start":27,"end":40},"name":"a","#
+        + r#""type_node":{"kind":"NamedType","span":{"start":33,"end":40},"name":"INTEGER"}}"#)
+    ));
+    assert!(json.contains(
+      &(r#""type_node":{"kind":"ArrayType","span":{"start":53,"end":70},"#.to_string()
+        + r#""index_type":{"kind":"SubrangeType","span":{"start":60,"end":64},"low":1,"high":3},"#
+        + r#""element_type":{"kind":"NamedType","span":{"start":69,"end":70},"name":"R"}}"#)
+    ));
+    assert_eq!(&source[20..44], "record a, b: integer end");
+    assert_eq!(&source[27..40], "a, b: integer");
+    assert_eq!(&source[53..70], "array [1..3] of R");
+    assert_eq!(&source[60..64], "1..3");
+  }
+
+  #[test]
+  fn dump_ast_from_the_command_line() {
+    use std::process::Command;
+    let path = std::env::temp_dir().join(format!("dump_ast_{}.pas", std::process::id()));
+    std::fs::write(&path, "program P; begin writeln(1 + 2) end.").unwrap();
+    let dump = |args: &[&str]| {
+      Command::new(env!("CARGO_BIN_EXE_basic-interpreter"))
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap()
+    };
+
+    let output = dump(&["--dump-ast=sexpr"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+      String::from_utf8(output.stdout).unwrap(),
+      "(Program P (Block () (Compound ((ProcedureCall writeln \
+       ((BinOp + (IntegerNum 1) (IntegerNum 2))))))))\n"
+    );
+    let output = dump(&["--dump-ast=json"]);
+    assert!(String::from_utf8(output.stdout)
+      .unwrap()
+      .starts_with(r#"{"kind":"Program","span":{"start":0,"end":36},"#));
+    assert_eq!(dump(&["--dump-ast=xml"]).status.code(), Some(2));
+
+    // The ast command prints the same trees
+    for format in &["sexpr", "json"] {
+      assert_eq!(
+        dump(&["ast", &format!("--format={}", format)]).stdout,
+        dump(&[&format!("--dump-ast={}", format)]).stdout
+      );
+    }
+    assert_eq!(dump(&["ast", "--format=xml"]).status.code(), Some(2));
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn graph_the_syntax_tree() {
+    let interpreter = Interpreter::new("program P; begin if x > 1 then y := -x end.");
+    assert_eq!(
+      interpreter.ast_graph(),
+      r#"digraph astgraph {
+  node [shape=box, style=rounded, fontsize=12, fontname="Courier", height=.1];
+  ranksep=.3;
+  edge [arrowsize=.5]
+
+  node0 [label="Program P"]
+  node1 [label="Block"]
+  node2 [label="Compound"]
+  node3 [label="If"]
+  node4 [label=">"]
+  node5 [label="x"]
+  node4 -> node5
+  node6 [label="1"]
+  node4 -> node6
+  node3 -> node4
+  node7 [label=":="]
+  node8 [label="y"]
+  node7 -> node8
+  node9 [label="unary -"]
+  node10 [label="x"]
+  node9 -> node10
+  node7 -> node9
+  node3 -> node7 [label="then"]
+  node2 -> node3
+  node1 -> node2
+  node0 -> node1
+}
+"#
+    );
+  }
+
+  #[test]
+  fn graph_the_nested_scopes() {
+    let interpreter = Interpreter::new(
+      r#"
+    program Scopes;
+    var total: integer;
+    procedure Add(n: integer);
+    var doubled: integer;
+    begin doubled := n * 2; total := total + doubled end;
+    function Half(n: real): real;
+    begin Half := n / 2 end;
+    begin Add(1) end.
+    "#,
+    );
+    let graph = interpreter.scope_graph().unwrap();
+    let lines: Vec<&str> = graph.lines().collect();
+    assert!(lines[4].starts_with(r#"  scope0 [label="{Built-in Scope (level 0)|INTEGER\l"#));
+    assert_eq!(
+      &lines[5..],
+      &[
+        r#"  scope1 [label="{Global Scope (level 1)|total: INTEGER\lAdd \{ n: INTEGER \}\lHalf \{ n: REAL \}: REAL\l}"]"#,
+        r#"  scope2 [label="{Add (level 2)|n: INTEGER\ldoubled: INTEGER\l}"]"#,
+        "  scope1 -> scope2",
+        r#"  scope3 [label="{Half (level 2)|n: REAL\l}"]"#,
+        "  scope1 -> scope3",
+        "  scope0 -> scope1",
+        "}",
+      ]
+    );
+    assert_eq!(
+      Interpreter::new("program P; begin x := 1 end.").scope_graph(),
+      Err("Undeclared variable x found.".to_string())
+    );
+  }
+
+  #[test]
+  fn graph_from_the_command_line() {
+    use std::process::Command;
+    let path = std::env::temp_dir().join(format!("graph_{}.pas", std::process::id()));
+    std::fs::write(&path, "program P; begin end.").unwrap();
+    let graph = |args: &[&str]| {
+      let output = Command::new(env!("CARGO_BIN_EXE_basic-interpreter"))
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap();
+      String::from_utf8(output.stdout).unwrap()
+    };
+    let ast = graph(&["--dot=ast"]);
+    assert!(ast.starts_with("digraph astgraph {\n"));
+    assert!(ast.ends_with("  node1 -> node2\n  node0 -> node1\n}\n"));
+    assert_eq!(graph(&["ast", "--format=dot"]), ast);
+    assert_eq!(graph(&["symbols", "--format=dot"]), graph(&["--dot=scopes"]));
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn check_returns_the_scope_tree() {
+    let interpreter = Interpreter::new(
+      r#"
+    program Scopes;
+    var total: integer;
+    procedure Add(n, m: integer);
+    var doubled: integer;
+    begin doubled := n * 2; total := total + doubled end;
+    begin Add(1, 2) end.
+    "#,
+    );
+    let builtin_scope = interpreter.check().unwrap();
+    assert_eq!(builtin_scope.scope_name(), "Built-in Scope");
+    let global_scope = &builtin_scope.nested_scopes()[0];
+    assert_eq!(
+      global_scope.to_string(),
+      "Scope: Global Scope, Level: 1\n\
+       { total => total: INTEGER }\n\
+       { Add => Add { n: INTEGER, m: INTEGER } }\n  \
+       Scope: Add, Level: 2\n  \
+       { n => n: INTEGER }\n  \
+       { m => m: INTEGER }\n  \
+       { doubled => doubled: INTEGER }\n"
+    );
+    assert_eq!(
+      Interpreter::new("program P; begin x := 1 end.")
+        .check()
+        .map(|scope| scope.to_string()),
+      Err("Undeclared variable x found.".to_string())
+    );
+  }
+
+  #[test]
+  fn dump_symbols_as_json() {
+    let interpreter = Interpreter::new(
+      "program P; var c: char; function F(x: real): boolean; begin F := x > 0 end; begin end.",
+    );
+    let json = interpreter.dump_symbols(AstFormat::Json).unwrap();
+    assert!(json.starts_with(
+      r#"{"kind":"Scope","name":"Built-in Scope","level":0,"symbols":[{"kind":"BuiltIn","name":"INTEGER"}"#
+    ));
+    assert!(json.ends_with(
+      r#""scopes":[{"kind":"Scope","name":"Global Scope","level":1,"symbols":[{"kind":"Var","name":"c","type":"CHAR"},{"kind":"Function","name":"F","params":[{"kind":"Param","name":"x","type":"REAL"}],"type":"BOOLEAN"}],"scopes":[{"kind":"Scope","name":"F","level":2,"symbols":[{"kind":"Var","name":"x","type":"REAL"}],"scopes":[]}]}]}"#
+    ));
+    assert!(interpreter
+      .dump_symbols(AstFormat::SExpr)
+      .unwrap()
+      .ends_with("(Scope F 2 ((Var x REAL)) ())))))"));
+  }
+
+  #[test]
+  fn symbols_from_the_command_line() {
+    use std::process::Command;
+    let path = std::env::temp_dir().join(format!("symbols_{}.pas", std::process::id()));
+    std::fs::write(&path, "program P; var x: integer; begin x := 1 end.").unwrap();
+    let run = |args: &[&str]| {
+      let output = Command::new(env!("CARGO_BIN_EXE_basic-interpreter"))
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap();
+      String::from_utf8(output.stdout).unwrap()
+    };
+    assert!(
+      run(&["--symbols"]).ends_with("  Scope: Global Scope, Level: 1\n  { x => x: INTEGER }\n")
+    );
+    assert!(run(&["--symbols=json"]).ends_with(
+      r#"[{"kind":"Var","name":"x","type":"INTEGER"}],"scopes":[]}]}
+"#
+    ));
+    assert_eq!(run(&["symbols"]), run(&["--symbols"]));
+    assert_eq!(run(&["symbols", "--format=json"]), run(&["--symbols=json"]));
+    // Checking the program prints nothing before it runs
+    assert_eq!(run(&["run"]), "");
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn exit_codes_from_the_command_line() {
+    use std::process::{Command, Stdio};
+    let dir = std::env::temp_dir();
+    let write = |name: &str, source: &str| {
+      let path = dir.join(format!("{}_{}.pas", name, std::process::id()));
+      std::fs::write(&path, source).unwrap();
+      path
+    };
+    let good = write("good", "program P; begin writeln(42) end.");
+    let syntax = write("syntax", "program P; begin x := end.");
+    let semantic = write("semantic", "program P; begin x := 1 end.");
+    let runtime = write("runtime", "program P; begin writeln(1 div 0) end.");
+    let halt = write("halt", "program P; begin Halt(7) end.");
+    let wide_halt = write("wide_halt", "program P; begin Halt(256) end.");
+    let run = |args: &[&str], paths: &[&std::path::PathBuf]| {
+      Command::new(env!("CARGO_BIN_EXE_basic-interpreter"))
+        .args(args)
+        .args(paths)
+        .output()
+        .unwrap()
+    };
+
+    let output = run(&[], &[&good]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "42\n");
+    assert_eq!(run(&["run"], &[&runtime]).status.code(), Some(1));
+    assert_eq!(run(&["run"], &[&syntax]).status.code(), Some(3));
+    assert_eq!(run(&["run"], &[&semantic]).status.code(), Some(4));
+    assert_eq!(run(&["run"], &[&halt]).status.code(), Some(7));
+    assert_eq!(run(&["run"], &[&wide_halt]).status.code(), Some(1));
+    assert_eq!(run(&["check"], &[&runtime]).status.code(), Some(0));
+    assert_eq!(run(&["tokens"], &[&semantic]).status.code(), Some(0));
+    assert_eq!(run(&["ast"], &[&syntax]).status.code(), Some(3));
+    assert_eq!(run(&["symbols"], &[&semantic]).status.code(), Some(4));
+    assert_eq!(
+      run(&["check", "--verbose"], &[&good]).status.code(),
+      Some(2)
+    );
+    assert_eq!(run(&["check"], &[]).status.code(), Some(2));
+    assert_eq!(
+      run(&["missing.pas"], &[]).status.code(),
+      Some(2),
+      "a file that can't be read"
+    );
+
+    // Every file is processed, and the first to fail gives the exit code
+    let output = run(&["check"], &[&good, &semantic, &syntax]);
+    assert_eq!(output.status.code(), Some(4));
+    let errors = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(errors.lines().count(), 2);
+    assert!(errors.starts_with(&format!(
+      "{}: Undeclared variable x found.\n",
+      semantic.display()
+    )));
+
+    let help = run(&["--help"], &[]);
+    assert_eq!(help.status.code(), Some(0));
+    assert!(String::from_utf8(help.stdout)
+      .unwrap()
+      .starts_with("Usage: basic-interpreter [<command>]"));
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_basic-interpreter"))
+      .args(["fmt", "--keywords=lower", "-"])
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()
+      .unwrap();
+    child
+      .stdin
+      .take()
+      .unwrap()
+      .write_all(b"PROGRAM P; BEGIN WRITELN(1) END.")
+      .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(
+      String::from_utf8(output.stdout).unwrap(),
+      "program P;\n\nbegin\n  WRITELN(1)\nend.\n"
+    );
+
+    for path in &[good, syntax, semantic, runtime, halt, wide_halt] {
+      std::fs::remove_file(path).unwrap();
+    }
+  }
+
+  #[test]
+  fn repl_keeps_declarations_and_values() {
+    let output = Output::default();
+    let mut repl = Repl::new();
+    repl.set_output(Box::new(output.clone()));
+    assert_eq!(repl.eval("var x, y: integer; r: real;"), Ok("".into()));
+    assert_eq!(
+      repl.eval("x := 6 * 7; y := x - 2"),
+      Ok("x = 42\ny = 40".into())
+    );
+    assert_eq!(repl.eval("x div 5"), Ok("8 : INTEGER".into()));
+    assert_eq!(repl.eval("r := x"), Ok("r = 42".into()));
+    assert_eq!(repl.eval("x > y"), Ok("TRUE : BOOLEAN".into()));
+    assert_eq!(
+      repl.eval("function Twice(n: integer): integer;\nbegin Twice := n * 2 end;"),
+      Ok("".into())
+    );
+    assert_eq!(repl.eval("Twice(x)"), Ok("84 : INTEGER".into()));
+    assert_eq!(repl.eval("writeln('x is ', x)"), Ok("".into()));
+    assert_eq!(output.text(), "x is 42\n");
+
+    assert_eq!(repl.eval("type Colour = (Red, Green);"), Ok("".into()));
+    assert_eq!(
+      repl.eval("var c: Colour; s: set of char; a: array[1..2] of integer;"),
+      Ok("".into())
+    );
+    assert_eq!(
+      repl.eval("c := succ(Red); s := ['b', 'a']; a[2] := 5"),
+      Ok("c = Green\ns = ['a', 'b']".into())
+    );
+    assert_eq!(
+      repl.eval(":vars"),
+      Ok(
+        "x: INTEGER = 42\n\
+         y: INTEGER = 40\n\
+         r: REAL = 42\n\
+         c: (Red, Green) = Green\n\
+         s: SET OF CHAR = ['a', 'b']\n\
+         a: ARRAY[1..2] OF INTEGER = (?, 5)"
+          .into()
+      )
+    );
+  }
+
+  #[test]
+  fn repl_errors_leave_the_session_usable() {
+    let mut repl = Repl::new();
+    repl.eval("var x: integer;").unwrap();
+    assert_eq!(
+      repl.eval("z := 1"),
+      Err("Undeclared variable z found.".into())
+    );
+    // A declaration that fails to check declares nothing
+    assert!(repl.eval("var y: integer; x: real;").is_err());
+    assert!(repl.eval("var y: char;").is_ok());
+    assert_eq!(
+      repl.eval("x := 3; x := x div 0"),
+      Err("Runtime error: division by zero".into())
+    );
+    assert_eq!(repl.eval("x"), Ok("3 : INTEGER".into()));
+    assert!(repl.eval("1 +").is_err());
+    assert_eq!(repl.eval("Halt(3)"), Ok("Halted with exit code 3".into()));
+    assert_eq!(
+      repl.eval(":nope"),
+      Err("Unknown command :nope, :help lists the commands.".into())
+    );
+  }
+
+  #[test]
+  fn repl_commands() {
+    let mut repl = Repl::new();
+    assert_eq!(
+      repl.eval(":vars"),
+      Ok("No variables have been declared.".into())
+    );
+    repl.eval("var x: integer; r: real;").unwrap();
+    assert_eq!(repl.eval(":type x + r"), Ok("REAL".into()));
+    assert_eq!(repl.eval(":type x * 2 = 0"), Ok("BOOLEAN".into()));
+    assert_eq!(
+      repl.eval(":type x + 'a'"),
+      Err("Invalid operand types INTEGER and CHAR for +".into())
+    );
+    assert_eq!(
+      repl.eval(":ast -x * (r + 1)"),
+      Ok("(BinOp * (UnaryOp - (Var x)) (BinOp + (Var r) (IntegerNum 1)))".into())
+    );
+    assert_eq!(
+      repl.eval(":history"),
+      Ok(
+        "   1  :vars\n   2  var x: integer; r: real;\n   3  :type x + r\n   \
+         4  :type x * 2 = 0\n   5  :type x + 'a'\n   6  :ast -x * (r + 1)\n   7  :history"
+          .into()
+      )
+    );
+    assert_eq!(repl.history().len(), 7);
+  }
+
+  #[test]
+  fn repl_waits_for_the_end_of_an_entry() {
+    assert!(is_complete("x := 1"));
+    assert!(is_complete("var x: integer;"));
+    assert!(!is_complete("begin\n  x := 1;"));
+    assert!(!is_complete("begin\n  case x of 1: begin end;"));
+    assert!(is_complete("begin\n  case x of 1: begin end end\nend"));
+    assert!(!is_complete("x := 1 { a comment"));
+    assert!(!is_complete("procedure P;"));
+    assert!(!is_complete("procedure P;\n  procedure Q; begin end;"));
+    assert!(is_complete(
+      "procedure P;\n  procedure Q; begin end;\nbegin Q end;"
+    ));
+    assert!(is_complete("procedure P; forward;"));
+    assert!(!is_complete("type R = record"));
+    // Text no later line can mend is complete, so that its error is shown
+    assert!(is_complete("x := 'abc"));
+  }
+
+  #[test]
+  fn repl_from_the_command_line() {
+    use std::process::{Command, Stdio};
+    let mut child = Command::new(env!("CARGO_BIN_EXE_basic-interpreter"))
+      .arg("repl")
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .spawn()
+      .unwrap();
+    child
+      .stdin
+      .take()
+      .unwrap()
+      .write_all(b"var i: integer;\nbegin\n  i := 2;\n  writeln(i * i)\nend\ni +\ni + 1\n:quit\ni\n")
+      .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+      String::from_utf8(output.stdout).unwrap(),
+      "pascal> pascal>    ...>    ...>    ...> 4\npascal> !!!Error!!!: Unexpected token found at end of file: +\n\
+       pascal> 3 : INTEGER\npascal> "
+    );
+    // A syntax error is not a panic
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+  }
+
+  #[test]
+  fn evaluate_an_expression_with_bindings() {
+    use std::collections::HashMap;
+    let evaluate = |text: &str, bindings: &[(&str, basic_interpreter::utils::number::Number)]| {
+      let bindings: HashMap<String, _> = bindings
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.clone()))
+        .collect();
+      evaluate_expression(text, &bindings).map(|(value, kind)| (value, kind.to_string()))
+    };
+
+    assert_eq!(evaluate("1 + 2 * 3", &[]), Ok((Int(7), "INTEGER".into())));
+    assert_eq!(
+      evaluate(
+        "Price * Quantity",
+        &[("price", Int(3)), ("quantity", Int(4))]
+      ),
+      Ok((Int(12), "INTEGER".into()))
+    );
+    // An integer is taken as a real where the type is REAL
+    assert_eq!(
+      evaluate("price * 1.5 - 1", &[("price", Int(3))]),
+      Ok((Real(3.5), "REAL".into()))
+    );
+    assert_eq!(evaluate("7 / 2", &[]), Ok((Real(3.5), "REAL".into())));
+    assert_eq!(evaluate("7 div 2", &[]), Ok((Int(3), "INTEGER".into())));
+    assert_eq!(
+      evaluate(
+        "total > limit",
+        &[("total", Real(12.5)), ("limit", Int(10))]
+      ),
+      Ok((Bool(true), "BOOLEAN".into()))
+    );
+    assert_eq!(
+      evaluate("succ(grade)", &[("grade", Char('a'))]),
+      Ok((Char('b'), "CHAR".into()))
+    );
+
+    assert_eq!(
+      evaluate("x + 1", &[]),
+      Err("Undeclared variable x found.".into())
+    );
+    assert_eq!(
+      evaluate("x + 'a'", &[("x", Int(1))]),
+      Err("Invalid operand types INTEGER and CHAR for +".into())
+    );
+    assert_eq!(
+      evaluate("x div 0", &[("x", Int(1))]),
+      Err("Runtime error: division by zero".into())
+    );
+    assert_eq!(
+      evaluate("x * x", &[("x", Int(i32::MAX))]),
+      Err("Runtime error: integer overflow".into())
+    );
+    // Syntax errors are returned, without the parser panicking
+    assert_eq!(evaluate("1 +", &[]), Err("Invalid variable: EOF".into()));
+    assert_eq!(evaluate("x := 1", &[("x", Int(1))]), Err("Unexpected token found at end of file: :=".into()));
+    assert_eq!(evaluate("1 ? 2", &[]),
+      Err("Unknown token found: ? at line 1, column 3".into())
+    );
+
+    for name in &["a b", "begin", "1x", "x{c}", ""] {
+      assert_eq!(
+        evaluate("1", &[(name, Int(1))]),
+        Err(format!("Cannot bind '{}', which is not an identifier.", name))
+      );
+    }
+    // Whichever order the names are held in, the same one is reported
+    for _ in 0..10 {
+      assert_eq!(
+        evaluate("x", &[("x", Int(1)), ("X", Int(2))]),
+        Err("Found duplicate declaration for 'x'!".into())
+      );
+    }
+    assert_eq!(
+      evaluate("p", &[("p", Pointer(None))]),
+      Err(
+        "Cannot bind p to Pointer(NIL), only integers, reals, booleans, characters and strings \
+         can be bound."
+          .into()
+      )
+    );
+  }
 }